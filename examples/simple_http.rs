@@ -1,19 +1,19 @@
+#![allow(clippy::wrong_self_convention)]
+
 extern crate env_logger;
 
 extern crate gerust;
 extern crate mime;
-extern crate futures;
 extern crate http;
 extern crate serde;
-extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
-use gerust::resource::{Resource, ProvidedPair, Handles};
-
-use futures::Stream;
-use futures::Future;
-use futures::Sink;
+use gerust::context::Context;
+use gerust::decode;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, AcceptedPair, Handles, Deferred};
+use gerust::response::Entity;
 
 #[derive(Debug, Default)]
 struct OrderResource {
@@ -27,59 +27,45 @@ struct Order {
 }
 
 impl Resource for OrderResource {
-    fn post_is_create(&self) -> bool { true }
+    fn post_is_create(&mut self, _context: &Context) -> bool { true }
 
-    fn create_path(&self) -> String {
+    fn create_path(&mut self, _context: &Context) -> String {
         format!("orders/{}", self.order.as_ref().unwrap().id )
     }
 
-    fn allowed_methods(&self) -> &'static [http::Method] {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
         use http::method::Method;
 
         &[Method::GET, Method::HEAD, Method::PUT, Method::POST]
     }
 
-    fn content_types_provided(&self) -> &'static [ProvidedPair<Self>] {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
         &[ProvidedPair(mime::TEXT_HTML, OrderResource::to_html)]
     }
 
-    fn content_types_accepted(&self) -> &'static [(mime::Mime, fn (&mut Self, request: &mut http::Request<gerust::Body>, response: &mut gerust::flow::DelayedResponse) -> ())] {
-        &[(mime::APPLICATION_JSON, OrderResource::from_json)]
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::APPLICATION_JSON, decode::json)]
     }
 }
 
 impl Handles for OrderResource {
     type Item = Order;
 
-    fn handle(&mut self, item: Self::Item, request: &mut http::Request<gerust::Body>, response: &mut gerust::flow::DelayedResponse) {
+    fn handle(&mut self, item: Self::Item) {
         self.order = Some(item);
 
         println!("received order: {:?}", self.order);
     }
 }
 
-trait JSON: Handles<Item=Order> {
-    fn from_json(&mut self, request: &mut http::Request<gerust::Body>, response: &mut gerust::flow::DelayedResponse) {
-        // remove wait() here, the interface should move towards futures
-        let item = request.body_mut().concat2()
-            .then(|body| {
-                serde_json::from_slice(&body.unwrap())
-            }).wait();
-
-        self.handle(item.unwrap(), request, response)
-    }
-}
-
-impl JSON for OrderResource {}
-
 impl OrderResource {
-    fn to_html(&mut self, resp: &mut gerust::flow::DelayedResponse) {
-        resp.response_body().start_send(Ok("Hello, World!".into()));
+    fn to_html(&mut self, _context: &Context, _resp: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "Hello, World!".into()
     }
 }
 
 fn main() {
     env_logger::init().unwrap();
     // TBD Dispatching over multiple resources
-    gerust::server::run_server::<OrderResource>(100)
+    gerust::server::run_server::<OrderResource>(100).unwrap()
 }
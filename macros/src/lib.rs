@@ -0,0 +1,332 @@
+//! The `#[resource]` attribute, re-exported by gerust behind its `macros`
+//! feature.
+//!
+//! The attribute goes on the `impl Resource` block and generates the
+//! callbacks listing methods, types and handlers from annotations. The
+//! lists are built once per resource type, in a `static` of the callback
+//! or, for generic resources, in a registry keyed by type:
+//!
+//! ```rust
+//! extern crate gerust;
+//!
+//! use gerust::context::Context;
+//! use gerust::flow::DelayedResponse;
+//! use gerust::resource;
+//! use gerust::resource::{Resource, Deferred};
+//! use gerust::response::Entity;
+//!
+//! struct Page<T> {
+//!     content: T,
+//! }
+//!
+//! #[resource(methods(GET, HEAD, PUT), languages("en"), charsets("utf-8"))]
+//! impl<T: ToString + Send + 'static> Resource for Page<T> {
+//!     fn resource_exists(&mut self, _: &Context) -> Deferred<Self, bool> {
+//!         true.into()
+//!     }
+//!
+//!     #[provides("text/html", "application/xhtml+xml")]
+//!     fn to_html(&mut self, _: &Context, _: &mut DelayedResponse) -> Deferred<Self, Entity> {
+//!         format!("<p>{}</p>", self.content.to_string()).into()
+//!     }
+//!
+//!     #[accepts("text/plain")]
+//!     fn from_text(&mut self, _: &mut Context, _: &mut DelayedResponse) -> Deferred<Self, ()> {
+//!         ().into()
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! The arguments of `#[resource]` generate `allowed_methods`,
+//! `languages_provided` and `charsets_provided`. Handlers defined
+//! elsewhere, like the ones in `gerust::encode`, are listed there too:
+//! `provides("application/json" = encode::json)`. Types are offered in the
+//! order they are declared, annotated methods first.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+extern crate mime;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as Tokens;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Attribute, Error, GenericArgument, Ident, ImplItem, ItemImpl, LitStr, Path, PathArguments};
+
+const METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH"];
+
+/// Generates the static callbacks of a `Resource` implementation.
+///
+/// See the crate documentation for the arguments and annotations.
+#[proc_macro_attribute]
+pub fn resource(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as Args);
+    let item = parse_macro_input!(item as ItemImpl);
+
+    expand(args, item).unwrap_or_else(Error::into_compile_error).into()
+}
+
+#[derive(Default)]
+struct Args {
+    methods: Option<Vec<Ident>>,
+    provides: Vec<Handler>,
+    accepts: Vec<Handler>,
+    languages: Option<Vec<LitStr>>,
+    charsets: Option<Vec<LitStr>>,
+}
+
+/// A media type and the function that handles it.
+struct Handler {
+    media_type: LitStr,
+    function: Tokens,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Args> {
+        let mut args = Args::default();
+
+        while !input.is_empty() {
+            let name: Ident = input.parse()?;
+            let content;
+            parenthesized!(content in input);
+
+            match &*name.to_string() {
+                "methods" => once(&mut args.methods, &name, list(&content, method)?)?,
+                "provides" => args.provides.extend(list(&content, handler)?),
+                "accepts" => args.accepts.extend(list(&content, handler)?),
+                "languages" => once(&mut args.languages, &name, list(&content, |input| input.parse())?)?,
+                "charsets" => once(&mut args.charsets, &name, list(&content, |input| input.parse())?)?,
+                _ => return Err(Error::new(name.span(), "expected `methods`, `provides`, `accepts`, `languages` or `charsets`")),
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn once<T>(slot: &mut Option<T>, name: &Ident, value: T) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(Error::new(name.span(), format!("`{}` is given twice", name)));
+    }
+
+    *slot = Some(value);
+    Ok(())
+}
+
+fn list<T>(input: ParseStream, item: fn(ParseStream) -> syn::Result<T>) -> syn::Result<Vec<T>> {
+    Ok(Punctuated::<T, Token![,]>::parse_terminated_with(input, item)?.into_iter().collect())
+}
+
+fn method(input: ParseStream) -> syn::Result<Ident> {
+    let method: Ident = input.parse()?;
+
+    if METHODS.contains(&&*method.to_string()) {
+        Ok(method)
+    } else {
+        Err(Error::new(method.span(), format!("expected one of {}", METHODS.join(", "))))
+    }
+}
+
+/// `"text/html" = path::to::handler`
+fn handler(input: ParseStream) -> syn::Result<Handler> {
+    let media_type = media_type(input)?;
+    input.parse::<Token![=]>()?;
+    let function: Path = input.parse()?;
+
+    Ok(Handler { media_type, function: quote!(#function) })
+}
+
+/// A string literal that parses as a media type, checked here rather than
+/// on the first request.
+fn media_type(input: ParseStream) -> syn::Result<LitStr> {
+    let media_type: LitStr = input.parse()?;
+
+    match media_type.value().parse::<mime::Mime>() {
+        Ok(_) => Ok(media_type),
+        Err(e) => Err(Error::new(media_type.span(), format!("invalid media type: {}", e))),
+    }
+}
+
+fn expand(args: Args, mut item: ItemImpl) -> syn::Result<Tokens> {
+    let body = match item.trait_ {
+        Some((_, ref path, _)) => body_type(path),
+        None => return Err(Error::new(item.impl_token.span, "#[resource] goes on an `impl Resource for ..` block")),
+    };
+
+    let generic = !item.generics.params.is_empty();
+    let self_ty = item.self_ty.clone();
+
+    let mut provides = Vec::new();
+    let mut accepts = Vec::new();
+    let mut handlers = Vec::new();
+
+    for impl_item in std::mem::take(&mut item.items) {
+        let mut function = match impl_item {
+            ImplItem::Fn(function) => function,
+            other => {
+                item.items.push(other);
+                continue;
+            },
+        };
+
+        let name = function.sig.ident.clone();
+        let provided = take(&mut function.attrs, "provides")?;
+        let accepted = take(&mut function.attrs, "accepts")?;
+
+        match (provided, accepted) {
+            (None, None) => {
+                item.items.push(ImplItem::Fn(function));
+                continue;
+            },
+            (Some(_), Some(_)) => return Err(Error::new(name.span(), "a handler either provides or accepts")),
+            (Some(types), None) => provides.extend(types.into_iter().map(|media_type| Handler { media_type, function: quote!(<#self_ty>::#name) })),
+            (None, Some(types)) => accepts.extend(types.into_iter().map(|media_type| Handler { media_type, function: quote!(<#self_ty>::#name) })),
+        }
+
+        handlers.push(function);
+    }
+
+    provides.extend(args.provides);
+    accepts.extend(args.accepts);
+
+    let context = quote!(&::gerust::context::Context<#body>);
+
+    if let Some(methods) = args.methods {
+        generate(&mut item, "allowed_methods", quote! {
+            fn allowed_methods(&self, _context: #context) -> &'static [::gerust::macros::Method] {
+                &[#(::gerust::macros::Method::#methods),*]
+            }
+        })?;
+    }
+
+    if !provides.is_empty() {
+        let (types, functions) = split(provides);
+        let provided = static_list(generic, "content_types_provided", quote!(::gerust::resource::ProvidedPair<#self_ty, #body>), quote! {
+            vec![#(::gerust::resource::ProvidedPair(#types.parse().unwrap(), #functions)),*]
+        });
+        generate(&mut item, "content_types_provided", quote! {
+            fn content_types_provided(&self, _context: #context) -> &'static [::gerust::resource::ProvidedPair<Self, #body>] {
+                #provided
+            }
+        })?;
+    }
+
+    if !accepts.is_empty() {
+        let (types, functions) = split(accepts);
+        let accepted = static_list(generic, "content_types_accepted", quote!(::gerust::resource::AcceptedPair<#self_ty, #body>), quote! {
+            vec![#(::gerust::resource::AcceptedPair(#types.parse().unwrap(), #functions)),*]
+        });
+        generate(&mut item, "content_types_accepted", quote! {
+            fn content_types_accepted(&self, _context: #context) -> &'static [::gerust::resource::AcceptedPair<Self, #body>] {
+                #accepted
+            }
+        })?;
+    }
+
+    if let Some(languages) = args.languages {
+        let languages = static_list(generic, "languages_provided", quote!(::gerust::macros::HeaderValue), quote! {
+            vec![#(::gerust::macros::HeaderValue::from_static(#languages)),*]
+        });
+        generate(&mut item, "languages_provided", quote! {
+            fn languages_provided(&self, _context: #context) -> &'static [::gerust::macros::HeaderValue] {
+                #languages
+            }
+        })?;
+    }
+
+    if let Some(charsets) = args.charsets {
+        let charsets = static_list(generic, "charsets_provided", quote!(::gerust::macros::HeaderValue), quote! {
+            vec![#(::gerust::macros::HeaderValue::from_static(#charsets)),*]
+        });
+        generate(&mut item, "charsets_provided", quote! {
+            fn charsets_provided(&self, _context: #context) -> &'static [::gerust::macros::HeaderValue] {
+                #charsets
+            }
+        })?;
+    }
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics #self_ty #where_clause {
+            #(#handlers)*
+        }
+    })
+}
+
+/// The body type of `Resource<B>`, `gerust::Body` if not given.
+fn body_type(path: &Path) -> Tokens {
+    let arguments = path.segments.last().map(|segment| &segment.arguments);
+
+    let body = match arguments {
+        Some(PathArguments::AngleBracketed(arguments)) => arguments.args.iter().find_map(|argument| match argument {
+            GenericArgument::Type(body) => Some(quote!(#body)),
+            _ => None,
+        }),
+        _ => None,
+    };
+
+    body.unwrap_or_else(|| quote!(::gerust::Body))
+}
+
+/// Removes the `name` annotations from a method, returning their types.
+fn take(attrs: &mut Vec<Attribute>, name: &str) -> syn::Result<Option<Vec<LitStr>>> {
+    let mut types: Option<Vec<LitStr>> = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident(name)) {
+        let listed = attr.parse_args_with(|input: ParseStream| list(input, media_type))?;
+        types.get_or_insert_with(Vec::new).extend(listed);
+    }
+
+    attrs.retain(|attr| !attr.path().is_ident(name));
+    Ok(types)
+}
+
+fn split(handlers: Vec<Handler>) -> (Vec<LitStr>, Vec<Tokens>) {
+    handlers.into_iter().map(|handler| (handler.media_type, handler.function)).unzip()
+}
+
+/// The body of a callback returning the list `init` builds on the first
+/// call.
+///
+/// A `static` can't name the parameters of a generic impl and would be
+/// shared by all its instances, so generic resources keep their lists in
+/// the registry of `gerust::macros::leak` instead.
+fn static_list(generic: bool, callback: &str, ty: Tokens, init: Tokens) -> Tokens {
+    if generic {
+        quote! {
+            ::gerust::macros::leak::<Self, #ty, _>(#callback, || #init)
+        }
+    } else {
+        quote! {
+            static LIST: ::std::sync::OnceLock<::std::vec::Vec<#ty>> = ::std::sync::OnceLock::new();
+            LIST.get_or_init(|| #init)
+        }
+    }
+}
+
+/// Adds a generated callback, unless the block defines it itself.
+fn generate(item: &mut ItemImpl, callback: &str, function: Tokens) -> syn::Result<()> {
+    let defined = item.items.iter().find_map(|impl_item| match impl_item {
+        ImplItem::Fn(function) if function.sig.ident == callback => Some(function.sig.ident.span()),
+        _ => None,
+    });
+
+    if let Some(span) = defined {
+        return Err(Error::new(span, format!("`{}` is generated by #[resource]", callback)));
+    }
+
+    item.items.push(syn::parse2(function)?);
+    Ok(())
+}
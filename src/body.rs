@@ -1,13 +1,19 @@
-use chunk::Chunk;
 use futures::Stream;
 use futures::sync::mpsc;
 
+/// The sending half of a body created through `Body::pair`.
+pub type Sender<B> = mpsc::Sender<Result<<B as Body>::Chunk, <B as Body>::Error>>;
+
+/// A request or response body the flow can work with.
+///
+/// This is all gerust needs from an HTTP stack's body type; `hyper::Body`
+/// is implemented in the crate root.
 // TODO: maybe FROM is not the right abstraction here
-pub trait Body: Stream + Sized {
-    type Chunk: From<::chunk::Chunk>;
-    type Error: From<::error::Error>;
+pub trait Body: Stream<Item = <Self as Body>::Chunk, Error = <Self as Body>::Error> + From<::chunk::Chunk> + Sized + Send + 'static {
+    type Chunk: From<::chunk::Chunk> + AsRef<[u8]> + Send + 'static;
+    type Error: From<::error::Error> + Send + 'static;
 
     fn empty() -> Self;
 
-    fn pair() -> (mpsc::Sender<Result<Self::Chunk, <Self as Body>::Error>>, Self);
+    fn pair() -> (Sender<Self>, Self);
 }
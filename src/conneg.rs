@@ -1,11 +1,8 @@
 use std;
 use mime;
 use http;
-use regex::Regex;
 
-use std::borrow::Borrow;
-
-use std::cmp::Ordering;
+use std::borrow::{Borrow, Cow};
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
@@ -31,108 +28,245 @@ impl From<std::num::ParseFloatError> for Error {
     }
 }
 
+/// Chooses the provided media type the `Accept` header prefers, following
+/// RFC 7231, section 5.3.2.
+///
+/// Each provided type gets the quality of the most specific range that
+/// matches it, parameters included; a quality of 0 rules it out. Of equal
+/// qualities, the type named by the more specific range wins, then the one
+/// provided first.
 pub fn choose_mediatype<'a, M: Borrow<mime::Mime>>(provided: &'a [M], header: &http::header::HeaderValue) -> Result<&'a mime::Mime, Error> {
-    lazy_static! {
-        static ref CONNEG: Regex = Regex::new(r"^\s*([^;]+)(?:;\s*q=(\S*))?\s*$").unwrap();
-    }
-
-    let header = header.to_str().unwrap();
-
-    let res = header.split(",")
-        .map(|s| CONNEG.captures(s))
-        .map(|c| {
-            match c {
-                Some(c) => {
-                    if let Some(mime_string) = c.get(1) {
-                        let m: mime::Mime = mime_string.as_str().parse()?;
-
-                        let q = if let Some(q_string) = c.get(2) {
-                            q_string.as_str().parse()?
-                        } else {
-                            1.0
-                        };
-
-                        Ok((m, q))
-                    } else {
-                        Err(Error::ParseError)
-                    }
-                }
-                None => Err(Error::ParseError)
+    let ranges = split(header.to_str().map_err(|_| Error::ParseError)?, ',')?
+        .into_iter()
+        .filter(|range| !range.trim().is_empty())
+        .map(MediaRange::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut chosen: Option<(&mime::Mime, (u16, Precedence))> = None;
+
+    for provided in provided {
+        let provided = provided.borrow();
+        let range = ranges.iter().filter(|range| range.matches(provided)).max_by_key(|range| range.precedence());
+
+        if let Some(range) = range.filter(|range| range.quality > 0) {
+            let rank = (range.quality, range.precedence());
+            let better = match chosen {
+                Some((_, chosen)) => rank > chosen,
+                None => true,
+            };
+
+            if better {
+                chosen = Some((provided, rank));
             }
+        }
+    }
 
-        });
+    chosen.map(|(provided, _)| provided).ok_or(Error::NotProvided)
+}
 
-    let mut found = None;
-    let mut found_quality = 0.0;
-    let mut partial = 0;
+/// Chooses the provided content coding the `Accept-Encoding` header
+/// prefers, following RFC 7231, section 5.3.4.
+///
+/// `identity` is always provided, and acceptable unless ruled out by a
+/// quality of 0, for itself or for `*`. Codings neither named nor matched
+/// by `*` are not acceptable. Of equal qualities, the coding provided
+/// first wins, `identity` last.
+pub fn choose_encoding<'a>(provided: &[&'a str], header: &http::header::HeaderValue) -> Result<&'a str, Error> {
+    let codings = split(header.to_str().map_err(|_| Error::ParseError)?, ',')?
+        .into_iter()
+        .filter(|coding| !coding.trim().is_empty())
+        .map(parse_coding)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let quality = |provided: &str| {
+        let named = codings.iter().find(|&&(coding, _)| coding.eq_ignore_ascii_case(provided));
+
+        match named.or_else(|| codings.iter().find(|&&(coding, _)| coding == "*")) {
+            Some(&(_, quality)) => quality,
+            None if provided.eq_ignore_ascii_case("identity") => 1000,
+            None => 0,
+        }
+    };
 
-    for parsed in res {
-        let (mime_type, quality) = parsed?;
+    let identity = if provided.iter().any(|coding| coding.eq_ignore_ascii_case("identity")) { None } else { Some("identity") };
+    let mut chosen: Option<(&str, u16)> = None;
 
-        let (provided_mime_type, current_partial) = if let Some((t, partial)) = mime_type_provided(&mime_type, provided) {
-            (t, partial)
-        } else {
-            continue;
+    for coding in provided.iter().cloned().chain(identity) {
+        let quality = quality(coding);
+        let better = match chosen {
+            Some((_, chosen)) => quality > chosen,
+            None => quality > 0,
         };
 
-        let (next, next_quality) = match found {
-            Some(t) if quality > found_quality => {
-                partial = current_partial;
-                (provided_mime_type, quality)
-            },
-            Some(t) if quality == found_quality => {
-                if partial > current_partial {
-                    partial = current_partial;
-                    (provided_mime_type, quality)
-                } else {
-                    continue;
-                }
-            },
-            Some(t) => {
-                continue;
-            },
-            None => {
-                (provided_mime_type, quality)
-            }
-        };
-        found = Some(next);
-        found_quality = quality;
+        if better {
+            chosen = Some((coding, quality));
+        }
+    }
+
+    chosen.map(|(coding, _)| coding).ok_or(Error::NotProvided)
+}
+
+/// A content coding of the `Accept-Encoding` header and its quality.
+fn parse_coding(coding: &str) -> Result<(&str, u16), Error> {
+    let mut parts = split(coding, ';')?.into_iter();
+    let name = parts.next().unwrap_or("").trim();
+
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '/' || c == '"' || c == '=') {
+        return Err(Error::ParseError);
     }
 
+    for param in parts {
+        let eq = param.find('=').ok_or(Error::ParseError)?;
 
-    if let Some(f) = found {
-        Ok(f)
-    } else {
-        Err(Error::ParseError)
+        if param[..eq].trim().eq_ignore_ascii_case("q") {
+            return Ok((name, parse_quality(param[eq + 1..].trim())?));
+        }
     }
+
+    Ok((name, 1000))
 }
 
-fn mime_type_provided<'a, M: Borrow<mime::Mime>>(mime_type: &mime::Mime, provided_mime_types: &'a [M]) -> Option<(&'a mime::Mime, u8)> {
-    for provided_ref in provided_mime_types {
-        let provided = provided_ref.borrow();
+/// Whether the type and subtype are given, and how many parameters.
+type Precedence = (u8, usize);
 
-        if mime_type.type_() == provided.type_() {
-            if mime_type.subtype() == provided.subtype() {
-                return Some((provided, 0))
-            }
-            if mime_type.subtype() == mime::STAR {
-                return Some((provided, 1))
+/// An element of the `Accept` header.
+#[derive(Debug)]
+struct MediaRange {
+    range: mime::Mime,
+    params: Vec<(String, String)>,
+    /// In thousandths, the precision a quality value has.
+    quality: u16,
+}
+
+impl MediaRange {
+    fn parse(range: &str) -> Result<MediaRange, Error> {
+        let mut parts = split(range, ';')?.into_iter();
+        let range: mime::Mime = parts.next().unwrap_or("").trim().parse()?;
+
+        if range.type_() == mime::STAR && range.subtype() != mime::STAR {
+            return Err(Error::ParseError);
+        }
+
+        let mut params = Vec::new();
+        let mut quality = 1000;
+
+        for param in parts {
+            let eq = param.find('=').ok_or(Error::ParseError)?;
+            let name = param[..eq].trim().to_lowercase();
+            let value = param[eq + 1..].trim();
+
+            // Parameters after the weight are accept extensions, not part of the range.
+            if name == "q" {
+                quality = parse_quality(value)?;
+                break;
             }
+
+            params.push((name, unquote(value).into_owned()));
+        }
+
+        Ok(MediaRange { range, params, quality })
+    }
+
+    fn matches(&self, mime: &mime::Mime) -> bool {
+        let type_ = self.range.type_() == mime::STAR || self.range.type_() == mime.type_();
+        let subtype = self.range.subtype() == mime::STAR || self.range.subtype() == mime.subtype();
+
+        type_ && subtype && self.params.iter().all(|(name, value)| {
+            mime.params().any(|(provided_name, provided)| {
+                let provided = unquote(provided.as_str());
+
+                provided_name.as_str().eq_ignore_ascii_case(name)
+                    && if name == "charset" { provided.eq_ignore_ascii_case(value) } else { *provided == **value }
+            })
+        })
+    }
+
+    fn precedence(&self) -> Precedence {
+        let given = if self.range.type_() == mime::STAR {
+            0
+        } else if self.range.subtype() == mime::STAR {
+            1
         } else {
-            if *mime_type == mime::STAR_STAR {
-                return Some((provided, 2))
-            }
+            2
+        };
+
+        (given, self.params.len())
+    }
+}
+
+/// `qvalue = ( "0" [ "." 0*3DIGIT ] ) / ( "1" [ "." 0*3("0") ] )`
+fn parse_quality(value: &str) -> Result<u16, Error> {
+    let (integer, fraction) = match value.find('.') {
+        Some(dot) => (&value[..dot], &value[dot + 1..]),
+        None => (value, ""),
+    };
+
+    if fraction.len() > 3 || !fraction.bytes().all(|digit| digit.is_ascii_digit()) {
+        return Err(Error::ParseError);
+    }
+
+    let thousandths = format!("{:0<3}", fraction).parse::<u16>().map_err(|_| Error::ParseError)?;
+
+    match integer {
+        "0" => Ok(thousandths),
+        "1" if thousandths == 0 => Ok(1000),
+        _ => Err(Error::ParseError),
+    }
+}
+
+/// Splits `value` at `separator`, except within quoted strings.
+fn split(value: &str, separator: char) -> Result<Vec<&str>, Error> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let (mut quoted, mut escaped) = (false, false);
+
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if !quoted && c == separator {
+            parts.push(&value[start..i]);
+            start = i + c.len_utf8();
         }
     }
 
-    None
+    if quoted {
+        return Err(Error::ParseError);
+    }
+
+    parts.push(&value[start..]);
+    Ok(parts)
+}
+
+/// The content of a quoted string, or `value` if it isn't quoted.
+fn unquote(value: &str) -> Cow<'_, str> {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Cow::Borrowed(value);
+    }
+
+    let quoted = &value[1..value.len() - 1];
+    if !quoted.contains('\\') {
+        return Cow::Borrowed(quoted);
+    }
+
+    let mut escaped = false;
+    let unquoted = quoted.chars().filter(|&c| {
+        let keep = escaped || c != '\\';
+        escaped = !escaped && c == '\\';
+        keep
+    });
+
+    Cow::Owned(unquoted.collect())
 }
 
 #[cfg(test)]
 mod tests {
     use http;
     use mime;
-    use super::choose_mediatype;
+    use super::{choose_encoding, choose_mediatype, Error};
 
     #[test]
     fn accept_type_parsing() {
@@ -144,7 +278,7 @@ mod tests {
 
         assert_eq!(res, Ok(&mime::TEXT_HTML));
 
-        let provided = &[xml.clone()];
+        let provided = std::slice::from_ref(&xml);
 
         let res = choose_mediatype(provided, &header);
 
@@ -179,4 +313,112 @@ mod tests {
         assert_eq!(res, Ok(&mime::TEXT_PLAIN));
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn most_specific_range_wins() {
+        let header = http::header::HeaderValue::from_static("text/*;q=0.3, text/html;q=0.7, text/html;level=1, */*;q=0.5");
+        let level_1: mime::Mime = "text/html;level=1".parse().unwrap();
+
+        assert_eq!(choose_mediatype(&[mime::TEXT_HTML, level_1.clone()], &header), Ok(&level_1));
+        assert_eq!(choose_mediatype(&[mime::TEXT_PLAIN, mime::TEXT_HTML], &header), Ok(&mime::TEXT_HTML));
+        assert_eq!(choose_mediatype(&[mime::TEXT_PLAIN, mime::IMAGE_PNG], &header), Ok(&mime::IMAGE_PNG));
+    }
+
+    #[test]
+    fn named_type_beats_wildcard_of_equal_quality() {
+        let header = http::header::HeaderValue::from_static("*/*, application/json");
+
+        assert_eq!(choose_mediatype(&[mime::TEXT_PLAIN, mime::APPLICATION_JSON], &header), Ok(&mime::APPLICATION_JSON));
+    }
+
+    #[test]
+    fn parameters_must_match() {
+        let header = http::header::HeaderValue::from_static("application/vnd.api+json; version=\"2\", text/plain; charset=UTF-8; q=0.5");
+        let v1: mime::Mime = "application/vnd.api+json; version=1".parse().unwrap();
+        let v2: mime::Mime = "application/vnd.api+json; version=2".parse().unwrap();
+
+        assert_eq!(choose_mediatype(&[v1.clone(), v2.clone()], &header), Ok(&v2));
+        assert_eq!(choose_mediatype(&[v1.clone(), mime::TEXT_PLAIN_UTF_8], &header), Ok(&mime::TEXT_PLAIN_UTF_8));
+        assert_eq!(choose_mediatype(&[v1, mime::TEXT_PLAIN], &header), Err(Error::NotProvided));
+    }
+
+    #[test]
+    fn quoted_parameters() {
+        let header = http::header::HeaderValue::from_static("text/plain; foo=\"a,b\", text/html; foo=\"x;y\"; q=0.5");
+        let plain: mime::Mime = "text/plain; foo=\"a,b\"".parse().unwrap();
+        let html: mime::Mime = "text/html; foo=\"x;y\"".parse().unwrap();
+
+        assert_eq!(choose_mediatype(&[html.clone(), plain.clone()], &header), Ok(&plain));
+        assert_eq!(choose_mediatype(&[mime::TEXT_HTML, html.clone()], &header), Ok(&html));
+        assert_eq!(choose_mediatype(&[mime::TEXT_PLAIN, mime::TEXT_HTML], &header), Err(Error::NotProvided));
+
+        // An escaped quote doesn't end the quoted string.
+        let header = http::header::HeaderValue::from_static("text/html; foo=\"x\\\",y\", text/plain; q=0.5");
+        assert_eq!(choose_mediatype(&[html, mime::TEXT_PLAIN], &header), Ok(&mime::TEXT_PLAIN));
+    }
+
+    #[test]
+    fn zero_quality_excludes() {
+        let header = http::header::HeaderValue::from_static("text/*, text/html;q=0");
+
+        assert_eq!(choose_mediatype(&[mime::TEXT_HTML, mime::TEXT_PLAIN], &header), Ok(&mime::TEXT_PLAIN));
+        assert_eq!(choose_mediatype(&[mime::TEXT_HTML], &header), Err(Error::NotProvided));
+    }
+
+    #[test]
+    fn not_provided() {
+        let header = http::header::HeaderValue::from_static("text/plain");
+
+        assert_eq!(choose_mediatype(&[mime::TEXT_HTML], &header), Err(Error::NotProvided));
+    }
+
+    #[test]
+    fn malformed_ranges() {
+        for header in &["text", "*/html", "text/html;q=2", "text/html;q=0.1234", "text/html;level", "text/html;level=\"1"] {
+            let header = http::header::HeaderValue::from_static(header);
+            assert_eq!(choose_mediatype(&[mime::TEXT_HTML], &header), Err(Error::ParseError), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn encoding_by_quality() {
+        let header = http::header::HeaderValue::from_static("gzip;q=0.5, br, identity;q=0.1");
+
+        assert_eq!(choose_encoding(&["gzip", "br"], &header), Ok("br"));
+        assert_eq!(choose_encoding(&["gzip"], &header), Ok("gzip"));
+        assert_eq!(choose_encoding(&["deflate"], &header), Ok("identity"));
+    }
+
+    #[test]
+    fn identity_is_acceptable_unless_excluded() {
+        let any = http::header::HeaderValue::from_static("gzip");
+        assert_eq!(choose_encoding(&[], &any), Ok("identity"));
+
+        let none = http::header::HeaderValue::from_static("");
+        assert_eq!(choose_encoding(&["gzip"], &none), Ok("identity"));
+
+        for header in &["gzip, identity;q=0", "gzip, *;q=0", "*;q=0"] {
+            let header = http::header::HeaderValue::from_static(header);
+            assert_eq!(choose_encoding(&[], &header), Err(Error::NotProvided), "{:?}", header);
+        }
+
+        let wildcard = http::header::HeaderValue::from_static("*;q=0, Identity");
+        assert_eq!(choose_encoding(&["gzip"], &wildcard), Ok("identity"));
+    }
+
+    #[test]
+    fn wildcard_encoding() {
+        let header = http::header::HeaderValue::from_static("*, gzip;q=0");
+
+        assert_eq!(choose_encoding(&["gzip", "br"], &header), Ok("br"));
+        assert_eq!(choose_encoding(&["gzip"], &header), Ok("identity"));
+    }
+
+    #[test]
+    fn malformed_encodings() {
+        for header in &["gzip;q=2", "gzip;q", "gzip br", "text/html"] {
+            let header = http::header::HeaderValue::from_static(header);
+            assert_eq!(choose_encoding(&["gzip"], &header), Err(Error::ParseError), "{:?}", header);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use http;
+use http::uri::Scheme;
+
+use body::Body;
+
+/// The request context handed to every `Resource` callback.
+///
+/// Wraps the incoming request, so resources can base their decisions
+/// on method, headers and URI, and consume the body in their handlers.
+#[derive(Debug)]
+pub struct Context<B = ::Body> {
+    request: http::Request<B>,
+    scheme: Scheme,
+}
+
+impl<B: Body> Context<B> {
+    /// The scheme is taken from a `Scheme` request extension, which the
+    /// server sets for TLS connections, and defaults to `http`.
+    pub fn new(request: http::Request<B>) -> Context<B> {
+        let scheme = request.extensions().get::<Scheme>().cloned().unwrap_or(Scheme::HTTP);
+
+        Context { request, scheme }
+    }
+
+    pub fn request(&self) -> &http::Request<B> {
+        &self.request
+    }
+
+    pub fn request_mut(&mut self) -> &mut http::Request<B> {
+        &mut self.request
+    }
+
+    pub fn method(&self) -> &http::Method {
+        self.request.method()
+    }
+
+    pub fn uri(&self) -> &http::Uri {
+        self.request.uri()
+    }
+
+    pub fn headers(&self) -> &http::HeaderMap {
+        self.request.headers()
+    }
+
+    /// The id of the last event an `EventSource` received before it
+    /// reconnected, to resume a `sse` stream from.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.headers().get("last-event-id")?.to_str().ok()
+    }
+
+    /// The scheme the request was received over.
+    pub fn scheme(&self) -> &Scheme {
+        &self.scheme
+    }
+
+    /// The URI the request was addressed to, without its path.
+    ///
+    /// Built from the scheme and the `Host` header, `None` if the client
+    /// sent neither a host nor an absolute URI.
+    pub fn base_uri(&self) -> Option<http::Uri> {
+        let authority = match self.uri().authority_part() {
+            Some(authority) => authority.as_str().to_string(),
+            None => self.headers().get(http::header::HOST)?.to_str().ok()?.to_string(),
+        };
+
+        format!("{}://{}/", self.scheme, authority).parse().ok()
+    }
+
+    pub fn body_mut(&mut self) -> &mut B {
+        self.request.body_mut()
+    }
+
+    /// Takes the request body, leaving an empty one in its place.
+    ///
+    /// Deferred handlers use this to consume the body in a future.
+    pub fn take_body(&mut self) -> B {
+        ::std::mem::replace(self.request.body_mut(), B::empty())
+    }
+
+    pub fn into_request(self) -> http::Request<B> {
+        self.request
+    }
+}
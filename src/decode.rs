@@ -0,0 +1,110 @@
+//! Accepted-content handlers that decode request bodies.
+//!
+//! Each handler reads the whole body, decodes it into the `Item` of the
+//! resource's `Handles` implementation and passes it to `handle`. Bodies
+//! that don't decode are answered with `400 Bad Request` and the reason.
+//!
+//! ```rust
+//! # extern crate gerust;
+//! # extern crate mime;
+//! # extern crate serde;
+//! # #[macro_use] extern crate serde_derive;
+//! # use gerust::context::Context;
+//! # use gerust::decode;
+//! # use gerust::flow::DelayedResponse;
+//! # use gerust::resource::{Resource, AcceptedPair, ProvidedPair, Handles, Deferred};
+//! # use gerust::response::Entity;
+//! #[derive(Deserialize)]
+//! struct Order {
+//!     id: u32,
+//!     title: String,
+//! }
+//!
+//! #[derive(Default)]
+//! struct Orders {
+//!     received: Vec<Order>,
+//! }
+//!
+//! impl Resource for Orders {
+//! #   fn content_types_provided(&self, _: &Context) -> &'static [ProvidedPair<Self>] {
+//! #       &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+//! #   }
+//!     fn content_types_accepted(&self, _: &Context) -> &'static [AcceptedPair<Self>] {
+//!         &[
+//!             AcceptedPair(mime::APPLICATION_JSON, decode::json),
+//!             AcceptedPair(mime::APPLICATION_WWW_FORM_URLENCODED, decode::form),
+//!         ]
+//!     }
+//! }
+//!
+//! # impl Orders {
+//! #     fn to_text(&mut self, _: &Context, _: &mut DelayedResponse) -> Deferred<Self, Entity> {
+//! #         "".into()
+//! #     }
+//! # }
+//! impl Handles for Orders {
+//!     type Item = Order;
+//!
+//!     fn handle(&mut self, order: Order) {
+//!         self.received.push(order);
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! The handlers are behind the `json`, `form` and `multipart` features.
+
+use futures::{Future, Stream};
+use serde::de::DeserializeOwned;
+
+use body::Body;
+use context::Context;
+use error::Error;
+use flow::DelayedResponse;
+use resource::{Resource, Handles, Deferred};
+
+#[cfg(feature = "multipart")]
+pub use self::multipart::{multipart, FilePart, Multipart};
+
+#[cfg(feature = "multipart")]
+mod multipart;
+
+/// Decodes an `application/json` body.
+#[cfg(feature = "json")]
+pub fn json<R, B>(_resource: &mut R, context: &mut Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, ()>
+    where R: Resource<B> + Handles,
+          R::Item: DeserializeOwned + Send + 'static,
+          B: Body
+{
+    decode(context, |body| ::serde_json::from_slice(&body).map_err(|e| format!("invalid JSON: {}", e)))
+}
+
+/// Decodes an `application/x-www-form-urlencoded` body.
+#[cfg(feature = "form")]
+pub fn form<R, B>(_resource: &mut R, context: &mut Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, ()>
+    where R: Resource<B> + Handles,
+          R::Item: DeserializeOwned + Send + 'static,
+          B: Body
+{
+    decode(context, |body| ::serde_urlencoded::from_bytes(&body).map_err(|e| format!("invalid form: {}", e)))
+}
+
+/// Reads the whole body and hands what `parse` made of it to the resource.
+fn decode<R, B, F>(context: &mut Context<B>, parse: F) -> Deferred<R, ()>
+    where R: Resource<B> + Handles,
+          R::Item: Send + 'static,
+          B: Body,
+          F: FnOnce(Vec<u8>) -> Result<R::Item, String> + Send + 'static
+{
+    let item = read(context.take_body())
+        .and_then(|body| parse(body).map_err(Error::BadRequest));
+
+    Deferred::apply(item.map(|item| move |resource: &mut R| resource.handle(item)))
+}
+
+fn read<B: Body>(body: B) -> impl Future<Item=Vec<u8>, Error=Error> + Send {
+    body.map_err(|_| Error::DefaultError).fold(Vec::new(), |mut body, chunk| {
+        body.extend_from_slice(chunk.as_ref());
+        Ok::<_, Error>(body)
+    })
+}
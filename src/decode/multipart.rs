@@ -0,0 +1,377 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{future, Future, Stream};
+use mime;
+use serde::de::DeserializeOwned;
+use serde_urlencoded;
+
+use body::Body;
+use context::Context;
+use error::Error;
+use flow::DelayedResponse;
+use resource::{Resource, Handles, Deferred};
+
+/// Part headers larger than this are rejected.
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+/// Text fields larger than this are rejected, files are not limited.
+const MAX_FIELD_SIZE: usize = 64 * 1024;
+
+static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// A `multipart/form-data` body: the text fields decoded into `T`, and the
+/// uploaded files.
+#[derive(Debug)]
+pub struct Multipart<T> {
+    pub fields: T,
+    pub files: Vec<FilePart>,
+}
+
+/// An uploaded file, streamed to a temporary file while the body arrives.
+///
+/// The temporary file is removed when the part is dropped, unless it was
+/// persisted.
+#[derive(Debug)]
+pub struct FilePart {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<mime::Mime>,
+    path: PathBuf,
+    len: u64,
+    persisted: bool,
+}
+
+impl FilePart {
+    /// The name of the form field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the file on the client, if it sent one.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&mime::Mime> {
+        self.content_type.as_ref()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Where the upload is stored for now.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Moves the upload to `path`, keeping it.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> io::Result<()> {
+        if fs::rename(&self.path, &path).is_err() {
+            // Temporary files may live on another file system.
+            fs::copy(&self.path, &path)?;
+            fs::remove_file(&self.path)?;
+        }
+
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for FilePart {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Decodes a `multipart/form-data` body, streaming file parts to disk.
+///
+/// Text fields are decoded the same way as a form.
+pub fn multipart<R, B, T>(_resource: &mut R, context: &mut Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, ()>
+    where R: Resource<B> + Handles<Item=Multipart<T>>,
+          T: DeserializeOwned + Send + 'static,
+          B: Body
+{
+    let boundary = context.headers().get(::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .and_then(|mime| mime.get_param(mime::BOUNDARY).map(|boundary| boundary.as_str().to_string()));
+
+    let parser = match boundary {
+        Some(boundary) => Parser::new(&boundary),
+        None => return Deferred::future(future::err(Error::BadRequest("missing multipart boundary".to_string()))),
+    };
+
+    let item = context.take_body()
+        .map_err(|_| Error::DefaultError)
+        .fold(parser, |mut parser, chunk| parser.feed(chunk.as_ref()).map(|()| parser))
+        .and_then(Parser::finish);
+
+    Deferred::apply(item.map(|item| move |resource: &mut R| resource.handle(item)))
+}
+
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    /// Right after a boundary, which either ends the body or starts a part.
+    Delimiter,
+    Headers,
+    Field(String, Vec<u8>),
+    File(FilePart, File),
+    /// After the closing boundary.
+    Epilogue,
+}
+
+/// Splits a body into parts as it arrives.
+struct Parser {
+    /// The CRLF and dashes before the boundary end the previous part.
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+    fields: Vec<(String, String)>,
+    files: Vec<FilePart>,
+}
+
+impl Parser {
+    fn new(boundary: &str) -> Parser {
+        Parser {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // Lets the first boundary match without a part before it.
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+            fields: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.buffer.extend_from_slice(chunk);
+
+        while self.step()? {}
+
+        Ok(())
+    }
+
+    /// Makes progress on the buffered bytes; false if more are needed.
+    fn step(&mut self) -> Result<bool, Error> {
+        match self.state {
+            State::Preamble => match find(&self.buffer, &self.delimiter) {
+                Some(at) => {
+                    self.buffer.drain(..at + self.delimiter.len());
+                    self.state = State::Delimiter;
+                    Ok(true)
+                },
+                None => {
+                    let keep = self.delimiter.len() - 1;
+                    if self.buffer.len() > keep {
+                        let end = self.buffer.len() - keep;
+                        self.buffer.drain(..end);
+                    }
+                    Ok(false)
+                },
+            },
+            State::Delimiter => {
+                if self.buffer.len() < 2 {
+                    return Ok(false);
+                }
+
+                self.state = match &self.buffer[..2] {
+                    b"--" => State::Epilogue,
+                    b"\r\n" => State::Headers,
+                    _ => return Err(invalid("malformed boundary")),
+                };
+                self.buffer.drain(..2);
+                Ok(true)
+            },
+            State::Headers => {
+                // A part without headers starts with the empty line.
+                let end = if self.buffer.starts_with(b"\r\n") {
+                    Some(0)
+                } else {
+                    find(&self.buffer, b"\r\n\r\n").map(|end| end + 2)
+                };
+
+                let end = match end {
+                    Some(end) => end,
+                    None if self.buffer.len() > MAX_HEADER_SIZE => return Err(invalid("part headers too large")),
+                    None => return Ok(false),
+                };
+
+                let headers: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                self.state = self.start_part(&headers[..end])?;
+                Ok(true)
+            },
+            State::Field(..) | State::File(..) => {
+                let (data, done) = match find(&self.buffer, &self.delimiter) {
+                    Some(at) => (at, true),
+                    None => (self.buffer.len().saturating_sub(self.delimiter.len() - 1), false),
+                };
+
+                self.write(data)?;
+                if !done {
+                    return Ok(false);
+                }
+
+                self.buffer.drain(..self.delimiter.len());
+                self.finish_part()?;
+                Ok(true)
+            },
+            State::Epilogue => {
+                self.buffer.clear();
+                Ok(false)
+            },
+        }
+    }
+
+    fn start_part(&mut self, headers: &[u8]) -> Result<State, Error> {
+        let headers = String::from_utf8(headers.to_vec()).map_err(|_| invalid("part headers are not UTF-8"))?;
+
+        let mut disposition = None;
+        let mut content_type = None;
+
+        for line in headers.split("\r\n").filter(|line| !line.is_empty()) {
+            let colon = line.find(':').ok_or_else(|| invalid("malformed part header"))?;
+            let (name, value) = (line[..colon].trim(), line[colon + 1..].trim());
+
+            if name.eq_ignore_ascii_case("content-disposition") {
+                disposition = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = value.parse().ok();
+            }
+        }
+
+        let disposition = disposition.ok_or_else(|| invalid("part without Content-Disposition"))?;
+        let mut params = parameters(&disposition);
+        let name = params.iter().position(|(key, _)| key == "name")
+            .map(|i| params.swap_remove(i).1)
+            .ok_or_else(|| invalid("part without a name"))?;
+        let filename = params.into_iter().find(|(key, _)| key == "filename").map(|(_, value)| value);
+
+        if filename.is_none() {
+            return Ok(State::Field(name, Vec::new()));
+        }
+
+        let path = env::temp_dir().join(format!("gerust-upload-{}-{}", process::id(), UPLOADS.fetch_add(1, Ordering::SeqCst)));
+        let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        let part = FilePart { name, filename, content_type, path, len: 0, persisted: false };
+
+        Ok(State::File(part, file))
+    }
+
+    /// Moves the first `len` buffered bytes into the current part.
+    fn write(&mut self, len: usize) -> Result<(), Error> {
+        match self.state {
+            State::Field(_, ref mut value) => {
+                if value.len() + len > MAX_FIELD_SIZE {
+                    return Err(invalid("field too large"));
+                }
+                value.extend_from_slice(&self.buffer[..len]);
+            },
+            State::File(ref mut part, ref mut file) => {
+                file.write_all(&self.buffer[..len])?;
+                part.len += len as u64;
+            },
+            _ => unreachable!("not inside a part"),
+        }
+
+        self.buffer.drain(..len);
+        Ok(())
+    }
+
+    fn finish_part(&mut self) -> Result<(), Error> {
+        match ::std::mem::replace(&mut self.state, State::Delimiter) {
+            State::Field(name, value) => {
+                let value = String::from_utf8(value).map_err(|_| invalid("field is not UTF-8"))?;
+                self.fields.push((name, value));
+            },
+            State::File(part, mut file) => {
+                file.flush()?;
+                self.files.push(part);
+            },
+            _ => unreachable!("not inside a part"),
+        }
+
+        Ok(())
+    }
+
+    fn finish<T: DeserializeOwned>(self) -> Result<Multipart<T>, Error> {
+        match self.state {
+            State::Epilogue => {},
+            _ => return Err(invalid("body ended early")),
+        }
+
+        // Decoded like a form, so fields get the same conversions.
+        let form = serde_urlencoded::to_string(&self.fields).map_err(|e| invalid(&e.to_string()))?;
+        let fields = serde_urlencoded::from_str(&form).map_err(|e| Error::BadRequest(format!("invalid form: {}", e)))?;
+
+        Ok(Multipart { fields, files: self.files })
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::BadRequest(format!("invalid multipart body: {}", reason))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// The `key=value` parameters of a `Content-Disposition` header, with
+/// quoted values unescaped.
+fn parameters(disposition: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = match disposition.find(';') {
+        Some(start) => &disposition[start + 1..],
+        None => return params,
+    };
+
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_lowercase();
+        let value = rest[eq + 1..].trim_start();
+
+        let (value, next) = if let Some(quoted) = value.strip_prefix('"') {
+            let mut unquoted = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = value.len();
+
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 2;
+                        break;
+                    },
+                    c => unquoted.push(c),
+                }
+            }
+
+            (unquoted, &value[end..])
+        } else {
+            let end = value.find(';').unwrap_or(value.len());
+            (value[..end].trim().to_string(), &value[end..])
+        };
+
+        params.push((key, value));
+        rest = match next.find(';') {
+            Some(start) => &next[start + 1..],
+            None => break,
+        };
+    }
+
+    params
+}
@@ -0,0 +1,124 @@
+//! Output handlers that serialize the representation of a resource.
+//!
+//! A resource implements `Represents` once and lists a provider for every
+//! format it offers, so the client's `Accept` header picks one:
+//!
+//! ```rust
+//! # extern crate gerust;
+//! # extern crate mime;
+//! # extern crate serde;
+//! # #[macro_use] extern crate serde_derive;
+//! # use gerust::context::Context;
+//! # use gerust::encode;
+//! # use gerust::resource::{Resource, ProvidedPair, Represents};
+//! #[derive(Serialize)]
+//! struct Order {
+//!     id: u32,
+//!     title: String,
+//! }
+//!
+//! #[derive(Default)]
+//! struct OrderResource;
+//!
+//! impl Resource for OrderResource {
+//!     fn content_types_provided(&self, _: &Context) -> &'static [ProvidedPair<Self>] {
+//!         &[ProvidedPair(mime::APPLICATION_JSON, encode::json)]
+//!     }
+//! }
+//!
+//! impl Represents for OrderResource {
+//!     type Representation = Order;
+//!
+//!     fn represent(&mut self, _: &Context) -> Order {
+//!         Order { id: 7, title: "Tea".to_string() }
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! JSON is behind the `json` feature, CBOR, MessagePack and YAML behind
+//! `cbor`, `msgpack` and `yaml`. Their media types are available as
+//! statics here, as `mime` has no constants for them.
+
+use std::fmt::Display;
+
+use futures::future;
+use serde::Serialize;
+
+use body::Body;
+use context::Context;
+use error::Error;
+use flow::DelayedResponse;
+use resource::{Resource, Represents, Deferred};
+use response::Entity;
+
+#[cfg(feature = "cbor")]
+lazy_static! {
+    /// `application/cbor`
+    pub static ref APPLICATION_CBOR: ::mime::Mime = "application/cbor".parse().unwrap();
+}
+
+#[cfg(feature = "msgpack")]
+lazy_static! {
+    /// `application/msgpack`
+    pub static ref APPLICATION_MSGPACK: ::mime::Mime = "application/msgpack".parse().unwrap();
+}
+
+#[cfg(feature = "yaml")]
+lazy_static! {
+    /// `application/yaml`
+    pub static ref APPLICATION_YAML: ::mime::Mime = "application/yaml".parse().unwrap();
+}
+
+/// Provides `application/json`.
+#[cfg(feature = "json")]
+pub fn json<R, B>(resource: &mut R, context: &Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, Entity>
+    where R: Resource<B> + Represents<B>,
+          R::Representation: Serialize,
+          B: Body
+{
+    encode(::serde_json::to_vec(&resource.represent(context)))
+}
+
+/// Provides `application/cbor`.
+#[cfg(feature = "cbor")]
+pub fn cbor<R, B>(resource: &mut R, context: &Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, Entity>
+    where R: Resource<B> + Represents<B>,
+          R::Representation: Serialize,
+          B: Body
+{
+    encode(::serde_cbor::to_vec(&resource.represent(context)))
+}
+
+/// Provides `application/msgpack`, with structs as maps so fields keep
+/// their names.
+#[cfg(feature = "msgpack")]
+pub fn msgpack<R, B>(resource: &mut R, context: &Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, Entity>
+    where R: Resource<B> + Represents<B>,
+          R::Representation: Serialize,
+          B: Body
+{
+    encode(::rmp_serde::to_vec_named(&resource.represent(context)))
+}
+
+/// Provides `application/yaml`.
+#[cfg(feature = "yaml")]
+pub fn yaml<R, B>(resource: &mut R, context: &Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<R, Entity>
+    where R: Resource<B> + Represents<B>,
+          R::Representation: Serialize,
+          B: Body
+{
+    encode(::serde_yaml::to_vec(&resource.represent(context)))
+}
+
+/// Serialization only fails for representations the format can't express,
+/// which is a bug in the resource rather than the request.
+fn encode<R: 'static, E: Display>(encoded: Result<Vec<u8>, E>) -> Deferred<R, Entity> {
+    match encoded {
+        Ok(body) => body.into(),
+        Err(e) => {
+            error!("serializing the representation failed: {}", e);
+            Deferred::future(future::err(Error::DefaultError))
+        },
+    }
+}
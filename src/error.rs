@@ -1,3 +1,29 @@
+use std::io;
+
+use response;
+
+#[derive(Debug)]
 pub enum Error {
-    DefaultError
-}
\ No newline at end of file
+    DefaultError,
+    /// Reading a response body, e.g. from a file, failed.
+    Io(io::Error),
+    /// The request can't be processed, e.g. because its body doesn't
+    /// decode. Answered with `400 Bad Request` and the reason as body.
+    BadRequest(String),
+    /// A request body grew larger than the resource accepts.
+    PayloadTooLarge,
+    /// Writing the response failed, e.g. because the client went away.
+    Response(response::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<response::Error> for Error {
+    fn from(e: response::Error) -> Error {
+        Error::Response(e)
+    }
+}
@@ -1,254 +1,459 @@
 use http;
-use hyper;
 use mime;
-use backtrace;
 use std;
 use futures;
-use futures::Sink;
-use futures::Future;
+use futures::{Async, Future, Poll};
 use futures::sync::oneshot::Sender;
 use futures_cpupool;
-use tokio_core;
 use std::sync::Arc;
 
-use ::Body;
+use body::Body;
 
-use resource::Resource;
+use context::Context;
+use error::Error;
+use resource::{Resource, AcceptedPair, AcceptHandler, ProvidedPair, ProvideHandler, Deferred};
 
-use std::fmt::Debug;
+pub use response::DelayedResponse;
+use range;
+use server;
+use websocket;
+use response::Entity;
+use upload::Upload;
+
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
 
 pub static DIAGRAM_VERSION: u8 = 3;
 
-type StateFn<R> = fn(&mut ResourceWrapper<R>) -> Outcomes<R>;
+type StateFn<R, B> = fn(&mut ResourceWrapper<R, B>) -> Outcomes<R, B>;
 
-pub enum Outcomes<R> where R: Resource {
-    Next(StateFn<R>),
-    StartResponse(StateFn<R>),
-    Done,
-    InputHandler(fn(&mut R, &mut http::Request<Body>, &mut DelayedResponse)),
-    OutputHandler(fn(&mut R, &mut DelayedResponse)),
-    Halt(http::status::StatusCode),
+/// A decision in the graph, named after its diagram coordinates.
+pub struct Node<R, B> where R: Resource<B>, B: Body {
+    name: &'static str,
+    decide: StateFn<R, B>,
 }
 
-// TODO: Maybe turn into struct, holding body and builder?
-pub enum DelayedResponse {
-    Waiting(http::response::Builder),
-    Started(futures::sync::mpsc::Sender<Result<hyper::Chunk, hyper::Error>>)
+/// The node for the `ResourceWrapper` method `$name`.
+macro_rules! node {
+    ($name:ident) => { Node { name: stringify!($name), decide: Self::$name } }
 }
 
-impl DelayedResponse {
-    fn new() -> DelayedResponse {
-        let builder = http::response::Builder::new();
-        DelayedResponse::Waiting(builder)
-    }
+/// The decisions a flow went through, in order.
+///
+/// Attached as an extension to every response produced by the flow.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace(Vec<&'static str>);
 
-    fn builder(&mut self) -> &mut http::response::Builder {
-        match *self {
-            DelayedResponse::Waiting(ref mut b) => b,
-            _ => { panic!("called builder() after response has started!") }
-        }
+impl Trace {
+    /// The visited nodes, e.g. `["b13", "b12", ...]`.
+    pub fn nodes(&self) -> &[&'static str] {
+        &self.0
     }
 
-    pub fn response_body(&mut self) -> &mut futures::sync::mpsc::Sender<Result<hyper::Chunk, hyper::Error>> {
-        match *self {
-            DelayedResponse::Started(ref mut r) => r,
-            _ => { panic!("called response_body() before response has started!") }
-        }
+    pub fn visited(&self, node: &str) -> bool {
+        self.0.contains(&node)
     }
 
-    pub fn waiting(&self) -> bool {
-        match *self {
-            DelayedResponse::Waiting(_) => true,
-            _ => false
-        }
+    /// The node that decided the response.
+    pub fn last(&self) -> Option<&'static str> {
+        self.0.last().cloned()
     }
+}
 
-    pub fn started(&self) -> bool {
-        match *self {
-            DelayedResponse::Started(_) => true,
-            _ => false
-        }
-    }
+/// Continues the flow once a deferred callback resolved.
+type Transition<R, B> = Box<dyn FnOnce(&mut ResourceWrapper<R, B>) -> Outcomes<R, B> + Send>;
+
+pub enum Outcomes<R, B> where R: Resource<B>, B: Body {
+    Next(Node<R, B>),
+    Pending(Box<dyn Future<Item=Transition<R, B>, Error=Error> + Send>),
+    Done,
+    InputHandler(AcceptHandler<R, B>),
+    OutputHandler(ProvideHandler<R, B>),
+    Halt(http::status::StatusCode),
 }
 
 pub trait Flow {
+    type Body: Body;
     type Request;
     type Response;
     type Future;
     type Error;
 
-    fn new(pool: Arc<futures_cpupool::CpuPool>, handle: tokio_core::reactor::Remote) -> Self;
+    fn new(pool: Arc<futures_cpupool::CpuPool>) -> Self;
 
     fn execute<R>(&mut self, resource: R, request: Self::Request, sx: Sender<Self::Response>)
-        where R: Resource + Debug;
+        where R: Resource<Self::Body> + Debug + Send;
+
+    /// Like `execute`, but resolves to the response instead of sending it.
+    fn handle<R>(&mut self, resource: R, request: Self::Request) -> Self::Future
+        where R: Resource<Self::Body> + Debug + Send;
 }
 
+/// Runs resources on requests and responses with bodies of type `B`.
 #[derive(Debug)]
-pub struct HttpFlow {
+pub struct HttpFlow<B = ::Body> {
     pool: Arc<futures_cpupool::CpuPool>,
-    handle: tokio_core::reactor::Remote,
+    body: PhantomData<fn() -> B>,
 }
 
+/// A flow ended without producing a response, e.g. because a resource
+/// callback panicked.
+#[derive(Debug)]
 pub struct FlowError;
 
-impl Flow for HttpFlow
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the flow ended without a response")
+    }
+}
+
+impl std::error::Error for FlowError {}
+
+lazy_static! {
+    /// Runs the flows started through `handle`.
+    static ref POOL: Arc<futures_cpupool::CpuPool> = Arc::new(futures_cpupool::Builder::new()
+        .name_prefix("gerust-flow-")
+        .create());
+}
+
+/// Runs `resource` on `request` and resolves to its response.
+///
+/// No `Server` is involved, so resources can be mounted inside other
+/// servers and frameworks. The flow runs on a pool shared by all calls,
+/// use `HttpFlow::handle` to run it on a pool of your own. The response
+/// resolves once its head is ready, the body is streamed afterwards.
+pub fn handle<R, B>(resource: R, request: http::Request<B>) -> impl Future<Item=http::Response<B>, Error=FlowError> + Send
+    where R: Resource<B> + Debug + Send,
+          B: Body
 {
-    type Request = http::Request<Body>;
-    type Response = http::Response<Body>;
+    HttpFlow::new(default_pool()).handle(resource, request)
+}
+
+/// The pool flows run on unless told otherwise.
+pub(crate) fn default_pool() -> Arc<futures_cpupool::CpuPool> {
+    POOL.clone()
+}
+
+impl<B: Body> Flow for HttpFlow<B>
+{
+    type Body = B;
+    type Request = http::Request<B>;
+    type Response = http::Response<B>;
     type Error = FlowError;
-    type Future = Box<Future<Item=Self::Response, Error=Self::Error>>;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error> + Send>;
 
-    fn new(pool: Arc<futures_cpupool::CpuPool>, handle: tokio_core::reactor::Remote) -> HttpFlow {
-        HttpFlow { pool, handle }
+    fn new(pool: Arc<futures_cpupool::CpuPool>) -> HttpFlow<B> {
+        HttpFlow { pool, body: PhantomData }
     }
 
+    /// Runs the decision graph for `resource` on the pool.
+    ///
+    /// Synchronous callbacks run inline on a pool thread, deferred callbacks
+    /// suspend the flow until their future resolves.
     fn execute<R>(&mut self, resource: R, request: Self::Request, sx: Sender<Self::Response>)
-        where R: Resource + Debug
+        where R: Resource<B> + Debug + Send
     {
-        let mut sender = Some(sx);
+        let execution = Execution {
+            wrapper: ResourceWrapper::new(resource, request, self.pool.clone()),
+            current: Some(Outcomes::Next(Node { name: "b13", decide: ResourceWrapper::b13 })),
+            sender: Some(sx),
+            trace: Trace::default(),
+        };
 
-        let mut wrapper = ResourceWrapper::new(resource, request);
+        self.pool.spawn(execution).forget();
+    }
 
-        let mut current = Outcomes::Next(ResourceWrapper::b13);
+    fn handle<R>(&mut self, resource: R, request: Self::Request) -> Self::Future
+        where R: Resource<B> + Debug + Send
+    {
+        let (sx, rx) = futures::sync::oneshot::channel();
+        self.execute(resource, request, sx);
 
-        loop {
-            //println!("transitioning from: {:?}", self);
+        // The sender is only dropped without a response if the flow panicked.
+        Box::new(rx.map_err(|_| FlowError))
+    }
+}
 
-            match current {
-                Outcomes::Next(f) => {
-                    backtrace::resolve(f as *mut std::os::raw::c_void, |symbol| {
-                        println!("transitioned into: {:?}", symbol);
-                    });
-                    current = f(&mut wrapper);
-                    continue;
-                },
-                Outcomes::StartResponse(f) => {
-                    backtrace::resolve(f as *mut std::os::raw::c_void, |symbol| {
-                        println!("transitioned into: {:?}", symbol);
-                    });
-                    //println!("received StartResponse!");
-
-                    let (sink, body) = Body::pair();
-                    // TODO: Fail properly
-                    let response = wrapper.response.builder().body(body).unwrap();
-                    wrapper.response = DelayedResponse::Started(sink);
-                    // TODO: Fail properly
-                    sender.take().unwrap().send(response);
-                    //println!("response started: {:?}", self);
-                    current = f(&mut wrapper);
-                },
-                Outcomes::Done => {
-                    //println!("received StartResponse!");
+/// Drives a resource through the decision graph.
+struct Execution<R, B> where R: Resource<B>, B: Body {
+    wrapper: ResourceWrapper<R, B>,
+    current: Option<Outcomes<R, B>>,
+    sender: Option<Sender<http::Response<B>>>,
+    trace: Trace,
+}
+
+impl<R, B> Execution<R, B> where R: Resource<B>, B: Body {
+    fn send(&mut self, mut response: http::Response<B>) {
+        response.extensions_mut().insert(self.trace.clone());
 
-                    // TODO: Fail properly
-                    let response = wrapper.response.builder().body("".into()).unwrap();
+        // The receiver is gone if the client disconnected, nothing to do then.
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send(response);
+        }
+    }
+}
 
-                    // TODO: Fail properly
-                    sender.take().unwrap().send(response);
-                    break;
+impl<R, B> Future for Execution<R, B> where R: Resource<B>, B: Body {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            // Handlers commit the response by writing to it.
+            if let Some(head) = self.wrapper.response.take_head() {
+                self.send(head);
+            }
+
+            let current = match self.current.take() {
+                Some(current) => current,
+                None => return Ok(Async::Ready(())),
+            };
+
+            match current {
+                Outcomes::Next(node) => {
+                    trace!("transitioned into {}", node.name);
+                    self.trace.0.push(node.name);
+                    self.current = Some((node.decide)(&mut self.wrapper));
                 },
-                outcome @ Outcomes::InputHandler(_) | outcome @ Outcomes::OutputHandler(_) => {
-                    //println!("handling!");
-                    let (sink, body) = Body::pair();
-                    // TODO: Fail properly
-                    let response = wrapper.response.builder().body(body).unwrap();
-                    wrapper.response = DelayedResponse::Started(sink);
-                    // TODO: Fail properly
-                    sender.take().unwrap().send(response);
-                    //println!("response started: {:?}", self);
-                    match outcome {
-                        Outcomes::InputHandler(handler) => {
-                            handler(&mut wrapper.resource, &mut wrapper.request, &mut wrapper.response);
+                Outcomes::Pending(mut future) => {
+                    match future.poll() {
+                        Ok(Async::Ready(transition)) => {
+                            self.current = Some(transition(&mut self.wrapper));
+                        },
+                        Ok(Async::NotReady) => {
+                            self.current = Some(Outcomes::Pending(future));
+                            return Ok(Async::NotReady);
                         },
-                        Outcomes::OutputHandler(handler) => {
-                            handler(&mut wrapper.resource, &mut wrapper.response);
+                        // Most likely from reading the body past the limit.
+                        Err(_) if self.wrapper.upload.as_ref().is_some_and(Upload::is_exceeded) => {
+                            self.current = Some(self.wrapper.too_large());
                         },
-                        _ => { unreachable!() }
+                        Err(Error::BadRequest(reason)) => {
+                            debug!("bad request: {}", reason);
+
+                            match self.wrapper.response.respond(Some(http::StatusCode::BAD_REQUEST), B::from(reason.into())) {
+                                Some(response) => self.send(response),
+                                None => {
+                                    warn!("cannot reject the request, the response was already committed");
+                                    self.current = Some(Outcomes::Done);
+                                },
+                            }
+                        },
+                        Err(e) => {
+                            warn!("deferred resource callback failed: {:?}", e);
+
+                            self.current = if !self.wrapper.response.is_committed() {
+                                Some(Outcomes::Halt(http::StatusCode::INTERNAL_SERVER_ERROR))
+                            } else {
+                                Some(Outcomes::Done)
+                            };
+                        }
+                    }
+                },
+                Outcomes::Done => {
+                    // The body is still needed only if the resource handed it on.
+                    *self.wrapper.context.body_mut() = B::empty();
+                    if let Some(upload) = self.wrapper.upload.take() {
+                        if upload.is_exceeded() {
+                            // Too large only if the resource read that far.
+                            let read = upload.outcome().map(|read| Box::new(move |wrapper: &mut ResourceWrapper<R, B>| {
+                                if read { wrapper.too_large() } else { Outcomes::Done }
+                            }) as Transition<R, B>);
+
+                            self.current = Some(Outcomes::Pending(Box::new(read)));
+                            continue;
+                        }
                     }
 
-                    wrapper.response.response_body().poll_complete();
-                    break;
+                    if let Some(response) = self.wrapper.response.respond(None, B::empty()) {
+                        self.send(response);
+                    } else {
+                        match self.wrapper.response.poll_flush() {
+                            Ok(Async::NotReady) => {
+                                // Keep the body open until the client took all chunks.
+                                self.current = Some(Outcomes::Done);
+                                return Ok(Async::NotReady);
+                            },
+                            Ok(Async::Ready(())) => {},
+                            Err(e) => warn!("response body not completed: {}", e),
+                        }
+
+                        self.wrapper.response.close();
+                    }
+                },
+                Outcomes::InputHandler(handler) => {
+                    let wrapper = &mut self.wrapper;
+                    let deferred = handler(&mut wrapper.resource, &mut wrapper.context, &mut wrapper.response);
+                    self.current = Some(wrapper.decide(deferred, |_, ()| Outcomes::Done));
+                },
+                Outcomes::OutputHandler(handler) => {
+                    let wrapper = &mut self.wrapper;
+                    let deferred = handler(&mut wrapper.resource, &wrapper.context, &mut wrapper.response);
+                    self.current = Some(wrapper.decide(deferred, ResourceWrapper::write_entity));
                 },
                 Outcomes::Halt(s) => {
-                    let response: http::Response<Body> = wrapper.response.builder().status(s).body(s.canonical_reason().unwrap().into()).unwrap();
-                    // TODO: Fail properly
-                    sender.take().unwrap().send(response);
-                    break;
+                    // These responses must not have a body.
+                    let body = if s.is_informational() || s == http::StatusCode::NO_CONTENT || s == http::StatusCode::NOT_MODIFIED {
+                        B::empty()
+                    } else {
+                        B::from(s.canonical_reason().unwrap().into())
+                    };
+
+                    if let Some(response) = self.wrapper.response.respond(Some(s), body) {
+                        self.send(response);
+                    } else {
+                        warn!("cannot respond with {}, the response was already committed", s);
+                        self.current = Some(Outcomes::Done);
+                    }
                 }
             };
         }
     }
 }
 
+/// Resolves `path` against `base`, the way a client resolves a link.
+fn resolve(base: Option<http::Uri>, path: &str) -> String {
+    let base = match base {
+        Some(ref base) if !path.contains("://") => base,
+        _ => return path.to_string(),
+    };
+
+    let origin = match (base.scheme_part(), base.authority_part()) {
+        (Some(scheme), Some(authority)) => format!("{}://{}", scheme, authority),
+        _ => return path.to_string(),
+    };
+
+    if path.starts_with('/') {
+        format!("{}{}", origin, path)
+    } else {
+        let dir = &base.path()[..base.path().rfind('/').map_or(0, |i| i + 1)];
+        format!("{}{}{}", origin, if dir.is_empty() { "/" } else { dir }, path)
+    }
+}
+
 pub struct Metadata {
     content_type: Option<mime::Mime>,
+    /// Whether the body may be narrowed to the requested byte ranges.
+    ranges: bool,
 }
 
-pub struct ResourceWrapper<R>
-    where R: Resource {
+pub struct ResourceWrapper<R, B>
+    where R: Resource<B>, B: Body {
     resource: R,
-    pub request: http::Request<Body>,
-    response: DelayedResponse,
-    metadata: Metadata
+    pub context: Context<B>,
+    response: DelayedResponse<B>,
+    metadata: Metadata,
+    /// A request body without a declared length, limited as it arrives.
+    upload: Option<Upload>,
+    pool: Arc<futures_cpupool::CpuPool>,
 }
 
-impl<R> ResourceWrapper<R>
-    where R: Resource
+impl<R, B> ResourceWrapper<R, B>
+    where R: Resource<B>, B: Body
 {
-    fn new(resource: R, request: http::Request<Body>) -> Self {
+    fn new(resource: R, request: http::Request<B>, pool: Arc<futures_cpupool::CpuPool>) -> Self {
         let delay = DelayedResponse::new();
-        let metadata = Metadata { content_type: None };
+        let metadata = Metadata { content_type: None, ranges: false };
 
-        ResourceWrapper { resource: resource, request: request, response: delay, metadata: metadata }
+        ResourceWrapper { resource, context: Context::new(request), response: delay, metadata, upload: None, pool }
+    }
+
+    /// The largest body `valid_entity_length` accepts, `None` if any.
+    ///
+    /// Bodies without a declared length are limited while they stream,
+    /// apart from the flow, so the limit is bisected up front instead of
+    /// checked as chunks arrive.
+    fn entity_limit(&mut self) -> Option<u64> {
+        if self.resource.valid_entity_length(&self.context, u64::MAX) {
+            return None;
+        }
+
+        let (mut valid, mut invalid) = (0, u64::MAX);
+        while invalid - valid > 1 {
+            let middle = valid + (invalid - valid) / 2;
+
+            if self.resource.valid_entity_length(&self.context, middle) {
+                valid = middle;
+            } else {
+                invalid = middle;
+            }
+        }
+
+        Some(valid)
+    }
+
+    /// Answers an upload that grew larger than the resource accepts.
+    fn too_large(&self) -> Outcomes<R, B> {
+        if !self.response.is_committed() {
+            Outcomes::Halt(http::StatusCode::PAYLOAD_TOO_LARGE)
+        } else {
+            warn!("request body too large, but the response was already committed");
+            Outcomes::Done
+        }
+    }
+
+    /// Continues with `next` once the callback result is available.
+    fn decide<T>(&mut self, deferred: Deferred<R, T>, next: fn(&mut Self, T) -> Outcomes<R, B>) -> Outcomes<R, B>
+        where T: 'static
+    {
+        match deferred {
+            Deferred::Ready(value) => next(self, value),
+            Deferred::Pending(future) => {
+                Outcomes::Pending(Box::new(future.map(move |apply| {
+                    Box::new(move |wrapper: &mut Self| {
+                        let value = apply(&mut wrapper.resource);
+                        next(wrapper, value)
+                    }) as Transition<R, B>
+                })))
+            }
+        }
     }
 }
 
-impl<R> ResourceWrapper<R> where R: Resource {
-    fn b13(&mut self) -> Outcomes<R> {
-        if self.resource.service_available() {
-            Outcomes::Next(Self::b12)
+impl<R, B> ResourceWrapper<R, B> where R: Resource<B>, B: Body {
+    fn b13(&mut self) -> Outcomes<R, B> {
+        if self.resource.service_available(&self.context) {
+            Outcomes::Next(node!(b12))
         } else {
             Outcomes::Halt(http::StatusCode::SERVICE_UNAVAILABLE)
         }
     }
 
-    fn b12(&mut self) -> Outcomes<R> {
-        if self.resource.known_methods().contains(self.request.method()) {
-            Outcomes::Next(Self::b11)
+    fn b12(&mut self) -> Outcomes<R, B> {
+        if self.resource.known_methods(&self.context).contains(self.context.method()) {
+            Outcomes::Next(node!(b11))
         } else {
             Outcomes::Halt(http::StatusCode::NOT_IMPLEMENTED)
         }
     }
 
-    fn b11(&mut self) -> Outcomes<R> {
-        if self.resource.uri_too_long(self.request.uri()) {
+    fn b11(&mut self) -> Outcomes<R, B> {
+        if self.resource.uri_too_long(&self.context) {
             Outcomes::Halt(http::StatusCode::URI_TOO_LONG)
         } else {
-            Outcomes::Next(Self::b10)
+            Outcomes::Next(node!(b10))
         }
     }
 
-    fn b10(&mut self) -> Outcomes<R> {
-        let builder = self.response.builder();
-
-        if self.resource.allowed_methods().contains(self.request.method()) {
-            Outcomes::Next(Self::b9)
+    fn b10(&mut self) -> Outcomes<R, B> {
+        if self.resource.allowed_methods(&self.context).contains(self.context.method()) {
+            Outcomes::Next(node!(b9))
         } else {
-            let header = http::header::HeaderValue::from_str(&self.resource.allowed_methods().iter().map(|m| m.as_str()).collect::<Vec<_>>().join(", ")).unwrap();
+            let header = self.resource.allowed_methods(&self.context).iter().map(|m| m.as_str()).collect::<Vec<_>>().join(", ");
 
-            builder.header(http::header::ACCEPT, header);
+            self.set_header(http::header::ALLOW, &*header);
 
             Outcomes::Halt(http::StatusCode::METHOD_NOT_ALLOWED)
         }
     }
 
-    fn b9(&mut self) -> Outcomes<R> {
-        if let Some(result) = self.resource.validate_content_checksum() {
+    fn b9(&mut self) -> Outcomes<R, B> {
+        if let Some(result) = self.resource.validate_content_checksum(&self.context) {
             if result {
-                if self.resource.malformed_request() {
+                if self.resource.malformed_request(&self.context) {
                     Outcomes::Halt(http::StatusCode::BAD_REQUEST)
                 } else {
-                    Outcomes::Next(Self::b8)
+                    Outcomes::Next(node!(b8))
                 }
             } else {
                 //resource.response_mut().body("Content-MD5 header does not match request body.")
@@ -258,10 +463,10 @@ impl<R> ResourceWrapper<R> where R: Resource {
             // TODO: MD5 validation of body
             let valid = true;
             if valid {
-                if self.resource.malformed_request() {
+                if self.resource.malformed_request(&self.context) {
                     Outcomes::Halt(http::StatusCode::BAD_REQUEST)
                 } else {
-                    Outcomes::Next(Self::b8)
+                    Outcomes::Next(node!(b8))
                 }
             } else {
                 Outcomes::Halt(http::StatusCode::BAD_REQUEST)
@@ -269,63 +474,100 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn b8(&mut self) -> Outcomes<R> {
-        let auth_header = self.request.headers().get(http::header::AUTHORIZATION);
+    fn b8(&mut self) -> Outcomes<R, B> {
+        let authorized = self.resource.is_authorized(&self.context);
 
         // TODO: Implement full is_authorized protocol
-        if self.resource.is_authorized(auth_header) {
-            Outcomes::Next(Self::b7)
-        } else {
-            Outcomes::Halt(http::StatusCode::UNAUTHORIZED)
-        }
+        self.decide(authorized, |_, authorized| {
+            if authorized {
+                Outcomes::Next(node!(b7))
+            } else {
+                Outcomes::Halt(http::StatusCode::UNAUTHORIZED)
+            }
+        })
     }
 
-    fn b7(&mut self) -> Outcomes<R> {
-        if self.resource.forbidden() {
-            Outcomes::Halt(http::StatusCode::FORBIDDEN)
-        } else {
-            Outcomes::Next(Self::b6)
+    fn b7(&mut self) -> Outcomes<R, B> {
+        let forbidden = self.resource.forbidden(&self.context);
+
+        self.decide(forbidden, |this, forbidden| {
+            if forbidden {
+                Outcomes::Halt(http::StatusCode::FORBIDDEN)
+            } else if this.wants_websocket() {
+                Outcomes::Next(node!(b7b))
+            } else {
+                Outcomes::Next(node!(b6))
+            }
+        })
+    }
+
+    /// Upgrades to a WebSocket if the resource accepts the handshake.
+    fn b7b(&mut self) -> Outcomes<R, B> {
+        let handler = match self.resource.accept_websocket(&self.context) {
+            Some(handler) => handler,
+            None => return Outcomes::Next(node!(b6)),
+        };
+
+        let version = self.context.headers().get(http::header::SEC_WEBSOCKET_VERSION);
+        if version.and_then(|version| version.to_str().ok()).map(str::trim) != Some("13") {
+            self.set_header(http::header::SEC_WEBSOCKET_VERSION, "13");
+            return Outcomes::Halt(http::StatusCode::UPGRADE_REQUIRED);
         }
+
+        let key = match self.context.headers().get(http::header::SEC_WEBSOCKET_KEY).and_then(|key| key.to_str().ok()) {
+            Some(key) if websocket::is_valid_key(key) => websocket::accept_key(key),
+            _ => return Outcomes::Halt(http::StatusCode::BAD_REQUEST),
+        };
+
+        self.set_header(http::header::UPGRADE, "websocket");
+        self.set_header(http::header::CONNECTION, "Upgrade");
+        self.set_header(http::header::SEC_WEBSOCKET_ACCEPT, &*key);
+
+        if let Err(e) = self.response.extension(websocket::Upgrade::new(handler)) {
+            warn!("cannot upgrade to a WebSocket: {}", e);
+        }
+
+        Outcomes::Halt(http::StatusCode::SWITCHING_PROTOCOLS)
     }
 
-    fn b6(&mut self) -> Outcomes<R> {
-        let headers = self.request.headers().iter()
-            .filter(|&(name, _)| name.as_str().starts_with("CONTENT-"));
+    fn b6(&mut self) -> Outcomes<R, B> {
+        let headers = self.context.headers().iter()
+            .filter(|&(name, _)| name.as_str().starts_with("content-"));
 
-        if self.resource.valid_content_headers(headers) {
-            Outcomes::Next(Self::b5)
+        if self.resource.valid_content_headers(&self.context, headers) {
+            Outcomes::Next(node!(b5))
         } else {
             Outcomes::Halt(http::StatusCode::NOT_IMPLEMENTED)
         }
     }
 
-    fn b5(&mut self) -> Outcomes<R> {
-        let content_type = self.request.headers().get("Content-Type");
+    fn b5(&mut self) -> Outcomes<R, B> {
+        let content_type = self.context.headers().get("Content-Type");
 
         // Default Content-Type is application/octet-stream. https://www.w3.org/Protocols/rfc2616/rfc2616-sec7.html#sec7.2.1
         let default = http::header::HeaderValue::from_str("application/octet-stream").unwrap();
         let ct = content_type.unwrap_or(&default);
 
-        if self.resource.known_content_type(ct) {
-            Outcomes::Next(Self::b4)
+        if self.resource.known_content_type(&self.context, ct) {
+            Outcomes::Next(node!(b4))
         } else {
             Outcomes::Halt(http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
         }
     }
 
-    fn b4(&mut self) -> Outcomes<R> {
+    fn b4(&mut self) -> Outcomes<R, B> {
         use http::method::Method;
 
-        let content_length = self.request.headers().get("Content-Length");
-        let transfer_encoding = self.request.headers().get("Transfer-Encoding");
+        let content_length = self.context.headers().get("Content-Length");
+        let transfer_encoding = self.context.headers().get("Transfer-Encoding");
 
-        match *self.request.method() {
+        match *self.context.method() {
             Method::GET | Method::HEAD | Method::OPTIONS => {
                 if content_length.is_some() {
                     // TODO: Communicate _why_ it is a BAD_REQUEST
                     return Outcomes::Halt(http::StatusCode::BAD_REQUEST)
                 } else {
-                    return Outcomes::Next(Self::b3)
+                    return Outcomes::Next(node!(b3))
                 }
             },
             _ => {}
@@ -336,17 +578,23 @@ impl<R> ResourceWrapper<R> where R: Resource {
             return Outcomes::Halt(http::StatusCode::BAD_REQUEST)
         }
 
-        if let Some(cl) = content_length {
-            if let Ok(stringed) = cl.to_str() {
-                if let Ok(parsed) = stringed.parse() {
-                    if self.resource.valid_entity_length(parsed) {
-                        Outcomes::Next(Self::b3)
-                    } else {
-                        Outcomes::Halt(http::StatusCode::PAYLOAD_TOO_LARGE)
-                    }
+        let cl = match content_length {
+            Some(cl) => cl,
+            None => {
+                // Chunked, counted while it streams in instead.
+                let limit = self.entity_limit();
+                self.upload = Some(Upload::start(self.context.body_mut(), limit, &self.pool));
+
+                return Outcomes::Next(node!(b3));
+            },
+        };
+
+        if let Ok(stringed) = cl.to_str() {
+            if let Ok(parsed) = stringed.parse() {
+                if self.resource.valid_entity_length(&self.context, parsed) {
+                    Outcomes::Next(node!(b3))
                 } else {
-                    // TODO: Communicate _why_ it is a BAD_REQUEST
-                    Outcomes::Halt(http::StatusCode::BAD_REQUEST)
+                    Outcomes::Halt(http::StatusCode::PAYLOAD_TOO_LARGE)
                 }
             } else {
                 // TODO: Communicate _why_ it is a BAD_REQUEST
@@ -358,42 +606,49 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn b3(&mut self) -> Outcomes<R> {
-        let method = self.request.method();
+    fn b3(&mut self) -> Outcomes<R, B> {
+        let method = self.context.method();
 
         if *method == http::method::Method::OPTIONS {
             Outcomes::Halt(http::StatusCode::OK)
         } else {
-            Outcomes::Next(Self::c3)
+            // Past the checks that can reject a request before its body
+            // arrived, so a client waiting on `Expect: 100-continue` can send it.
+            if let Some(expect) = self.context.request().extensions().get::<server::Continue>() {
+                expect.send();
+            }
+
+            Outcomes::Next(node!(c3))
         }
     }
 
-    fn c3(&mut self) -> Outcomes<R> {
-        let accept = self.request.headers().get(http::header::ACCEPT);
+    fn c3(&mut self) -> Outcomes<R, B> {
+        let accept = self.context.headers().get(http::header::ACCEPT);
 
         let next = if accept.is_some() {
-            Self::c4
+            node!(c4)
         } else {
-            // TODO: Proper error handling
-            self.metadata.content_type = Some(self.resource.content_types_provided().first().unwrap().0.clone());
+            // Resources providing nothing fail in o18, if they get there.
+            self.metadata.content_type = self.resource.content_types_provided(&self.context).first()
+                .map(|ProvidedPair(mime, _)| mime.clone());
 
-            Self::d4
+            node!(d4)
         };
 
         Outcomes::Next(next)
     }
 
-    fn c4(&mut self) -> Outcomes<R> {
-        let accept = self.request.headers().get(http::header::ACCEPT);
+    fn c4(&mut self) -> Outcomes<R, B> {
+        let accept = self.context.headers().get(http::header::ACCEPT);
 
         if let Some(header) = accept {
-            let chosen_type = ::conneg::choose_mediatype(self.resource.content_types_provided(), header);
+            let chosen_type = ::conneg::choose_mediatype(self.resource.content_types_provided(&self.context), header);
 
             match chosen_type {
                 Ok(mime) => {
                     self.metadata.content_type = Some(mime.clone());
 
-                    Outcomes::Next(Self::d4)
+                    Outcomes::Next(node!(d4))
                 },
                 Err(::conneg::Error::NotProvided) => Outcomes::Halt(http::StatusCode::NOT_ACCEPTABLE),
                 // TODO: Communicate _why_ it is a BAD_REQUEST
@@ -404,25 +659,26 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn d4(&mut self) -> Outcomes<R> {
-        let accept_language = self.request.headers().get(http::header::ACCEPT_LANGUAGE);
+    fn d4(&mut self) -> Outcomes<R, B> {
+        let accept_language = self.context.headers().get(http::header::ACCEPT_LANGUAGE);
 
         let next = if accept_language.is_some() {
-            Self::d5
+            node!(d5)
         } else {
-            Self::e5
+            node!(e5)
         };
 
         Outcomes::Next(next)
     }
 
-    fn d5(&mut self) -> Outcomes<R> {
-        let accept_language = self.request.headers().get(http::header::ACCEPT_LANGUAGE);
+    fn d5(&mut self) -> Outcomes<R, B> {
+        let accept_language = self.context.headers().get(http::header::ACCEPT_LANGUAGE);
 
         if let Some(header) = accept_language {
             // TODO: this algorithm is too simple
-            if self.resource.languages_provided().contains(&header.to_str().unwrap()) {
-                Outcomes::Next(Self::e5)
+            let provided = self.resource.languages_provided(&self.context);
+            if provided.is_empty() || provided.contains(header) {
+                Outcomes::Next(node!(e5))
             } else {
                 Outcomes::Halt(http::StatusCode::NOT_ACCEPTABLE)
             }
@@ -431,25 +687,26 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn e5(&mut self) -> Outcomes<R> {
-        let accept_charset = self.request.headers().get(http::header::ACCEPT_CHARSET);
+    fn e5(&mut self) -> Outcomes<R, B> {
+        let accept_charset = self.context.headers().get(http::header::ACCEPT_CHARSET);
 
         let next = if accept_charset.is_some() {
-            Self::e6
+            node!(e6)
         } else {
-            Self::f6
+            node!(f6)
         };
 
         Outcomes::Next(next)
     }
 
-    fn e6(&mut self) -> Outcomes<R> {
-        let accept_charset = self.request.headers().get(http::header::ACCEPT_CHARSET);
+    fn e6(&mut self) -> Outcomes<R, B> {
+        let accept_charset = self.context.headers().get(http::header::ACCEPT_CHARSET);
 
         if let Some(header) = accept_charset {
             // TODO: this algorithm is too simple
-            if self.resource.charsets_provided().contains(header) {
-                Outcomes::Next(Self::g7)
+            let provided = self.resource.charsets_provided(&self.context);
+            if provided.is_empty() || provided.contains(header) {
+                Outcomes::Next(node!(f6))
             } else {
                 Outcomes::Halt(http::StatusCode::NOT_ACCEPTABLE)
             }
@@ -458,64 +715,69 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn f6(&mut self) -> Outcomes<R> {
-        let accept_charset = self.request.headers().get(http::header::ACCEPT_CHARSET);
+    fn f6(&mut self) -> Outcomes<R, B> {
+        let accept_encoding = self.context.headers().get(http::header::ACCEPT_ENCODING);
 
-        let next = if accept_charset.is_some() {
-            Self::f7
+        let next = if accept_encoding.is_some() {
+            node!(f7)
         } else {
-            Self::g7
+            node!(g7)
         };
 
         Outcomes::Next(next)
     }
 
 
-    fn f7(&mut self) -> Outcomes<R> {
-        let accept_encoding = self.request.headers().get(http::header::ACCEPT_ENCODING);
+    fn f7(&mut self) -> Outcomes<R, B> {
+        let accept_encoding = self.context.headers().get(http::header::ACCEPT_ENCODING);
 
-        if let Some(_header) = accept_encoding {
-            // TODO: this algorithm is too simple
-            if true {
-                Outcomes::Next(Self::g7)
-            } else {
-                Outcomes::Halt(http::StatusCode::NOT_ACCEPTABLE)
+        if let Some(header) = accept_encoding {
+            let provided = self.resource.encodings_provided(&self.context).iter().map(|&(coding, _)| coding).collect::<Vec<_>>();
+
+            match ::conneg::choose_encoding(&provided, header) {
+                Ok(_) => Outcomes::Next(node!(g7)),
+                Err(::conneg::Error::NotProvided) => Outcomes::Halt(http::StatusCode::NOT_ACCEPTABLE),
+                Err(::conneg::Error::ParseError) => Outcomes::Halt(http::StatusCode::BAD_REQUEST),
             }
         } else {
             unreachable!()
         }
     }
 
-    fn g7(&mut self) -> Outcomes<R> {
-        let next = if self.resource.resource_exists() {
-            Self::g8
-        } else {
-            unimplemented!() //Self::h7
-        };
+    fn g7(&mut self) -> Outcomes<R, B> {
+        let exists = self.resource.resource_exists(&self.context);
 
-        Outcomes::Next(next)
+        self.decide(exists, |_, exists| {
+            let next = if exists {
+                node!(g8)
+            } else {
+                node!(h7)
+            };
+
+            Outcomes::Next(next)
+        })
     }
 
-    fn g8(&mut self) -> Outcomes<R> {
-        let if_match = self.request.headers().get(http::header::IF_MATCH);
+    fn g8(&mut self) -> Outcomes<R, B> {
+        let if_match = self.context.headers().get(http::header::IF_MATCH);
 
         let next = if let Some(_header) = if_match {
-            Self::g9
+            node!(g9)
         } else {
-            Self::h10
+            node!(h10)
         };
 
         Outcomes::Next(next)
     }
 
-    fn g9(&mut self) -> Outcomes<R> {
-        let if_match = self.request.headers().get(http::header::IF_MATCH);
+    fn g9(&mut self) -> Outcomes<R, B> {
+        let if_match = self.context.headers().get(http::header::IF_MATCH);
 
         if let Some(header) = if_match {
-            let next = if header.to_str().unwrap() == "*" {
-                Self::h10
+            let next = if is_wildcard(header) {
+                node!(h10)
             } else {
-                Self::g11
+                node!(g11)
             };
 
             Outcomes::Next(next)
@@ -524,14 +786,13 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn g11(&mut self) -> Outcomes<R> {
-        let if_match = self.request.headers().get(http::header::IF_MATCH);
+    fn g11(&mut self) -> Outcomes<R, B> {
+        let etag = self.resource.generate_etag(&self.context);
+        let if_match = self.context.headers().get(http::header::IF_MATCH);
 
-        if let Some(_header) = if_match {
-            //TODO: Implement correctly
-            let etag_in_if_match = true;
-            if etag_in_if_match {
-                Outcomes::Next(Self::h10)
+        if let Some(header) = if_match {
+            if etag.is_some_and(|etag| etag_matches(header, &etag, false)) {
+                Outcomes::Next(node!(h10))
             } else {
                 Outcomes::Halt(http::StatusCode::PRECONDITION_FAILED)
             }
@@ -540,107 +801,556 @@ impl<R> ResourceWrapper<R> where R: Resource {
         }
     }
 
-    fn h10(&mut self) -> Outcomes<R> {
-        // TODO: we currently just skip through
-        Outcomes::Next(Self::m16)
+    fn h7(&mut self) -> Outcomes<R, B> {
+        let if_match = self.context.headers().get(http::header::IF_MATCH);
+
+        if if_match.is_some_and(is_wildcard) {
+            Outcomes::Halt(http::StatusCode::PRECONDITION_FAILED)
+        } else {
+            Outcomes::Next(node!(i7))
+        }
     }
 
-    // TODO: CONDITION HANDLING
+    fn h10(&mut self) -> Outcomes<R, B> {
+        let next = if self.context.headers().contains_key(http::header::IF_UNMODIFIED_SINCE) {
+            node!(h11)
+        } else {
+            node!(i12)
+        };
 
-    fn m16(&mut self) -> Outcomes<R> {
-        let next = if http::method::Method::DELETE == *self.request.method() {
-            unimplemented!() //Self::m20
+        Outcomes::Next(next)
+    }
+
+    fn h11(&mut self) -> Outcomes<R, B> {
+        let next = if self.header_date(http::header::IF_UNMODIFIED_SINCE).is_some() {
+            node!(h12)
         } else {
-            Self::n16
+            node!(i12)
         };
 
         Outcomes::Next(next)
     }
-//    base_uri = resource.base_uri || request.base_uri
-//    new_uri = URI.join(base_uri.to_s, uri)
-//    request.disp_path = new_uri.path
-//    response.headers[LOCATION] = new_uri.to_s
-//    result = accept_helper
-    fn n11(&mut self) -> Outcomes<R> {
-        if self.resource.post_is_create() {
-            let mime: mime::Mime = {
-                let content_type = self.request.headers().get("Content-Type");
-                if let Some(ct) = content_type {
-                    ct.to_str().unwrap().parse().unwrap()
-                } else {
-                    // TODO handle parse errors correctly
-                    unimplemented!()
-                }
-            };
 
-            let pair = self.resource.content_types_accepted().iter().find(|&&(ref m, _)| *m == mime);
+    fn h12(&mut self) -> Outcomes<R, B> {
+        let since = self.header_date(http::header::IF_UNMODIFIED_SINCE);
+        let last_modified = self.resource.last_modified(&self.context).map(whole_seconds);
 
-            if let Some(&(_, handler)) = pair {
-                handler(&mut self.resource, &mut self.request, &mut self.response);
+        match (last_modified, since) {
+            (Some(last_modified), Some(since)) if last_modified > since => {
+                Outcomes::Halt(http::StatusCode::PRECONDITION_FAILED)
+            },
+            _ => Outcomes::Next(node!(i12)),
+        }
+    }
 
-                // TODO: rewriting response for better support of this protocol
-                if !self.response.started() {
-                    self.response.builder()
-                        .status(http::status::StatusCode::SEE_OTHER)
-                        .header(http::header::LOCATION, &*self.resource.create_path());
-                }
+    fn i4(&mut self) -> Outcomes<R, B> {
+        if let Some(uri) = self.resource.moved_permanently(&self.context) {
+            self.redirect(http::StatusCode::MOVED_PERMANENTLY, &uri)
+        } else {
+            Outcomes::Next(node!(p3))
+        }
+    }
+
+    fn i7(&mut self) -> Outcomes<R, B> {
+        let next = if http::method::Method::PUT == *self.context.method() {
+            node!(i4)
+        } else {
+            node!(k7)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn i12(&mut self) -> Outcomes<R, B> {
+        let next = if self.context.headers().contains_key(http::header::IF_NONE_MATCH) {
+            node!(i13)
+        } else {
+            node!(l13)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn i13(&mut self) -> Outcomes<R, B> {
+        let if_none_match = self.context.headers().get(http::header::IF_NONE_MATCH);
 
-                return Outcomes::Done;
+        let next = if if_none_match.is_some_and(is_wildcard) {
+            node!(j18)
+        } else {
+            node!(k13)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn j18(&mut self) -> Outcomes<R, B> {
+        use http::method::Method;
+
+        match *self.context.method() {
+            Method::GET | Method::HEAD => self.not_modified(),
+            _ => Outcomes::Halt(http::StatusCode::PRECONDITION_FAILED),
+        }
+    }
+
+    fn k5(&mut self) -> Outcomes<R, B> {
+        if let Some(uri) = self.resource.moved_permanently(&self.context) {
+            self.redirect(http::StatusCode::MOVED_PERMANENTLY, &uri)
+        } else {
+            Outcomes::Next(node!(l5))
+        }
+    }
+
+    fn k7(&mut self) -> Outcomes<R, B> {
+        let next = if self.resource.previously_existed(&self.context) {
+            node!(k5)
+        } else {
+            node!(l7)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn k13(&mut self) -> Outcomes<R, B> {
+        let etag = self.resource.generate_etag(&self.context);
+        let if_none_match = self.context.headers().get(http::header::IF_NONE_MATCH);
+
+        let next = match (etag, if_none_match) {
+            (Some(ref etag), Some(header)) if etag_matches(header, etag, true) => node!(j18),
+            _ => node!(l13),
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn l5(&mut self) -> Outcomes<R, B> {
+        if let Some(uri) = self.resource.moved_temporarily(&self.context) {
+            self.redirect(http::StatusCode::TEMPORARY_REDIRECT, &uri)
+        } else {
+            Outcomes::Next(node!(m5))
+        }
+    }
+
+    fn l7(&mut self) -> Outcomes<R, B> {
+        if http::method::Method::POST == *self.context.method() {
+            Outcomes::Next(node!(m7))
+        } else {
+            Outcomes::Halt(http::StatusCode::NOT_FOUND)
+        }
+    }
+
+    fn l13(&mut self) -> Outcomes<R, B> {
+        let next = if self.context.headers().contains_key(http::header::IF_MODIFIED_SINCE) {
+            node!(l14)
+        } else {
+            node!(m16)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn l14(&mut self) -> Outcomes<R, B> {
+        let next = if self.header_date(http::header::IF_MODIFIED_SINCE).is_some() {
+            node!(l15)
+        } else {
+            node!(m16)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn l15(&mut self) -> Outcomes<R, B> {
+        let since = self.header_date(http::header::IF_MODIFIED_SINCE);
+
+        let next = match since {
+            Some(since) if since > std::time::SystemTime::now() => node!(m16),
+            _ => node!(l17),
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn l17(&mut self) -> Outcomes<R, B> {
+        let since = self.header_date(http::header::IF_MODIFIED_SINCE);
+        let last_modified = self.resource.last_modified(&self.context).map(whole_seconds);
+
+        match (last_modified, since) {
+            (Some(last_modified), Some(since)) if last_modified <= since => self.not_modified(),
+            _ => Outcomes::Next(node!(m16)),
+        }
+    }
+
+    fn m5(&mut self) -> Outcomes<R, B> {
+        if http::method::Method::POST == *self.context.method() {
+            Outcomes::Next(node!(n5))
+        } else {
+            Outcomes::Halt(http::StatusCode::GONE)
+        }
+    }
+
+    fn m7(&mut self) -> Outcomes<R, B> {
+        if self.resource.allow_missing_post(&self.context) {
+            Outcomes::Next(node!(n11))
+        } else {
+            Outcomes::Halt(http::StatusCode::NOT_FOUND)
+        }
+    }
+
+    fn m16(&mut self) -> Outcomes<R, B> {
+        let next = if http::method::Method::DELETE == *self.context.method() {
+            node!(m20)
+        } else {
+            node!(n16)
+        };
+
+        Outcomes::Next(next)
+    }
+
+    fn m20(&mut self) -> Outcomes<R, B> {
+        let deleted = self.resource.delete_resource(&self.context);
+
+        self.decide(deleted, |_, deleted| {
+            if deleted {
+                Outcomes::Next(node!(m20b))
             } else {
-                return Outcomes::Halt(http::status::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                Outcomes::Halt(http::StatusCode::INTERNAL_SERVER_ERROR)
             }
+        })
+    }
+
+    fn m20b(&mut self) -> Outcomes<R, B> {
+        if self.resource.delete_completed(&self.context) {
+            Outcomes::Next(node!(o20))
+        } else {
+            Outcomes::Halt(http::StatusCode::ACCEPTED)
+        }
+    }
+
+    fn n5(&mut self) -> Outcomes<R, B> {
+        if self.resource.allow_missing_post(&self.context) {
+            Outcomes::Next(node!(n11))
         } else {
-            self.resource.process_post(&mut self.response);
+            Outcomes::Halt(http::StatusCode::GONE)
         }
+    }
 
-        // TODO
-        // if self.response.redirect()
-        // Outcomes::Done
-        // } else {
-            Outcomes::Next(Self::p11)
+    fn n11(&mut self) -> Outcomes<R, B> {
+        if self.resource.post_is_create(&self.context) {
+            self.accept(|wrapper, ()| {
+                // Unlike webmachine, the path is created after accepting,
+                // so it can name what the handler read from the body.
+                if !wrapper.response.is_committed() {
+                    let path = wrapper.resource.create_path(&wrapper.context);
+                    let base_uri = wrapper.resource.base_uri(&wrapper.context)
+                        .or_else(|| wrapper.context.base_uri());
+                    let location = resolve(base_uri, &path);
+
+                    wrapper.set_header(http::header::LOCATION, &*location);
+                }
+
+                wrapper.see_other()
+            })
+        } else {
+            let processed = self.resource.process_post(&mut self.context, &mut self.response);
+
+            self.decide(processed, |wrapper, ()| wrapper.see_other())
+        }
     }
 
-    fn n16(&mut self) -> Outcomes<R> {
-        let next = if http::method::Method::POST == *self.request.method() {
-            Self::n11
+    fn n16(&mut self) -> Outcomes<R, B> {
+        let next = if http::method::Method::POST == *self.context.method() {
+            node!(n11)
         } else {
-            Self::o16
+            node!(o16)
         };
 
         Outcomes::Next(next)
     }
 
-    fn o16(&mut self) -> Outcomes<R> {
-        let next = if http::method::Method::PUT == *self.request.method() {
-            unimplemented!() //Self::o14
+    fn o14(&mut self) -> Outcomes<R, B> {
+        if self.resource.is_conflict(&self.context) {
+            Outcomes::Halt(http::StatusCode::CONFLICT)
         } else {
-            Self::o18
+            self.accept(|_, ()| Outcomes::Next(node!(p11)))
+        }
+    }
+
+    fn o16(&mut self) -> Outcomes<R, B> {
+        let next = if http::method::Method::PUT == *self.context.method() {
+            node!(o14)
+        } else {
+            node!(o18)
         };
 
         Outcomes::Next(next)
     }
 
-    fn o18(&mut self) -> Outcomes<R> {
-        self.response.builder().status(200);
+    fn o18(&mut self) -> Outcomes<R, B> {
+        self.validators();
+
+        let status = if self.resource.multiple_choices(&self.context) {
+            http::StatusCode::MULTIPLE_CHOICES
+        } else {
+            http::StatusCode::OK
+        };
+        self.set_status(status);
 
-        let content_type = self.metadata.content_type.as_ref();
+        if status == http::StatusCode::OK && self.resource.accept_ranges(&self.context) {
+            self.set_header(http::header::ACCEPT_RANGES, "bytes");
+            self.metadata.ranges = http::Method::GET == *self.context.method();
+        }
 
-        if let Some(mime) = content_type {
-            let pair = self.resource.content_types_provided().iter().find(|&&::resource::ProvidedPair(ref m, _)| m == mime);
+        let handler = self.metadata.content_type.as_ref().and_then(|mime| {
+            self.resource.content_types_provided(&self.context).iter()
+                .find(|&ProvidedPair(m, _)| m == mime)
+                .map(|&ProvidedPair(_, handler)| handler)
+        });
 
-            if let Some(&::resource::ProvidedPair(_, handler)) = pair {
-                Outcomes::OutputHandler(handler)
-            } else {
-                panic!("No handler for content type.")
+        if let Some(handler) = handler {
+            Outcomes::OutputHandler(handler)
+        } else {
+            warn!("no handler for content type {:?}", self.metadata.content_type);
+            Outcomes::Halt(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+
+    fn o20(&mut self) -> Outcomes<R, B> {
+        if self.response.is_committed() {
+            // The handler already responded with a body.
+            Outcomes::Done
+        } else {
+            Outcomes::Halt(http::StatusCode::NO_CONTENT)
+        }
+    }
+
+    fn p3(&mut self) -> Outcomes<R, B> {
+        if self.resource.is_conflict(&self.context) {
+            Outcomes::Halt(http::StatusCode::CONFLICT)
+        } else {
+            self.accept(|_, ()| Outcomes::Next(node!(p11)))
+        }
+    }
+
+    fn p11(&mut self) -> Outcomes<R, B> {
+        let location = self.response.headers().is_some_and(|headers| headers.contains_key(http::header::LOCATION));
+
+        if location {
+            Outcomes::Halt(http::StatusCode::CREATED)
+        } else {
+            Outcomes::Next(node!(o20))
+        }
+    }
+}
+
+impl<R, B> ResourceWrapper<R, B> where R: Resource<B>, B: Body {
+    /// Runs the accepted handler for the request's content type, then
+    /// continues with `next`.
+    fn accept(&mut self, next: fn(&mut Self, ()) -> Outcomes<R, B>) -> Outcomes<R, B> {
+        // Default Content-Type is application/octet-stream, as in b5.
+        let mime: mime::Mime = match self.context.headers().get(http::header::CONTENT_TYPE) {
+            Some(ct) => match ct.to_str().ok().and_then(|ct| ct.parse().ok()) {
+                Some(mime) => mime,
+                None => return Outcomes::Halt(http::StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            },
+            None => mime::APPLICATION_OCTET_STREAM,
+        };
+
+        let pair = self.resource.content_types_accepted(&self.context).iter()
+            .find(|&AcceptedPair(m, _)| m.type_() == mime.type_() && m.subtype() == mime.subtype());
+
+        if let Some(&AcceptedPair(_, handler)) = pair {
+            let accepted = handler(&mut self.resource, &mut self.context, &mut self.response);
+            self.decide(accepted, next)
+        } else {
+            Outcomes::Halt(http::StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        }
+    }
+
+    /// Answers 303 if the handler asked for a redirect, which needs a
+    /// `Location`, and continues with p11 otherwise.
+    fn see_other(&mut self) -> Outcomes<R, B> {
+        let location = match self.response.headers() {
+            Some(headers) if self.response.is_redirect() => headers.contains_key(http::header::LOCATION),
+            _ => return Outcomes::Next(node!(p11)),
+        };
+
+        if location {
+            Outcomes::Halt(http::StatusCode::SEE_OTHER)
+        } else {
+            warn!("cannot redirect without a Location");
+            Outcomes::Halt(http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+
+    /// Writes what an output handler returned, then finishes.
+    fn write_entity(&mut self, entity: Entity) -> Outcomes<R, B> {
+        let head = http::Method::HEAD == *self.context.method();
+
+        let entity = if self.metadata.ranges {
+            match self.select_ranges(entity) {
+                Ok(entity) => entity,
+                Err(outcome) => return outcome,
             }
         } else {
-            unimplemented!();
+            entity
+        };
+
+        // The negotiated type, unless the handler or a multipart range chose one.
+        let untyped = self.response.headers().is_some_and(|headers| !headers.contains_key(http::header::CONTENT_TYPE));
+        if let (true, Some(mime)) = (untyped, self.metadata.content_type.clone()) {
+            self.set_header(http::header::CONTENT_TYPE, mime.as_ref());
+        }
+
+        match entity.write_to(&mut self.response, head) {
+            Some(writing) => self.decide(Deferred::future(writing), |_, ()| Outcomes::Done),
+            None => Outcomes::Done,
+        }
+    }
+
+    /// Narrows `entity` to the byte ranges requested with `Range`, unless
+    /// `If-Range` doesn't match. Unsatisfiable ranges halt with 416.
+    fn select_ranges(&mut self, entity: Entity) -> Result<Entity, Outcomes<R, B>> {
+        let length = match entity.len() {
+            Some(length) if !self.response.is_committed() => length,
+            _ => return Ok(entity),
+        };
+
+        let ranges = match self.context.headers().get(http::header::RANGE) {
+            Some(header) if self.if_range() => range::parse(header, length),
+            _ => return Ok(entity),
+        };
+
+        match ranges {
+            Ok(ranges) => match entity.ranges(&ranges, self.metadata.content_type.as_ref()) {
+                Ok((entity, name, value)) => {
+                    self.set_status(http::StatusCode::PARTIAL_CONTENT);
+                    self.set_header(name, &*value);
+                    Ok(entity)
+                },
+                Err(entity) => Ok(entity),
+            },
+            Err(range::Error::Unsatisfiable) => {
+                self.set_header(http::header::CONTENT_RANGE, &*range::unsatisfied_range(length));
+                Err(Outcomes::Halt(http::StatusCode::RANGE_NOT_SATISFIABLE))
+            },
+            Err(range::Error::Invalid) => Ok(entity),
+        }
+    }
+
+    /// Whether `If-Range`, if sent, matches the `ETag` or `Last-Modified`
+    /// of the representation. Only strong validators match.
+    fn if_range(&self) -> bool {
+        let condition = match self.context.headers().get(http::header::IF_RANGE) {
+            Some(condition) => condition,
+            None => return true,
+        };
+        let headers = match self.response.headers() {
+            Some(headers) => headers,
+            None => return false,
+        };
+
+        let is_etag = condition.to_str().map(str::trim).is_ok_and(|value| value.starts_with('"') || value.starts_with("W/"));
+
+        if is_etag {
+            headers.get(http::header::ETAG).is_some_and(|etag| etag_matches(condition, etag, false))
+        } else {
+            let last_modified = headers.get(http::header::LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<::hyper::header::HttpDate>().ok())
+                .map(std::time::SystemTime::from);
+
+            last_modified.is_some() && last_modified == self.header_date(http::header::IF_RANGE)
+        }
+    }
+
+    /// Whether the request is a WebSocket handshake.
+    fn wants_websocket(&self) -> bool {
+        let headers = self.context.headers();
+
+        http::Method::GET == *self.context.method()
+            && self.context.request().version() == http::Version::HTTP_11
+            && has_token(headers, http::header::CONNECTION, "upgrade")
+            && has_token(headers, http::header::UPGRADE, "websocket")
+    }
+
+    /// Parses the HTTP date in header `name`.
+    fn header_date(&self, name: http::header::HeaderName) -> Option<std::time::SystemTime> {
+        let value = self.context.headers().get(name)?.to_str().ok()?;
+        let date: ::hyper::header::HttpDate = value.parse().ok()?;
+
+        Some(date.into())
+    }
+
+    /// Sets `ETag` and `Last-Modified` from the resource.
+    fn validators(&mut self) {
+        if let Some(etag) = self.resource.generate_etag(&self.context) {
+            self.set_header(http::header::ETAG, etag);
+        }
+
+        if let Some(last_modified) = self.resource.last_modified(&self.context) {
+            let date = ::hyper::header::HttpDate::from(last_modified).to_string();
+            self.set_header(http::header::LAST_MODIFIED, &*date);
         }
     }
 
-    fn p11(&mut self) -> Outcomes<R> {
-        unimplemented!()
+    fn not_modified(&mut self) -> Outcomes<R, B> {
+        self.validators();
+        Outcomes::Halt(http::StatusCode::NOT_MODIFIED)
+    }
+
+    fn redirect(&mut self, status: http::StatusCode, uri: &http::Uri) -> Outcomes<R, B> {
+        self.set_header(http::header::LOCATION, uri.to_string().as_str());
+        Outcomes::Halt(status)
     }
+
+    // Decisions run before handlers could commit the response, so only
+    // invalid values from the resource are rejected here.
+
+    fn set_status(&mut self, status: http::StatusCode) {
+        if let Err(e) = self.response.status(status) {
+            warn!("cannot set status {}: {}", status, e);
+        }
+    }
+
+    fn set_header<V>(&mut self, name: http::header::HeaderName, value: V)
+        where http::header::HeaderValue: http::HttpTryFrom<V>
+    {
+        if let Err(e) = self.response.header(name.clone(), value) {
+            warn!("cannot set header {}: {}", name, e);
+        }
+    }
+}
+
+/// Whether the comma separated header `name` lists `token`, ignoring case.
+fn has_token(headers: &http::HeaderMap, name: http::header::HeaderName, token: &str) -> bool {
+    headers.get_all(name).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
 }
 
+fn is_wildcard(header: &http::header::HeaderValue) -> bool {
+    header.to_str().ok().map(str::trim) == Some("*")
+}
 
+/// Whether `etag` is listed in an `If-Match` or `If-None-Match` header.
+///
+/// `If-None-Match` uses the weak comparison, which ignores `W/` prefixes.
+fn etag_matches(header: &http::header::HeaderValue, etag: &http::header::HeaderValue, weak: bool) -> bool {
+    let (header, etag) = match (header.to_str(), etag.to_str()) {
+        (Ok(header), Ok(etag)) => (header, etag.trim()),
+        _ => return false,
+    };
+
+    if weak {
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        header.split(',').any(|tag| opaque(tag) == opaque(etag))
+    } else {
+        !etag.starts_with("W/") && header.split(',').any(|tag| tag.trim() == etag)
+    }
+}
+
+/// HTTP dates have a resolution of one second.
+fn whole_seconds(time: std::time::SystemTime) -> std::time::SystemTime {
+    let since_epoch = time.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(since_epoch.as_secs())
+}
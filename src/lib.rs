@@ -5,21 +5,73 @@ extern crate http;
 extern crate mime;
 extern crate futures;
 extern crate futures_cpupool;
+extern crate num_cpus;
 extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_signal;
+#[cfg(unix)]
+extern crate tokio_uds;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "tls")]
+extern crate rustls;
+#[cfg(feature = "tls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tls")]
+extern crate webpki;
+#[cfg(feature = "tower")]
+extern crate tower_service;
+#[cfg(any(feature = "json", feature = "form", feature = "cbor", feature = "msgpack", feature = "yaml"))]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "form")]
+extern crate serde_urlencoded;
+#[cfg(feature = "cbor")]
+extern crate serde_cbor;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
+#[cfg(feature = "macros")]
+extern crate gerust_macros;
 extern crate hyper;
-extern crate backtrace;
-extern crate regex;
 extern crate bytes;
+extern crate h2;
+extern crate sha1_smol;
+extern crate base64;
 #[macro_use]
 extern crate lazy_static;
 
 pub mod resource;
+pub mod context;
 pub mod flow;
+mod upload;
+pub mod response;
+pub mod range;
+pub mod sse;
+pub mod websocket;
 pub mod server;
+mod listener;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tower")]
+pub mod tower;
 pub mod conneg;
+#[cfg(any(feature = "json", feature = "form"))]
+pub mod decode;
+#[cfg(any(feature = "json", feature = "cbor", feature = "msgpack", feature = "yaml"))]
+pub mod encode;
 pub mod body;
 pub mod chunk;
+pub mod testing;
 pub mod error;
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod macros;
+
+#[cfg(feature = "macros")]
+pub use gerust_macros::resource;
 
 pub type Body = hyper::Body;
 
@@ -31,17 +83,23 @@ impl body::Body for hyper::Body {
         hyper::Body::empty()
     }
 
-    fn pair() -> (futures::sync::mpsc::Sender<Result<Self::Chunk, <Self as body::Body>::Error>>, Self) {
+    fn pair() -> (body::Sender<Self>, Self) {
         hyper::Body::pair()
     }
 }
 
 impl From<error::Error> for hyper::Error {
-    fn from(e: error::Error) -> hyper::Error {
+    fn from(_e: error::Error) -> hyper::Error {
         hyper::Error::Version
     }
 }
 
+impl From<chunk::Chunk> for hyper::Body {
+    fn from(c: chunk::Chunk) -> hyper::Body {
+        hyper::Body::from(hyper::Chunk::from(c))
+    }
+}
+
 impl From<chunk::Chunk> for hyper::Chunk {
     fn from(c: chunk::Chunk) -> hyper::Chunk {
         let bytes: bytes::Bytes = c.into();
@@ -0,0 +1,153 @@
+//! The sockets a `Server` accepts connections on.
+
+use std::fmt;
+use std::io;
+use std::net::{self, SocketAddr};
+
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net as unix;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::Handle;
+
+#[cfg(unix)]
+use libc;
+#[cfg(unix)]
+use tokio_uds::UnixListener;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+/// The first file descriptor passed by systemd socket activation.
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    #[cfg(feature = "tls")]
+    Tls(TcpListener, TlsAcceptor),
+}
+
+impl Listener {
+    pub fn bind_tcp(addr: &SocketAddr, handle: &Handle) -> io::Result<Listener> {
+        let listener = net::TcpListener::bind(addr)?;
+        Ok(Listener::Tcp(TcpListener::from_listener(listener, addr, handle)?))
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn bind_tls(addr: &SocketAddr, tls: TlsAcceptor, handle: &Handle) -> io::Result<Listener> {
+        let listener = net::TcpListener::bind(addr)?;
+        Ok(Listener::Tls(TcpListener::from_listener(listener, addr, handle)?, tls))
+    }
+
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path, handle: &Handle) -> io::Result<Listener> {
+        Ok(Listener::Unix(UnixListener::bind(path, handle)?))
+    }
+
+    /// Takes over the listeners passed via `LISTEN_FDS`, if they are
+    /// meant for this process.
+    ///
+    /// The variables are removed, so child processes don't pick them up.
+    #[cfg(unix)]
+    pub fn inherited(handle: &Handle) -> io::Result<Vec<Listener>> {
+        let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+        let fds = env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<RawFd>().ok());
+
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+
+        let fds = match (pid, fds) {
+            (Some(pid), Some(fds)) if pid == ::std::process::id() => fds,
+            _ => return Ok(Vec::new()),
+        };
+
+        (LISTEN_FDS_START..LISTEN_FDS_START + fds)
+            .map(|fd| Listener::from_fd(fd, handle))
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn from_fd(fd: RawFd, handle: &Handle) -> io::Result<Listener> {
+        unsafe {
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut addr: libc::sockaddr_storage = mem::zeroed();
+            let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+            if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            match libc::c_int::from(addr.ss_family) {
+                libc::AF_INET | libc::AF_INET6 => {
+                    let listener = net::TcpListener::from_raw_fd(fd);
+                    let addr = listener.local_addr()?;
+                    Ok(Listener::Tcp(TcpListener::from_listener(listener, &addr, handle)?))
+                },
+                libc::AF_UNIX => {
+                    let listener = unix::UnixListener::from_raw_fd(fd);
+                    Ok(Listener::Unix(UnixListener::from_listener(listener, handle)?))
+                },
+                _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                        format!("inherited file descriptor {} is not a stream socket", fd))),
+            }
+        }
+    }
+
+    pub fn tcp_addr(&self) -> Option<io::Result<SocketAddr>> {
+        match *self {
+            Listener::Tcp(ref listener) => Some(listener.local_addr()),
+            #[cfg(unix)]
+            Listener::Unix(_) => None,
+            #[cfg(feature = "tls")]
+            Listener::Tls(ref listener, _) => Some(listener.local_addr()),
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Listener::Tcp(ref listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "http://{}", addr),
+                Err(_) => write!(f, "http://<unknown>"),
+            },
+            #[cfg(feature = "tls")]
+            Listener::Tls(ref listener, _) => match listener.local_addr() {
+                Ok(addr) => write!(f, "https://{}", addr),
+                Err(_) => write!(f, "https://<unknown>"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(ref listener) => match listener.local_addr().ok().and_then(|a| a.as_pathname().map(Path::to_path_buf)) {
+                Some(path) => write!(f, "unix:{}", path.display()),
+                None => write!(f, "unix:<unnamed>"),
+            },
+        }
+    }
+}
+
+/// A Unix socket file the server created, removed when dropped.
+#[cfg(unix)]
+pub struct SocketFile(pub PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
@@ -0,0 +1,36 @@
+//! Support for the code `#[resource]` generates.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+pub use http::Method;
+pub use http::header::HeaderValue;
+
+type Key = (TypeId, TypeId, &'static str);
+
+lazy_static! {
+    static ref LEAKED: RwLock<HashMap<Key, &'static (dyn Any + Send + Sync)>> = RwLock::new(HashMap::new());
+}
+
+/// The list `callback` of generic resource `R` returns, built on the
+/// first call.
+///
+/// A `static` in a generic function is shared by all instances, so the
+/// lists are kept per resource type instead. Non-generic resources get a
+/// `static` per callback and don't come here.
+pub fn leak<R, T, F>(callback: &'static str, init: F) -> &'static [T]
+    where R: 'static,
+          T: Send + Sync + 'static,
+          F: FnOnce() -> Vec<T>
+{
+    let key = (TypeId::of::<R>(), TypeId::of::<T>(), callback);
+
+    let leaked = LEAKED.read().unwrap().get(&key).cloned();
+    let leaked = match leaked {
+        Some(leaked) => leaked,
+        None => *LEAKED.write().unwrap().entry(key).or_insert_with(|| Box::leak(Box::new(init()))),
+    };
+
+    leaked.downcast_ref::<Vec<T>>().expect("leaked list of another type")
+}
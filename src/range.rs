@@ -0,0 +1,128 @@
+//! Byte ranges, as requested by the `Range` header (RFC 7233).
+
+use std::fmt;
+use std::error;
+use std::ops::Range;
+
+use http::header::HeaderValue;
+
+/// Headers asking for more ranges than this are ignored, so a request
+/// can't have the representation sent many times over (RFC 7233, 6.1).
+pub const MAX_RANGES: usize = 16;
+
+/// Why a `Range` header can't be served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The header isn't a valid `bytes` range set and is ignored.
+    Invalid,
+    /// No range overlaps the representation, answered with 416.
+    Unsatisfiable,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            Error::Invalid => "invalid byte range set",
+            Error::Unsatisfiable => "no satisfiable byte range",
+        };
+
+        f.write_str(description)
+    }
+}
+
+impl error::Error for Error {}
+
+/// Resolves the ranges in `header` against a representation of `length` bytes.
+///
+/// Ranges are returned in the requested order as half-open intervals,
+/// with overlapping and adjacent ones merged where the first of them was
+/// requested. Ranges starting beyond the end, and so every range of an
+/// empty representation, are dropped and ranges ending beyond it are
+/// shortened, as long as at least one range remains. More than
+/// `MAX_RANGES` ranges are invalid.
+pub fn parse(header: &HeaderValue, length: u64) -> Result<Vec<Range<u64>>, Error> {
+    let header = header.to_str().map_err(|_| Error::Invalid)?.trim();
+
+    let unit_len = "bytes=".len();
+    if header.len() < unit_len || !header[..unit_len].eq_ignore_ascii_case("bytes=") {
+        return Err(Error::Invalid);
+    }
+
+    let specs: Vec<&str> = header[unit_len..].split(',').map(str::trim).filter(|spec| !spec.is_empty()).collect();
+    if specs.len() > MAX_RANGES {
+        return Err(Error::Invalid);
+    }
+
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+
+    for spec in specs {
+        let dash = spec.find('-').ok_or(Error::Invalid)?;
+        let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+
+        let range = if first.is_empty() {
+            // A suffix: the last `n` bytes.
+            let suffix = number(last)?;
+            if suffix == 0 {
+                None
+            } else {
+                Some(length.saturating_sub(suffix)..length)
+            }
+        } else {
+            let first = number(first)?;
+            let last = if last.is_empty() { None } else { Some(number(last)?) };
+
+            match last {
+                Some(last) if last < first => return Err(Error::Invalid),
+                _ if first >= length => None,
+                Some(last) => Some(first..last.saturating_add(1).min(length)),
+                None => Some(first..length),
+            }
+        };
+
+        if let Some(range) = range.filter(|range| range.start < range.end) {
+            coalesce(&mut ranges, range);
+        }
+    }
+
+    if ranges.is_empty() {
+        if header[unit_len..].trim().is_empty() {
+            Err(Error::Invalid)
+        } else {
+            Err(Error::Unsatisfiable)
+        }
+    } else {
+        Ok(ranges)
+    }
+}
+
+/// The `Content-Range` value for `range` of a representation of `length` bytes.
+pub fn content_range(range: &Range<u64>, length: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, length)
+}
+
+/// The `Content-Range` value of a 416 response.
+pub fn unsatisfied_range(length: u64) -> String {
+    format!("bytes */{}", length)
+}
+
+/// Adds `range` to `ranges`, merged with those it overlaps or adjoins.
+fn coalesce(ranges: &mut Vec<Range<u64>>, mut range: Range<u64>) {
+    let mut at = ranges.len();
+
+    // A merged range can reach further ones, so look again until none touch.
+    while let Some(i) = ranges.iter().position(|other| other.start <= range.end && range.start <= other.end) {
+        let other = ranges.remove(i);
+        range = other.start.min(range.start)..other.end.max(range.end);
+        at = at.min(i);
+    }
+
+    ranges.insert(at.min(ranges.len()), range);
+}
+
+fn number(digits: &str) -> Result<u64, Error> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::Invalid);
+    }
+
+    digits.parse().map_err(|_| Error::Invalid)
+}
@@ -1,170 +1,267 @@
 use std;
 use http;
 use mime;
-use ::Body;
+use futures::Future;
+use body::Body;
+use context::Context;
+use error::Error;
+use flow::DelayedResponse;
+use response::Entity;
+use websocket;
+
+/// Applies the result of a deferred callback to the resource once it resolved.
+pub type Apply<R, T> = Box<dyn FnOnce(&mut R) -> T + Send>;
+
+/// The result of a resource callback that may complete asynchronously.
+///
+/// Synchronous callbacks return `Deferred::Ready`, usually through `into()`.
+/// Asynchronous callbacks return a future; the flow continues with the
+/// next decision once it resolved, without blocking a pool thread.
+pub enum Deferred<R, T> {
+    Ready(T),
+    Pending(Box<dyn Future<Item=Apply<R, T>, Error=Error> + Send>),
+}
+
+impl<R, T> Deferred<R, T> where R: 'static, T: Send + 'static {
+    /// Defers the callback to a future that yields the result.
+    pub fn future<F>(future: F) -> Deferred<R, T>
+        where F: Future<Item=T, Error=Error> + Send + 'static
+    {
+        Deferred::apply(future.map(|value| move |_: &mut R| value))
+    }
+
+    /// Defers the callback to a future that yields a function, which is
+    /// then applied to the resource. This allows caching state between
+    /// decisions, e.g. an entity loaded in `resource_exists`.
+    pub fn apply<F, A>(future: F) -> Deferred<R, T>
+        where F: Future<Item=A, Error=Error> + Send + 'static,
+              A: FnOnce(&mut R) -> T + Send + 'static
+    {
+        Deferred::Pending(Box::new(future.map(|apply| Box::new(apply) as Apply<R, T>)))
+    }
+}
+
+impl<R, T> From<T> for Deferred<R, T> {
+    fn from(value: T) -> Deferred<R, T> {
+        Deferred::Ready(value)
+    }
+}
+
+/// Produces the response body in a content type the resource provides,
+/// by returning it or writing it to the response.
+pub type ProvideHandler<R, B = ::Body> = fn (&mut R, context: &Context<B>, response: &mut DelayedResponse<B>) -> Deferred<R, Entity>;
 
-pub struct ProvidedPair<R: Resource>(
+/// Reads a request body in a content type the resource accepts.
+pub type AcceptHandler<R, B = ::Body> = fn (&mut R, context: &mut Context<B>, response: &mut DelayedResponse<B>) -> Deferred<R, ()>;
+
+pub struct ProvidedPair<R, B = ::Body>(
     pub mime::Mime,
-    pub fn (&mut R, response: &mut ::flow::DelayedResponse) -> ()
-);
+    pub ProvideHandler<R, B>
+) where R: Resource<B>, B: Body;
 
-impl<R: Resource> std::convert::AsRef<mime::Mime> for ProvidedPair<R> {
+impl<R, B> std::convert::AsRef<mime::Mime> for ProvidedPair<R, B> where R: Resource<B>, B: Body {
     fn as_ref(&self) -> &mime::Mime {
         &(self.0)
     }
 }
 
-impl<R: Resource> std::borrow::Borrow<mime::Mime> for ProvidedPair<R> {
+impl<R, B> std::borrow::Borrow<mime::Mime> for ProvidedPair<R, B> where R: Resource<B>, B: Body {
     fn borrow(&self) -> &mime::Mime {
         &(self.0)
     }
 }
 
-pub trait Resource where Self: Sized + 'static {
-    fn resource_exists(&self) -> bool {
-        true
+pub struct AcceptedPair<R, B = ::Body>(
+    pub mime::Mime,
+    pub AcceptHandler<R, B>
+) where R: Resource<B>, B: Body;
+
+impl<R, B> std::convert::AsRef<mime::Mime> for AcceptedPair<R, B> where R: Resource<B>, B: Body {
+    fn as_ref(&self) -> &mime::Mime {
+        &(self.0)
     }
+}
 
-    fn service_available(&self) -> bool {
-        true
+impl<R, B> std::borrow::Borrow<mime::Mime> for AcceptedPair<R, B> where R: Resource<B>, B: Body {
+    fn borrow(&self) -> &mime::Mime {
+        &(self.0)
+    }
+}
+
+pub type Encoder<R, B = ::Body> = fn (&R, context: &Context<B>);
+
+/// A resource served through the decision flow, for bodies of type `B`.
+///
+/// `B` defaults to `hyper::Body`. Resources that don't care about the
+/// HTTP stack can implement `Resource<B>` for any `B: Body`.
+///
+/// The lists of methods, types, languages and charsets may borrow from
+/// the resource, so an instance can build them when it is created, e.g.
+/// from the extension of the file it serves.
+pub trait Resource<B = ::Body> where Self: Sized + 'static, B: Body {
+    fn resource_exists(&mut self, _context: &Context<B>) -> Deferred<Self, bool> {
+        true.into()
     }
 
-    fn is_authorized(&self, _authorization_header: Option<&http::header::HeaderValue>) -> bool {
+    fn service_available(&mut self, _context: &Context<B>) -> bool {
         true
     }
 
-    fn forbidden(&self) -> bool {
-        false
+    fn is_authorized(&mut self, _context: &Context<B>) -> Deferred<Self, bool> {
+        true.into()
+    }
+
+    fn forbidden(&mut self, _context: &Context<B>) -> Deferred<Self, bool> {
+        false.into()
+    }
+
+    /// Accepts a WebSocket handshake, asked after authorization for
+    /// `GET` requests with `Upgrade: websocket` over HTTP/1.1. The handler
+    /// takes over the connection after `101 Switching Protocols`; `None`
+    /// declines the upgrade and the request goes through the flow.
+    fn accept_websocket(&mut self, _context: &Context<B>) -> Option<websocket::Handler> {
+        None
     }
 
-    fn allow_missing_post(&self) -> bool {
+    fn allow_missing_post(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
-    fn malformed_request(&self) -> bool {
+    fn malformed_request(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
-    fn uri_too_long(&self, &http::uri::Uri) -> bool {
+    fn uri_too_long(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
-    fn known_content_type(&self, _content_type: &http::header::HeaderValue) -> bool {
+    fn known_content_type(&mut self, _context: &Context<B>, _content_type: &http::header::HeaderValue) -> bool {
         true
     }
 
-    fn valid_content_headers<'a, I: Iterator<Item=(&'a http::header::HeaderName, &'a http::header::HeaderValue)>>(&self, _content_headers: I) -> bool {
+    fn valid_content_headers<'a, I: Iterator<Item=(&'a http::header::HeaderName, &'a http::header::HeaderValue)>>(&mut self, _context: &Context<B>, _content_headers: I) -> bool {
         true
     }
 
-    fn valid_entity_length(&self, _len: u64) -> bool {
+    /// Checks the `Content-Length` of a request. Bodies without one, e.g.
+    /// chunked uploads, are limited to the largest length this accepts,
+    /// failing the body as it streams in and answering `413 Payload Too
+    /// Large` once it grew beyond that.
+    fn valid_entity_length(&mut self, _context: &Context<B>, _len: u64) -> bool {
         true
     }
 
-    fn options(&self) -> &'static [http::header::HeaderValue] {
+    fn options(&self, _context: &Context<B>) -> &'static [http::header::HeaderValue] {
         &[]
     }
 
-    fn allowed_methods(&self) -> &'static [http::Method] {
+    fn allowed_methods(&self, _context: &Context<B>) -> &[http::Method] {
         &[http::Method::GET, http::Method::HEAD]
     }
 
-    fn known_methods(&self) -> &'static [http::Method] {
+    fn known_methods(&self, _context: &Context<B>) -> &'static [http::Method] {
         use http::Method;
 
         &[Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::DELETE, Method::TRACE, Method::CONNECT, Method::OPTIONS]
     }
 
-    fn delete_method(&self) -> bool {
-        false
+    fn delete_resource(&mut self, _context: &Context<B>) -> Deferred<Self, bool> {
+        false.into()
     }
 
-    fn delete_completed(&self) -> bool {
+    fn delete_completed(&mut self, _context: &Context<B>) -> bool {
         true
     }
 
-    fn post_is_create(&self) -> bool {
+    fn post_is_create(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
     // TODO: find proper protocol for this.
-    fn create_path(&self) -> String {
-        format!("")
+    fn create_path(&mut self, _context: &Context<B>) -> String {
+        String::new()
     }
 
-    fn base_uri(&self) -> Option<http::uri::Uri> {
+    fn base_uri(&self, _context: &Context<B>) -> Option<http::uri::Uri> {
         None
     }
 
-    fn process_post(&self, response: &mut ::flow::DelayedResponse) {
+    fn process_post(&mut self, _context: &mut Context<B>, _response: &mut DelayedResponse<B>) -> Deferred<Self, ()> {
+        ().into()
     }
 
     ///TODO: create handler interface
-    fn content_types_provided(&self) -> &'static [ProvidedPair<Self>];
+    fn content_types_provided(&self, context: &Context<B>) -> &[ProvidedPair<Self, B>];
 
     ///TODO: create handler interface
-    fn content_types_accepted(&self) -> &'static [(mime::Mime, fn (&mut Self, request: &mut http::Request<Body>, response: &mut ::flow::DelayedResponse) -> ())] {
+    fn content_types_accepted(&self, _context: &Context<B>) -> &[AcceptedPair<Self, B>] {
         &[]
     }
 
-    fn charsets_provided(&self) -> &'static [http::header::HeaderValue] {
+    fn charsets_provided(&self, _context: &Context<B>) -> &[http::header::HeaderValue] {
         &[]
     }
 
-    fn languages_provided(&self) -> &'static [&'static str] {
+    fn languages_provided(&self, _context: &Context<B>) -> &[http::header::HeaderValue] {
         &[]
     }
 
     ///TODO: create handler interface
-    fn encodings_provided(&self) -> &'static [(&'static str, fn (&Self) -> ())] {
+    fn encodings_provided(&self, _context: &Context<B>) -> &'static [(&'static str, Encoder<Self, B>)] {
         &[]
         //&[("IDENTITY", encode_identity)]
     }
 
-    fn variances(&self) -> &'static [http::header::HeaderValue] {
+    fn variances(&self, _context: &Context<B>) -> &'static [http::header::HeaderValue] {
         &[]
     }
 
-    fn is_conflict(&self) -> bool {
+    fn is_conflict(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
-    fn multiple_choices(&self) -> bool {
+    fn multiple_choices(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
-    fn previously_existed(&self) -> bool {
+    fn previously_existed(&mut self, _context: &Context<B>) -> bool {
         false
     }
 
-    fn moved_permanently(&self) -> Option<http::uri::Uri> {
+    fn moved_permanently(&mut self, _context: &Context<B>) -> Option<http::uri::Uri> {
         None
     }
 
-    fn moved_temporarily(&self) -> Option<http::uri::Uri> {
+    fn moved_temporarily(&mut self, _context: &Context<B>) -> Option<http::uri::Uri> {
         None
     }
 
     /// TODO: Probably Chrono?
-    fn last_modified(&self) -> Option<std::time::SystemTime> {
+    fn last_modified(&mut self, _context: &Context<B>) -> Option<std::time::SystemTime> {
         None
     }
 
-    fn generate_etag(&self) -> Option<http::header::HeaderValue> {
+    fn generate_etag(&mut self, _context: &Context<B>) -> Option<http::header::HeaderValue> {
         None
     }
 
-    fn finish_request(&self) {
+    /// Whether GET requests may ask for byte ranges of the body with
+    /// `Range`. Only bodies of known length are ranged.
+    fn accept_ranges(&mut self, _context: &Context<B>) -> bool {
+        false
+    }
+
+    fn finish_request(&mut self, _context: &Context<B>) {
 
     }
 
     /// TODO: currently unsure if I want to adopt this API
-    fn handle_error(_e: Box<std::any::Any>) {
+    fn handle_error(_e: Box<dyn std::any::Any>) {
 
     }
 
     /// TODO: currently unsure if I want to adopt this API
-    fn validate_content_checksum(&self) -> Option<bool> {
+    fn validate_content_checksum(&mut self, _context: &Context<B>) -> Option<bool> {
         None
     }
 }
@@ -172,5 +269,13 @@ pub trait Resource where Self: Sized + 'static {
 pub trait Handles {
     type Item;
 
-    fn handle(&mut self, item: Self::Item, request: &mut http::Request<Body>, response: &mut ::flow::DelayedResponse);
+    fn handle(&mut self, item: Self::Item);
+}
+
+/// A resource with a single representation, which the providers in
+/// `encode` serialize into the negotiated format.
+pub trait Represents<B = ::Body> where B: Body {
+    type Representation;
+
+    fn represent(&mut self, context: &Context<B>) -> Self::Representation;
 }
\ No newline at end of file
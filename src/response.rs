@@ -0,0 +1,673 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::{future, stream, AsyncSink, Async, Future, Poll, Sink, Stream};
+use http;
+use http::HttpTryFrom;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use mime;
+
+use body::{self, Body};
+use bytes::Bytes;
+use chunk::Chunk;
+use range;
+use resource::Deferred;
+
+/// Files are streamed in chunks of this size.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many chunks `write` buffers for a client that doesn't keep up.
+const MAX_PENDING_CHUNKS: usize = 16;
+
+/// Why a `DelayedResponse` refused a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The head was already sent, status and headers are final.
+    Committed,
+    /// `finish` was called, no more chunks can be written.
+    Finished,
+    /// The client went away.
+    Closed,
+    /// The client didn't take the buffered chunks yet, the chunk was not
+    /// written.
+    Full,
+    /// A header name or value was invalid.
+    InvalidHeader,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            Error::Committed => "the response head was already sent",
+            Error::Finished => "the response body was already finished",
+            Error::Closed => "the response body was closed by the client",
+            Error::Full => "the response body buffer is full",
+            Error::InvalidHeader => "invalid header name or value",
+        };
+
+        f.write_str(description)
+    }
+}
+
+impl error::Error for Error {}
+
+/// The response handlers write to.
+///
+/// Status and headers can be changed until the response is committed by
+/// the first write, which sends the head. Writes never block: a few
+/// chunks the client didn't take yet are buffered and flushed before the
+/// body ends, beyond that `write` fails with `Error::Full`. Larger bodies
+/// go through `write_all`, which waits for the client.
+/// The body ends once the handler returned, or its deferred result
+/// resolved, and every `sender` is dropped.
+pub struct DelayedResponse<B: Body = ::Body> {
+    state: State<B>,
+    head: Option<http::Response<B>>,
+    pending: VecDeque<Result<B::Chunk, <B as Body>::Error>>,
+    finished: bool,
+    redirect: bool,
+}
+
+enum State<B: Body> {
+    Waiting(http::response::Builder),
+    Started(body::Sender<B>),
+    Closed,
+}
+
+impl<B: Body> DelayedResponse<B> {
+    pub(crate) fn new() -> DelayedResponse<B> {
+        DelayedResponse {
+            state: State::Waiting(http::response::Builder::new()),
+            head: None,
+            pending: VecDeque::new(),
+            finished: false,
+            redirect: false,
+        }
+    }
+
+    pub fn status(&mut self, status: http::StatusCode) -> Result<&mut Self, Error> {
+        self.builder()?.status(status);
+        Ok(self)
+    }
+
+    /// Adds a header, keeping earlier ones of the same name.
+    pub fn header<K, V>(&mut self, name: K, value: V) -> Result<&mut Self, Error>
+        where HeaderName: HttpTryFrom<K>,
+              HeaderValue: HttpTryFrom<V>
+    {
+        let name = HeaderName::try_from(name).map_err(|_| Error::InvalidHeader)?;
+        let value = HeaderValue::try_from(value).map_err(|_| Error::InvalidHeader)?;
+
+        self.builder()?.header::<HeaderName, HeaderValue>(name, value);
+        Ok(self)
+    }
+
+    /// The headers set so far, `None` once committed.
+    pub fn headers(&self) -> Option<&HeaderMap> {
+        match self.state {
+            State::Waiting(ref builder) => builder.headers_ref(),
+            _ => None,
+        }
+    }
+
+    /// Answers a POST with `303 See Other` to its `Location`, instead of
+    /// `201 Created` or a body.
+    pub fn redirect(&mut self) -> Result<&mut Self, Error> {
+        self.builder()?;
+        self.redirect = true;
+        Ok(self)
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.redirect
+    }
+
+    pub fn is_committed(&self) -> bool {
+        !matches!(self.state, State::Waiting(_))
+    }
+
+    /// Writes a chunk of the body, committing the response.
+    ///
+    /// Fails with `Error::Full` once `MAX_PENDING_CHUNKS` chunks wait for
+    /// the client, dropping the chunk.
+    pub fn write<C: Into<Chunk>>(&mut self, chunk: C) -> Result<(), Error> {
+        let sender = commit(&mut self.state, &mut self.head, self.finished)?;
+
+        if self.pending.len() >= MAX_PENDING_CHUNKS {
+            return Err(Error::Full);
+        }
+
+        let chunk = Ok(B::Chunk::from(chunk.into()));
+
+        if !self.pending.is_empty() {
+            self.pending.push_back(chunk);
+            return Ok(());
+        }
+
+        match sender.start_send(chunk) {
+            Ok(AsyncSink::Ready) => Ok(()),
+            Ok(AsyncSink::NotReady(chunk)) => {
+                self.pending.push_back(chunk);
+                Ok(())
+            },
+            Err(_) => Err(Error::Closed),
+        }
+    }
+
+    /// Writes every chunk of `stream`, committing the response.
+    ///
+    /// Resolves once the client took all chunks, so it applies
+    /// backpressure when returned from a handler via `Deferred::future`.
+    /// Fails with the stream's error, or `Error::Response` if the writer
+    /// refused the chunks or the client went away.
+    pub fn write_all<S>(&mut self, stream: S) -> impl Future<Item=(), Error=::error::Error> + Send
+        where S: Stream<Error=::error::Error> + Send + 'static,
+              S::Item: Into<Chunk>
+    {
+        let sender = match commit(&mut self.state, &mut self.head, self.finished) {
+            Ok(sender) => sender.clone(),
+            Err(e) => return future::Either::A(future::err(e.into())),
+        };
+
+        // Earlier writes go first.
+        let pending = stream::iter_ok(self.pending.drain(..).collect::<Vec<_>>());
+        let chunks = pending.chain(stream.map(|chunk| Ok(B::Chunk::from(chunk.into()))));
+
+        future::Either::B(sender.sink_map_err(|_| Error::Closed.into()).send_all(chunks).map(|_| ()))
+    }
+
+    /// A sender to stream the body from elsewhere, e.g. another thread,
+    /// committing the response. The body ends once all senders are gone.
+    pub fn sender(&mut self) -> Result<body::Sender<B>, Error> {
+        commit(&mut self.state, &mut self.head, self.finished).map(|sender| sender.clone())
+    }
+
+    /// Ends the body once the written chunks are flushed, committing the
+    /// response if that didn't happen yet.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        commit(&mut self.state, &mut self.head, self.finished)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Adds a response extension for the server, e.g. a WebSocket upgrade.
+    pub(crate) fn extension<T>(&mut self, extension: T) -> Result<&mut Self, Error>
+        where T: Send + Sync + 'static
+    {
+        self.builder()?.extension(extension);
+        Ok(self)
+    }
+
+    fn builder(&mut self) -> Result<&mut http::response::Builder, Error> {
+        match self.state {
+            State::Waiting(ref mut builder) => Ok(builder),
+            _ => Err(Error::Committed),
+        }
+    }
+
+    /// The committed head, if it wasn't sent yet.
+    pub(crate) fn take_head(&mut self) -> Option<http::Response<B>> {
+        self.head.take()
+    }
+
+    /// The complete response with `body`, unless already committed.
+    pub(crate) fn respond(&mut self, status: Option<http::StatusCode>, body: B) -> Option<http::Response<B>> {
+        match mem::replace(&mut self.state, State::Closed) {
+            State::Waiting(mut builder) => {
+                if let Some(status) = status {
+                    builder.status(status);
+                }
+
+                Some(build(builder, body))
+            },
+            state => {
+                self.state = state;
+                None
+            },
+        }
+    }
+
+    /// Hands the buffered chunks to the client.
+    pub(crate) fn poll_flush(&mut self) -> Poll<(), Error> {
+        let sender = match self.state {
+            State::Started(ref mut sender) => sender,
+            _ => return Ok(Async::Ready(())),
+        };
+
+        while let Some(chunk) = self.pending.pop_front() {
+            match sender.start_send(chunk) {
+                Ok(AsyncSink::Ready) => {},
+                Ok(AsyncSink::NotReady(chunk)) => {
+                    self.pending.push_front(chunk);
+                    return Ok(Async::NotReady);
+                },
+                Err(_) => return Err(Error::Closed),
+            }
+        }
+
+        sender.poll_complete().map_err(|_| Error::Closed)
+    }
+
+    /// Ends the body, dropping anything not flushed.
+    pub(crate) fn close(&mut self) {
+        self.state = State::Closed;
+        self.pending.clear();
+    }
+}
+
+/// Prepares the head with a streamed body and returns the body's sender,
+/// unless the response is finished or closed.
+fn commit<'a, B: Body>(state: &'a mut State<B>, head: &mut Option<http::Response<B>>, finished: bool) -> Result<&'a mut body::Sender<B>, Error> {
+    if finished {
+        return Err(Error::Finished);
+    }
+
+    if let State::Waiting(ref mut builder) = *state {
+        let (sender, body) = B::pair();
+        *head = Some(build(mem::replace(builder, http::response::Builder::new()), body));
+        *state = State::Started(sender);
+    }
+
+    match *state {
+        State::Started(ref mut sender) => Ok(sender),
+        _ => Err(Error::Closed),
+    }
+}
+
+/// Headers and status are validated when set, so building can't fail.
+fn build<B>(mut builder: http::response::Builder, body: B) -> http::Response<B> {
+    builder.body(body).expect("response head is validated when set")
+}
+
+/// A response body returned from an output handler.
+///
+/// The flow writes it to the `DelayedResponse`, setting `Content-Length`
+/// when the length is known and nothing was written before. Handlers that
+/// wrote the body themselves return `().into()`.
+pub struct Entity {
+    content: Content,
+    length: Option<u64>,
+}
+
+type ChunkStream = Box<dyn Stream<Item=Chunk, Error=::error::Error> + Send>;
+
+enum Content {
+    Empty,
+    Bytes(Chunk),
+    /// A file and the position its body starts at.
+    File(File, u64),
+    Stream(ChunkStream),
+}
+
+impl Entity {
+    /// No body beyond what the handler wrote.
+    pub fn empty() -> Entity {
+        Entity { content: Content::Empty, length: None }
+    }
+
+    /// Streams `stream`, of unknown length unless set with `length`.
+    pub fn stream<S>(stream: S) -> Entity
+        where S: Stream<Error=::error::Error> + Send + 'static,
+              S::Item: Into<Chunk>
+    {
+        Entity { content: Content::Stream(Box::new(stream.map(Into::into))), length: None }
+    }
+
+    /// Streams `file` from its current position to the end.
+    pub fn file(mut file: File) -> io::Result<Entity> {
+        let position = file.stream_position()?;
+        let length = file.metadata()?.len().saturating_sub(position);
+
+        Ok(Entity { content: Content::File(file, position), length: Some(length) })
+    }
+
+    /// Opens and streams the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Entity> {
+        Entity::file(File::open(path)?)
+    }
+
+    /// Sets the length of a streamed body, which has to be exact.
+    ///
+    /// Ignored for bytes and files, their length is already known.
+    pub fn length(mut self, length: u64) -> Entity {
+        if let Content::Stream(_) = self.content {
+            self.length = Some(length);
+        }
+        self
+    }
+
+    /// The length of the body, if known.
+    pub fn len(&self) -> Option<u64> {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == Some(0)
+    }
+
+    /// Writes the entity, resolving once the client took it.
+    ///
+    /// HEAD responses only get the `Content-Length`.
+    pub(crate) fn write_to<B: Body>(self, response: &mut DelayedResponse<B>, head: bool) -> Option<Box<dyn Future<Item=(), Error=::error::Error> + Send>> {
+        if let Some(length) = self.length {
+            if !response.is_committed() {
+                let _ = response.header(http::header::CONTENT_LENGTH, &*length.to_string());
+            }
+        }
+
+        if head {
+            let _ = response.finish();
+            return None;
+        }
+
+        match self.content {
+            Content::Empty => None,
+            Content::Bytes(chunk) => {
+                if response.pending.len() >= MAX_PENDING_CHUNKS {
+                    return Some(Box::new(response.write_all(stream::once(Ok(chunk)))));
+                }
+
+                match response.write(chunk) {
+                    Ok(()) => None,
+                    Err(e) => Some(Box::new(future::err(e.into()))),
+                }
+            },
+            Content::File(file, _) => Some(Box::new(response.write_all(FileStream::new(file)))),
+            Content::Stream(stream) => Some(Box::new(response.write_all(stream))),
+        }
+    }
+
+    /// Narrows the entity to `ranges` of its body, returning the header
+    /// describing them: `Content-Range` for a single range, or the
+    /// `Content-Type` of a `multipart/byteranges` body for several.
+    ///
+    /// Entities of unknown length can't be ranged and are given back, as
+    /// are streams whose ranges aren't ascending, which would need
+    /// buffering.
+    pub(crate) fn ranges(self, ranges: &[Range<u64>], content_type: Option<&mime::Mime>) -> Result<(Entity, HeaderName, String), Entity> {
+        let length = match (self.length, &self.content) {
+            (_, &Content::Empty) | (None, _) => return Err(self),
+            (Some(_), &Content::Stream(_)) if !ascending(ranges) => return Err(self),
+            (Some(length), _) => length,
+        };
+
+        let (segments, name, value) = if let [ref range] = *ranges {
+            let content_range = range::content_range(range, length);
+            (vec![Segment::Slice(range.clone())], http::header::CONTENT_RANGE, content_range)
+        } else {
+            let boundary = boundary();
+            let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+
+            for (i, range) in ranges.iter().enumerate() {
+                let mut part = format!("{}--{}\r\n", if i == 0 { "" } else { "\r\n" }, boundary);
+                if let Some(content_type) = content_type {
+                    part.push_str(&format!("Content-Type: {}\r\n", content_type));
+                }
+                part.push_str(&format!("Content-Range: {}\r\n\r\n", range::content_range(range, length)));
+
+                segments.push(Segment::Literal(part.into()));
+                segments.push(Segment::Slice(range.clone()));
+            }
+            segments.push(Segment::Literal(format!("\r\n--{}--\r\n", boundary).into()));
+
+            let content_type = format!("multipart/byteranges; boundary={}", boundary);
+            (segments, http::header::CONTENT_TYPE, content_type)
+        };
+
+        let length = segments.iter().map(Segment::len).sum();
+
+        let content = match self.content {
+            Content::Bytes(chunk) => {
+                let mut body = Vec::with_capacity(length as usize);
+                for segment in &segments {
+                    match *segment {
+                        Segment::Literal(ref literal) => body.extend_from_slice(literal),
+                        Segment::Slice(ref range) => body.extend_from_slice(&chunk[range.start as usize..range.end as usize]),
+                    }
+                }
+                Content::Bytes(body.into())
+            },
+            Content::File(file, position) => {
+                let parts: Vec<ChunkStream> = segments.into_iter()
+                    .map(|segment| -> ChunkStream {
+                        match segment {
+                            Segment::Literal(literal) => Box::new(stream::once(Ok(literal))),
+                            Segment::Slice(range) => match file.try_clone() {
+                                Ok(file) => Box::new(FileStream::range(file, position + range.start, range.end - range.start)),
+                                Err(e) => Box::new(stream::once(Err(e.into()))),
+                            },
+                        }
+                    })
+                    .collect();
+                Content::Stream(Box::new(stream::iter_ok::<_, ::error::Error>(parts).flatten()))
+            },
+            Content::Stream(stream) => {
+                Content::Stream(Box::new(Slices { stream, segments: segments.into(), offset: 0, rest: None }))
+            },
+            Content::Empty => unreachable!("empty entities are not ranged"),
+        };
+
+        Ok((Entity { content, length: Some(length) }, name, value))
+    }
+}
+
+/// A part of a ranged body.
+enum Segment {
+    Literal(Chunk),
+    Slice(Range<u64>),
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match *self {
+            Segment::Literal(ref literal) => literal.len() as u64,
+            Segment::Slice(ref range) => range.end - range.start,
+        }
+    }
+}
+
+fn ascending(ranges: &[Range<u64>]) -> bool {
+    ranges.windows(2).all(|pair| pair[0].end <= pair[1].start)
+}
+
+/// A `multipart/byteranges` boundary, unique enough not to occur in bodies.
+fn boundary() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.subsec_nanos()).unwrap_or(0);
+    format!("gerust-{:08x}{:04x}", nanos, COUNT.fetch_add(1, Ordering::Relaxed) & 0xffff)
+}
+
+impl fmt::Debug for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let content = match self.content {
+            Content::Empty => "Empty",
+            Content::Bytes(_) => "Bytes",
+            Content::File(..) => "File",
+            Content::Stream(_) => "Stream",
+        };
+
+        f.debug_struct("Entity").field("content", &content).field("length", &self.length).finish()
+    }
+}
+
+impl From<()> for Entity {
+    fn from(_: ()) -> Entity {
+        Entity::empty()
+    }
+}
+
+impl From<Chunk> for Entity {
+    fn from(chunk: Chunk) -> Entity {
+        let length = chunk.len() as u64;
+        Entity { content: Content::Bytes(chunk), length: Some(length) }
+    }
+}
+
+impl From<Vec<u8>> for Entity {
+    fn from(bytes: Vec<u8>) -> Entity {
+        Chunk::from(bytes).into()
+    }
+}
+
+impl From<String> for Entity {
+    fn from(text: String) -> Entity {
+        Chunk::from(text).into()
+    }
+}
+
+impl From<&'static [u8]> for Entity {
+    fn from(bytes: &'static [u8]) -> Entity {
+        Chunk::from(bytes).into()
+    }
+}
+
+impl From<&'static str> for Entity {
+    fn from(text: &'static str) -> Entity {
+        Chunk::from(text).into()
+    }
+}
+
+/// Lets output handlers return bodies with `into()`.
+macro_rules! ready_entity {
+    ($($body:ty),*) => {
+        $(
+            impl<R> From<$body> for Deferred<R, Entity> {
+                fn from(body: $body) -> Deferred<R, Entity> {
+                    Deferred::Ready(body.into())
+                }
+            }
+        )*
+    }
+}
+
+ready_entity!((), Chunk, Vec<u8>, String, &'static [u8], &'static str);
+
+/// Reads a file in chunks of `FILE_CHUNK_SIZE`, from a position and up
+/// to a length if given.
+///
+/// Reads block, which is fine on the flow's pool.
+struct FileStream {
+    file: File,
+    seek: Option<u64>,
+    remaining: Option<u64>,
+}
+
+impl FileStream {
+    fn new(file: File) -> FileStream {
+        FileStream { file, seek: None, remaining: None }
+    }
+
+    /// Seeks lazily, as clones of a file share their position.
+    fn range(file: File, start: u64, length: u64) -> FileStream {
+        FileStream { file, seek: Some(start), remaining: Some(length) }
+    }
+}
+
+impl Stream for FileStream {
+    type Item = Chunk;
+    type Error = ::error::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::error::Error> {
+        if let Some(position) = self.seek.take() {
+            self.file.seek(SeekFrom::Start(position))?;
+        }
+
+        let size = match self.remaining {
+            Some(0) => return Ok(Async::Ready(None)),
+            Some(remaining) => remaining.min(FILE_CHUNK_SIZE as u64) as usize,
+            None => FILE_CHUNK_SIZE,
+        };
+        let mut buffer = vec![0; size];
+
+        let read = loop {
+            match self.file.read(&mut buffer) {
+                Ok(read) => break read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        if read == 0 {
+            match self.remaining {
+                Some(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                None => Ok(Async::Ready(None)),
+            }
+        } else {
+            self.remaining = self.remaining.map(|remaining| remaining - read as u64);
+            buffer.truncate(read);
+            Ok(Async::Ready(Some(buffer.into())))
+        }
+    }
+}
+
+/// Cuts ascending ranges out of a stream, interleaved with literals.
+struct Slices<S> {
+    stream: S,
+    segments: VecDeque<Segment>,
+    /// The stream position `rest` starts at.
+    offset: u64,
+    /// What's left of the last chunk read.
+    rest: Option<Bytes>,
+}
+
+impl<S> Stream for Slices<S>
+    where S: Stream<Item=Chunk, Error=::error::Error>
+{
+    type Item = Chunk;
+    type Error = ::error::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::error::Error> {
+        loop {
+            let range = match self.segments.front() {
+                None => return Ok(Async::Ready(None)),
+                Some(Segment::Slice(range)) => range.clone(),
+                Some(Segment::Literal(_)) => match self.segments.pop_front() {
+                    Some(Segment::Literal(literal)) => return Ok(Async::Ready(Some(literal))),
+                    _ => unreachable!(),
+                },
+            };
+
+            let bytes = match self.rest.take() {
+                Some(bytes) => bytes,
+                None => match self.stream.poll()? {
+                    Async::Ready(Some(chunk)) => Bytes::from(chunk),
+                    Async::Ready(None) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                    Async::NotReady => return Ok(Async::NotReady),
+                },
+            };
+
+            let start = self.offset;
+            let end = start + bytes.len() as u64;
+
+            if end <= range.start {
+                self.offset = end;
+                continue;
+            }
+
+            let from = (range.start.max(start) - start) as usize;
+            let to = (range.end.min(end) - start) as usize;
+
+            if to < bytes.len() {
+                self.rest = Some(bytes.slice_from(to));
+            }
+            self.offset = start + to as u64;
+
+            if self.offset >= range.end {
+                self.segments.pop_front();
+            }
+
+            if from < to {
+                return Ok(Async::Ready(Some(bytes.slice(from, to).into())));
+            }
+        }
+    }
+}
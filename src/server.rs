@@ -1,79 +1,989 @@
 use http;
-use futures;
 use hyper;
+use num_cpus;
 
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
 use std::marker::PhantomData;
+use std::mem;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use resource::Resource;
 use flow::{Flow, HttpFlow};
+use websocket::{self, WebSocket};
+use listener::Listener;
+#[cfg(feature = "tls")]
+use tls::{self, TlsConfig};
+#[cfg(feature = "tls")]
+use rustls::Session;
+#[cfg(unix)]
+use listener::SocketFile;
 
-use hyper::server::{Http};
+use hyper::server::Http;
+
+use bytes::Bytes;
+
+use http::uri::Scheme;
 
 use futures::sync::oneshot;
-use futures::Future;
+use futures::unsync;
+use futures::future::{self, Either};
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll, Stream};
 
 use futures_cpupool;
-use tokio_core;
+
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+#[cfg(unix)]
+use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+use std::sync::Mutex;
 
 use std::sync::Arc;
 
 use std::fmt::Debug;
 
-struct GerustService<R> where R: Resource + Default + Debug + Sync {
+use self::expect::{ContinueGate, Gated};
+use self::http2::{Preface, Rewind};
+
+pub(crate) use self::expect::Continue;
+
+mod expect;
+mod http2;
+
+/// Errors raised while setting up or running a server.
+#[derive(Debug)]
+pub enum Error {
+    /// Binding or accepting on a socket failed.
+    Io(io::Error),
+    /// The builder was given a configuration that can't be served.
+    Config(&'static str),
+    /// Loading certificates or keys failed.
+    #[cfg(feature = "tls")]
+    Tls(tls::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<tls::Error> for Error {
+    fn from(e: tls::Error) -> Error {
+        Error::Tls(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "server I/O error: {}", e),
+            Error::Config(reason) => write!(f, "invalid server configuration: {}", reason),
+            #[cfg(feature = "tls")]
+            Error::Tls(ref e) => write!(f, "TLS setup failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Builds a `Server` serving a resource of type `R` for every request.
+///
+/// ```no_run
+/// # use gerust::server::ServerBuilder;
+/// # use std::time::Duration;
+/// # fn run<R: gerust::resource::Resource + std::fmt::Debug + Default + Send>() -> Result<(), gerust::server::Error> {
+/// ServerBuilder::new()
+///     .bind(([0, 0, 0, 0], 8080).into())
+///     .threads(8)
+///     .request_timeout(Duration::from_secs(30))
+///     .build::<R>()?
+///     .run()
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    addrs: Vec<SocketAddr>,
+    #[cfg(unix)]
+    unix_paths: Vec<PathBuf>,
+    #[cfg(feature = "tls")]
+    tls_addrs: Vec<(SocketAddr, TlsConfig)>,
+    socket_activation: bool,
+    threads: usize,
+    keep_alive: bool,
+    http2: bool,
+    header_read_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    max_header_size: Option<usize>,
+    shutdown_timeout: Duration,
+    handle_signals: bool,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+}
+
+impl ServerBuilder {
+    /// A builder listening on `127.0.0.1:3000` with one worker per CPU,
+    /// unless other listeners are configured.
+    pub fn new() -> ServerBuilder {
+        ServerBuilder {
+            addrs: Vec::new(),
+            #[cfg(unix)]
+            unix_paths: Vec::new(),
+            #[cfg(feature = "tls")]
+            tls_addrs: Vec::new(),
+            socket_activation: false,
+            threads: num_cpus::get(),
+            keep_alive: true,
+            http2: true,
+            header_read_timeout: None,
+            request_timeout: None,
+            max_header_size: None,
+            shutdown_timeout: Duration::from_secs(30),
+            handle_signals: true,
+        }
+    }
+
+    /// Adds an address to listen on. Can be called multiple times.
+    pub fn bind(&mut self, addr: SocketAddr) -> &mut Self {
+        self.addrs.push(addr);
+        self
+    }
+
+    /// Adds an address to serve HTTPS on, terminating TLS with the
+    /// certificates in `tls`. Can be called multiple times.
+    #[cfg(feature = "tls")]
+    pub fn bind_tls(&mut self, addr: SocketAddr, tls: TlsConfig) -> &mut Self {
+        self.tls_addrs.push((addr, tls));
+        self
+    }
+
+    /// Adds a Unix domain socket to listen on. Can be called multiple times.
+    ///
+    /// The socket file is created when building and removed once the
+    /// server is done.
+    #[cfg(unix)]
+    pub fn bind_unix<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.unix_paths.push(path.into());
+        self
+    }
+
+    /// Also listens on sockets passed by systemd socket activation
+    /// through `LISTEN_FDS`. Starting without any is not an error.
+    pub fn socket_activation(&mut self, enabled: bool) -> &mut Self {
+        self.socket_activation = enabled;
+        self
+    }
+
+    /// Sets the number of threads resources are executed on.
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Enables or disables HTTP keep-alive. Enabled by default.
+    pub fn keep_alive(&mut self, keep_alive: bool) -> &mut Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Enables or disables HTTP/2. Enabled by default.
+    ///
+    /// Plain connections starting with the HTTP/2 preface (prior
+    /// knowledge) are served over HTTP/2, TLS connections when the client
+    /// negotiates `h2` via ALPN. All others are served over HTTP/1.
+    pub fn http2(&mut self, enabled: bool) -> &mut Self {
+        self.http2 = enabled;
+        self
+    }
+
+    /// Closes connections that don't deliver a complete request head
+    /// within `timeout`.
+    ///
+    /// This also applies to idle keep-alive connections waiting for
+    /// their next request.
+    pub fn header_read_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.header_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Answers with `503 Service Unavailable` if a resource didn't start
+    /// its response within `timeout`.
+    pub fn request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Limits the size of the request head in bytes.
+    ///
+    /// hyper enforces this on its read buffer, which grows in steps of
+    /// 8 KiB, so the limit has to be at least that large.
+    pub fn max_header_size(&mut self, size: usize) -> &mut Self {
+        self.max_header_size = Some(size);
+        self
+    }
+
+    /// How long a shutdown waits for open connections to finish.
+    /// Defaults to 30 seconds.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Shuts down gracefully on `SIGTERM` and `SIGINT`. Enabled by default,
+    /// has no effect on platforms without signals.
+    pub fn handle_signals(&mut self, handle_signals: bool) -> &mut Self {
+        self.handle_signals = handle_signals;
+        self
+    }
+
+    /// Binds all addresses and sets up the worker pool.
+    pub fn build<R>(&self) -> Result<Server<R>, Error>
+        where R: Resource + Debug + Default + Send
+    {
+        if self.threads == 0 {
+            return Err(Error::Config("at least one worker thread is required"));
+        }
+
+        if let Some(size) = self.max_header_size {
+            if size < MIN_HEADER_SIZE {
+                return Err(Error::Config("max header size must be at least 8192 bytes"));
+            }
+        }
+
+        let core = Core::new()?;
+        let handle = core.handle();
+
+        let mut listeners = Vec::new();
+
+        #[cfg(unix)]
+        {
+            if self.socket_activation {
+                listeners.extend(Listener::inherited(&handle)?);
+            }
+        }
+
+        for addr in &self.addrs {
+            listeners.push(Listener::bind_tcp(addr, &handle)?);
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            for (addr, tls) in &self.tls_addrs {
+                listeners.push(Listener::bind_tls(addr, tls.acceptor(self.http2)?, &handle)?);
+            }
+        }
+
+        #[cfg(unix)]
+        let mut socket_files = Vec::new();
+
+        #[cfg(unix)]
+        {
+            for path in &self.unix_paths {
+                listeners.push(Listener::bind_unix(path, &handle)?);
+                socket_files.push(SocketFile(path.clone()));
+            }
+        }
+
+        if listeners.is_empty() {
+            listeners.push(Listener::bind_tcp(&([127, 0, 0, 1], 3000).into(), &handle)?);
+        }
+
+        let pool = futures_cpupool::Builder::new()
+            .pool_size(self.threads)
+            .name_prefix("gerust-worker-")
+            .create();
+
+        let (trigger, shutdown) = oneshot::channel();
+
+        Ok(Server {
+            core,
+            listeners,
+            #[cfg(unix)]
+            socket_files,
+            pool: Arc::new(pool),
+            config: self.clone(),
+            handle: ServerHandle { trigger: Arc::new(Mutex::new(Some(trigger))) },
+            shutdown,
+            resource: PhantomData,
+        })
+    }
+}
+
+/// The smallest read buffer hyper allocates.
+const MIN_HEADER_SIZE: usize = 8192;
+
+/// A bound server, ready to `run`.
+pub struct Server<R> {
+    core: Core,
+    listeners: Vec<Listener>,
+    #[cfg(unix)]
+    socket_files: Vec<SocketFile>,
     pool: Arc<futures_cpupool::CpuPool>,
-    handle: tokio_core::reactor::Remote,
-    resource: PhantomData<R>
+    config: ServerBuilder,
+    handle: ServerHandle,
+    shutdown: oneshot::Receiver<()>,
+    resource: PhantomData<R>,
 }
 
-impl<R> hyper::server::Service for GerustService<R>
-    where R: Resource + Default + Debug + Sync
+/// Stops a running `Server` from any thread.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    trigger: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+}
+
+impl ServerHandle {
+    /// Stops accepting connections and lets open ones finish.
+    ///
+    /// `Server::run` returns once all connections are closed or the
+    /// shutdown timeout elapsed. Calling this more than once has no effect.
+    pub fn shutdown(&self) {
+        if let Some(trigger) = self.trigger.lock().unwrap().take() {
+            let _ = trigger.send(());
+        }
+    }
+}
+
+impl<R> Server<R> where R: Resource + Debug + Default + Send {
+    /// The TCP addresses the server actually listens on.
+    ///
+    /// Useful when binding to port 0.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        self.listeners.iter()
+            .filter_map(Listener::tcp_addr)
+            .map(|addr| addr.map_err(Error::from))
+            .collect()
+    }
+
+    /// A handle to shut the server down once it runs.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Serves requests until shut down through a `ServerHandle` or,
+    /// unless disabled, a termination signal.
+    pub fn run(self) -> Result<(), Error> {
+        self.run_until(future::empty())
+    }
+
+    /// Like `run`, but also shuts down once `signal` resolves or fails.
+    pub fn run_until<F>(self, signal: F) -> Result<(), Error>
+        where F: Future<Item=(), Error=()> + 'static
+    {
+        for listener in &self.listeners {
+            info!("Listening on {} with {} threads.", listener, self.config.threads);
+        }
+
+        // Removes our socket files once we're done.
+        #[cfg(unix)]
+        let _socket_files = self.socket_files;
+
+        let Server { mut core, listeners, pool, config, shutdown, .. } = self;
+        let handle = core.handle();
+
+        let mut http = Http::<hyper::Chunk>::new();
+        http.keep_alive(config.keep_alive);
+        if let Some(size) = config.max_header_size {
+            http.max_buf_size(size);
+        }
+
+        let connections = Rc::new(Connections::default());
+
+        let acceptor = Rc::new(Acceptor {
+            http,
+            pool,
+            config: config.clone(),
+            connections: connections.clone(),
+            handle: handle.clone(),
+        });
+
+        let accepting = listeners.into_iter().map(|listener| {
+            let acceptor = acceptor.clone();
+
+            match listener {
+                Listener::Tcp(listener) => Box::new(listener.incoming().for_each(move |(socket, _)| {
+                    acceptor.serve::<R, _>(socket, Scheme::HTTP, None)
+                })) as Box<dyn Future<Item=(), Error=io::Error>>,
+                #[cfg(unix)]
+                Listener::Unix(listener) => Box::new(listener.incoming().for_each(move |(socket, _)| {
+                    acceptor.serve::<R, _>(socket, Scheme::HTTP, None)
+                })),
+                #[cfg(feature = "tls")]
+                Listener::Tls(listener, tls) => Box::new(listener.incoming().for_each(move |(socket, _)| {
+                    let handle = acceptor.handle.clone();
+                    let acceptor = acceptor.clone();
+
+                    // Handshakes run concurrently, so a slow client can't block accepting.
+                    let handshake = tls.accept(socket).then(move |result| match result {
+                        Ok(stream) => {
+                            let version = match stream.get_ref().1.get_alpn_protocol() {
+                                Some(b"h2") => http::Version::HTTP_2,
+                                _ => http::Version::HTTP_11,
+                            };
+
+                            acceptor.serve::<R, _>(stream, Scheme::HTTPS, Some(version))
+                                .map_err(|e| warn!("can't serve TLS connection: {}", e))
+                        },
+                        Err(e) => {
+                            debug!("TLS handshake failed: {}", e);
+                            Ok(())
+                        },
+                    });
+
+                    handle.spawn(handshake);
+                    Ok(())
+                })),
+            }
+        });
+
+        let mut signals: Vec<Box<dyn Future<Item=(), Error=()>>> = vec![
+            Box::new(signal),
+            // Dropping all handles must not stop the server.
+            Box::new(shutdown.or_else(|_| future::empty())),
+        ];
+
+        if config.handle_signals {
+            signals.extend(termination_signals(&handle));
+        }
+
+        let stop = future::select_all(signals).then(|_| Ok::<(), io::Error>(()));
+
+        // Stopping drops the listeners, so no new connections are accepted.
+        match core.run(future::join_all(accepting).select2(stop)) {
+            Ok(_) => {},
+            Err(Either::A((e, _))) | Err(Either::B((e, _))) => return Err(e.into()),
+        }
+
+        info!("Shutting down, waiting for {} open connections.", connections.len());
+        connections.shutdown();
+
+        let deadline = Timeout::new(config.shutdown_timeout, &handle)?;
+
+        match core.run(Drained(connections.clone()).select2(deadline)) {
+            Ok(Either::A(_)) => {},
+            Ok(Either::B(_)) => warn!("Shutdown timeout elapsed, closing {} connections.", connections.len()),
+            Err(Either::A((e, _))) | Err(Either::B((e, _))) => return Err(e.into()),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn termination_signals(handle: &Handle) -> Vec<Box<dyn Future<Item=(), Error=()>>> {
+    [SIGTERM, SIGINT].iter().map(|&signal| {
+        let received = Signal::with_handle(signal, handle.new_tokio_handle())
+            .flatten_stream()
+            .into_future()
+            .map(move |_| info!("Received signal {}.", signal))
+            .or_else(move |(e, _)| {
+                warn!("Can't listen for signal {}: {}", signal, e);
+                future::empty()
+            });
+
+        Box::new(received) as Box<dyn Future<Item=(), Error=()>>
+    }).collect()
+}
+
+#[cfg(not(unix))]
+fn termination_signals(_handle: &Handle) -> Vec<Box<dyn Future<Item=(), Error=()>>> {
+    Vec::new()
+}
+
+/// Everything needed to serve an accepted connection, whatever the socket.
+struct Acceptor {
+    http: Http<hyper::Chunk>,
+    pool: Arc<futures_cpupool::CpuPool>,
+    config: ServerBuilder,
+    connections: Rc<Connections>,
+    handle: Handle,
+}
+
+impl Acceptor {
+    /// Serves `socket` over `version`, or whatever HTTP version the client
+    /// starts speaking if it's not known yet.
+    fn serve<R, I>(&self, socket: I, scheme: Scheme, version: Option<http::Version>) -> io::Result<()>
+        where R: Resource + Debug + Default + Send,
+              I: AsyncRead + AsyncWrite + 'static
+    {
+        let state = Rc::new(ConnectionState::new());
+
+        let service = GerustService::<R> {
+            pool: self.pool.clone(),
+            handle: self.handle.clone(),
+            request_timeout: self.config.request_timeout,
+            scheme,
+            state: state.clone(),
+            expect: ContinueGate::default(),
+            resource: PhantomData,
+        };
+
+        let protocols = Protocols {
+            http: self.http.clone(),
+            max_header_size: self.config.max_header_size,
+            service,
+        };
+
+        let protocol = match version {
+            None if self.config.http2 => Protocol::Detecting(Preface::new(socket), Some(protocols)),
+            version => protocols.serve(Rewind::new(socket, Bytes::new()), version == Some(http::Version::HTTP_2)),
+        };
+
+        let connection = Draining::new(protocol, state.clone(), &self.connections);
+
+        match self.config.header_read_timeout {
+            Some(timeout) => {
+                let watchdog = HeaderTimeout::new(state, timeout, &self.handle)?;
+                self.handle.spawn(connection.select(watchdog).then(|_| Ok(())));
+            },
+            None => self.handle.spawn(connection),
+        }
+
+        Ok(())
+    }
+}
+
+/// The open connections, so they can be told to finish on shutdown.
+#[derive(Default)]
+struct Connections {
+    next_id: Cell<usize>,
+    open: RefCell<HashMap<usize, Option<unsync::oneshot::Sender<()>>>>,
+    drained: RefCell<Option<Task>>,
+}
+
+impl Connections {
+    fn len(&self) -> usize {
+        self.open.borrow().len()
+    }
+
+    /// Asks all open connections to close after their current request.
+    fn shutdown(&self) {
+        for closing in self.open.borrow_mut().values_mut() {
+            if let Some(closing) = closing.take() {
+                let _ = closing.send(());
+            }
+        }
+    }
+}
+
+/// Keeps a connection listed as open until dropped.
+struct Registration {
+    id: usize,
+    connections: Rc<Connections>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut open = self.connections.open.borrow_mut();
+        open.remove(&self.id);
+
+        if open.is_empty() {
+            if let Some(task) = self.connections.drained.borrow_mut().take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+/// Resolves once all connections are closed.
+struct Drained(Rc<Connections>);
+
+impl Future for Drained {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        if self.0.len() == 0 {
+            Ok(Async::Ready(()))
+        } else {
+            *self.0.drained.borrow_mut() = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// Everything needed to serve a connection once its protocol is known.
+struct Protocols<R> where R: Resource + Default + Debug + Send {
+    http: Http<hyper::Chunk>,
+    max_header_size: Option<usize>,
+    service: GerustService<R>,
+}
+
+impl<R> Protocols<R> where R: Resource + Default + Debug + Send {
+    fn serve<I>(self, socket: Rewind<I>, http2: bool) -> Protocol<I, R>
+        where I: AsyncRead + AsyncWrite + 'static
+    {
+        if http2 {
+            Protocol::Http2(Box::new(http2::Connection::new(socket, self.service, self.max_header_size)))
+        } else {
+            let socket = Gated::new(socket, self.service.expect.clone());
+            Protocol::Http1(Box::new(self.http.serve_connection(socket, self.service)))
+        }
+    }
+}
+
+/// A connection speaking either HTTP version.
+enum Protocol<I, R> where R: Resource + Default + Debug + Send {
+    /// Waiting for the first bytes to tell the versions apart.
+    Detecting(Preface<I>, Option<Protocols<R>>),
+    Http1(Box<hyper::server::Connection<Gated<Rewind<I>>, GerustService<R>>>),
+    Http2(Box<http2::Connection<Rewind<I>, R>>),
+    /// Done with HTTP/1: shutting down, or upgraded to a WebSocket.
+    Finishing(Box<dyn Future<Item=(), Error=()>>),
+}
+
+impl<I, R> Protocol<I, R>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
+{
+    /// Lets the current requests finish, but doesn't accept new ones.
+    fn drain(&mut self) {
+        match *self {
+            Protocol::Detecting(..) => {},
+            Protocol::Http1(ref mut connection) => connection.disable_keep_alive(),
+            Protocol::Http2(ref mut connection) => connection.graceful_shutdown(),
+            Protocol::Finishing(_) => {},
+        }
+    }
+}
+
+/// Hands an HTTP/1 connection that is done to the WebSocket handler of
+/// its last response, or shuts it down.
+fn finish<I, R>(connection: hyper::server::Connection<Gated<Rewind<I>>, GerustService<R>>) -> Box<dyn Future<Item=(), Error=()>>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
+{
+    let parts = connection.into_parts();
+    let handler = parts.service.state.upgrade.borrow_mut().take();
+
+    match handler {
+        Some(handler) => {
+            parts.service.state.upgraded.set(true);
+            // Frames the client sent right after the handshake were read already.
+            handler(WebSocket::new(Rewind::new(parts.io.io, parts.read_buf)))
+        },
+        None => Box::new(::tokio_io::io::shutdown(parts.io).then(|result| {
+            if let Err(e) = result {
+                debug!("connection shutdown error: {}", e);
+            }
+            Ok(())
+        })),
+    }
+}
+
+impl<I, R> Future for Protocol<I, R>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let next = match *self {
+                Protocol::Detecting(ref mut preface, ref mut protocols) => match preface.poll() {
+                    Ok(Async::Ready((http2, socket))) => {
+                        protocols.take().expect("polled Protocol after completion").serve(socket, http2)
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        debug!("connection error: {}", e);
+                        return Err(());
+                    },
+                },
+                // The socket is kept open for a possible upgrade.
+                Protocol::Http1(ref mut connection) => match connection.poll_without_shutdown() {
+                    Ok(Async::Ready(())) => Protocol::Finishing(Box::new(future::ok(()))),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        debug!("connection error: {}", e);
+                        return Err(());
+                    },
+                },
+                Protocol::Http2(ref mut connection) => {
+                    return connection.poll().map_err(|e| debug!("HTTP/2 connection error: {}", e));
+                },
+                Protocol::Finishing(ref mut finishing) => return finishing.poll(),
+            };
+
+            let next = match mem::replace(self, next) {
+                Protocol::Http1(connection) => Protocol::Finishing(finish(*connection)),
+                _ => continue,
+            };
+
+            *self = next;
+        }
+    }
+}
+
+/// A connection that stops taking new requests once the server shuts down.
+struct Draining<I, R> where R: Resource + Default + Debug + Send {
+    connection: Protocol<I, R>,
+    state: Rc<ConnectionState>,
+    closing: Option<unsync::oneshot::Receiver<()>>,
+    _registration: Registration,
+}
+
+impl<I, R> Draining<I, R>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
+{
+    fn new(connection: Protocol<I, R>,
+           state: Rc<ConnectionState>,
+           connections: &Rc<Connections>) -> Draining<I, R> {
+        let id = connections.next_id.get();
+        connections.next_id.set(id.wrapping_add(1));
+
+        let (sx, rx) = unsync::oneshot::channel();
+        connections.open.borrow_mut().insert(id, Some(sx));
+
+        Draining {
+            connection,
+            state,
+            closing: Some(rx),
+            _registration: Registration { id, connections: connections.clone() },
+        }
+    }
+}
+
+impl<I, R> Future for Draining<I, R>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
 {
-    type Request = http::Request<hyper::Body>;
-    type Response = http::Response<hyper::Body>;
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        let closing = match self.closing.as_mut().map(|closing| closing.poll()) {
+            Some(Ok(Async::NotReady)) | None => false,
+            Some(_) => true,
+        };
+
+        if closing {
+            self.closing = None;
+
+            // Nobody waits for a response, so there's nothing to finish.
+            if self.state.in_flight.get() == 0 {
+                return Ok(Async::Ready(()));
+            }
+
+            self.connection.drain();
+        }
+
+        self.connection.poll()
+    }
+}
+
+/// Bookkeeping shared by all requests on one connection.
+struct ConnectionState {
+    in_flight: Cell<usize>,
+    idle_since: Cell<Instant>,
+    /// The handler of a `101` response, taking over once it was sent.
+    upgrade: RefCell<Option<websocket::Handler>>,
+    upgraded: Cell<bool>,
+}
+
+impl ConnectionState {
+    fn new() -> ConnectionState {
+        ConnectionState {
+            in_flight: Cell::new(0),
+            idle_since: Cell::new(Instant::now()),
+            upgrade: RefCell::new(None),
+            upgraded: Cell::new(false),
+        }
+    }
+}
+
+/// Marks a request as in flight until its response body is dropped.
+struct InFlight(Rc<ConnectionState>);
+
+impl InFlight {
+    fn new(state: Rc<ConnectionState>) -> InFlight {
+        state.in_flight.set(state.in_flight.get() + 1);
+        InFlight(state)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.set(self.0.in_flight.get() - 1);
+        self.0.idle_since.set(Instant::now());
+    }
+}
+
+/// Resolves once a connection waited longer than `timeout` for a request.
+struct HeaderTimeout {
+    state: Rc<ConnectionState>,
+    timeout: Duration,
+    timer: Timeout,
+}
+
+impl HeaderTimeout {
+    fn new(state: Rc<ConnectionState>, timeout: Duration, handle: &Handle) -> io::Result<HeaderTimeout> {
+        let timer = Timeout::new(timeout, handle)?;
+        Ok(HeaderTimeout { state, timeout, timer })
+    }
+}
+
+impl Future for HeaderTimeout {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            match self.timer.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {},
+                Err(e) => {
+                    warn!("header read timer failed: {}", e);
+                    return Err(());
+                }
+            }
+
+            // WebSockets aren't HTTP anymore, the handler times out idle clients.
+            if self.state.upgraded.get() {
+                return Ok(Async::NotReady);
+            }
+
+            let now = Instant::now();
+
+            let deadline = if self.state.in_flight.get() > 0 {
+                // Reading the body and writing the response is not limited.
+                now + self.timeout
+            } else {
+                self.state.idle_since.get() + self.timeout
+            };
+
+            if deadline <= now {
+                debug!("closing connection: no request head within {:?}", self.timeout);
+                return Ok(Async::Ready(()));
+            }
+
+            self.timer.reset(deadline);
+        }
+    }
+}
+
+/// A response body that keeps its request marked as in flight.
+struct TrackedBody {
+    body: hyper::Body,
+    _in_flight: InFlight,
+}
+
+impl Stream for TrackedBody {
+    type Item = hyper::Chunk;
     type Error = hyper::Error;
-    type Future = Box<Future<Item=Self::Response, Error=Self::Error>>;
 
-    fn call(&self, req: Self::Request) -> Self::Future {
-        let (sx, rx): (futures::sync::oneshot::Sender<Self::Response>, _) = oneshot::channel::<Self::Response>();
+    fn poll(&mut self) -> Poll<Option<hyper::Chunk>, hyper::Error> {
+        self.body.poll()
+    }
+}
 
-        let app_reactor = self.handle.clone();
-        let app_threadpool = self.pool.clone();
+struct GerustService<R> where R: Resource + Default + Debug + Send {
+    pool: Arc<futures_cpupool::CpuPool>,
+    handle: Handle,
+    request_timeout: Option<Duration>,
+    scheme: Scheme,
+    state: Rc<ConnectionState>,
+    /// Gates the `100 Continue` of HTTP/1 requests.
+    expect: ContinueGate,
+    resource: PhantomData<R>
+}
 
-        let f = futures::future::lazy(move || {
-            let resource = R::default();
-            let mut flow = HttpFlow::new(app_threadpool, app_reactor);
+impl<R> GerustService<R> where R: Resource + Default + Debug + Send {
+    /// Runs the flow for `request`, whichever HTTP version it came in.
+    fn respond(&self, mut request: http::Request<hyper::Body>)
+        -> Box<dyn Future<Item=http::Response<TrackedBody>, Error=hyper::Error>>
+    {
+        let in_flight = InFlight::new(self.state.clone());
 
-            flow.execute(resource, req, sx);
-            futures::future::ok::<(), ()>(())
-        });
+        let resource = R::default();
+        let mut flow = HttpFlow::new(self.pool.clone());
 
-        let thread = self.pool.spawn(f);
+        request.extensions_mut().insert(self.scheme.clone());
 
-        self.handle.spawn(move |_handle| { thread } );
+        if let Some(expect) = self.expect.expect(&request) {
+            request.extensions_mut().insert(expect);
+        }
 
         // TODO: don't unwrap the response builder result here
-        Box::from(rx.or_else(|_| Ok(http::response::Builder::new()
+        let response = flow.handle(resource, request).or_else(|_| Ok::<_, hyper::Error>(http::response::Builder::new()
             .status(501).header(http::header::CONTENT_TYPE, "text/html")
-            .body(b"<body><head></head><h1>Internal Server Error</h1></body>".as_ref().into()).unwrap())))
-    }
-}
+            .body(b"<body><head></head><h1>Internal Server Error</h1></body>".as_ref().into()).unwrap()));
 
-// TODO: Relax these bounds
-pub fn run_server<R: Resource + Debug + Default + Sync>(threads: usize) {
-    let addr = ([127, 0, 0, 1], 3000).into();
+        let response: Box<dyn Future<Item=_, Error=hyper::Error>> = match self.request_timeout {
+            Some(timeout) => {
+                let timer = match Timeout::new(timeout, &self.handle) {
+                    Ok(timer) => timer,
+                    Err(e) => return Box::new(future::err(e.into())),
+                };
+
+                Box::new(response.select2(timer).then(|result| match result {
+                    Ok(Either::A((response, _))) => Ok(response),
+                    Ok(Either::B(_)) => {
+                        let status = http::StatusCode::SERVICE_UNAVAILABLE;
+                        Ok(http::response::Builder::new()
+                            .status(status)
+                            .body(status.canonical_reason().unwrap().into()).unwrap())
+                    },
+                    Err(Either::A((e, _))) => Err(e),
+                    Err(Either::B((e, _))) => Err(e.into()),
+                }))
+            },
+            None => Box::new(response),
+        };
+
+        let state = self.state.clone();
+        let expect = self.expect.clone();
 
-    let core = tokio_core::reactor::Core::new().unwrap();
+        Box::new(response.map(move |mut response| {
+            // The client still waits to send a body nobody reads.
+            if expect.respond() {
+                response.headers_mut().insert(http::header::CONNECTION, http::header::HeaderValue::from_static("close"));
+            }
 
-    let pool = Arc::new(futures_cpupool::CpuPool::new(threads));
+            if response.status() == http::StatusCode::SWITCHING_PROTOCOLS {
+                let handler = response.extensions().get::<websocket::Upgrade>().and_then(websocket::Upgrade::take);
+                *state.upgrade.borrow_mut() = handler;
+            }
+
+            let (parts, body) = response.into_parts();
+            let body = TrackedBody { body, _in_flight: in_flight };
+            http::Response::from_parts(parts, body)
+        }))
+    }
+}
 
-    let remote = core.remote();
+impl<R> hyper::server::Service for GerustService<R>
+    where R: Resource + Default + Debug + Send
+{
+    type Request = hyper::Request;
+    type Response = hyper::Response<TrackedBody>;
+    type Error = hyper::Error;
+    type Future = Box<dyn Future<Item=Self::Response, Error=Self::Error>>;
 
-    let service = move || {
-        Ok(GerustService { pool: pool.clone(), handle: remote.clone(), resource: PhantomData::<R> })
-    };
+    fn call(&self, req: Self::Request) -> Self::Future {
+        Box::new(self.respond(req.into()).map(Into::into))
+    }
+}
 
-    let server = Http::new().bind_compat(&addr, service).unwrap();
-    //server.no_proto();
-    info!("Listening on http://{} with 1 thread.", server.local_addr().unwrap());
-    server.run().unwrap();
+/// Serves `R` on `127.0.0.1:3000` with `threads` worker threads until
+/// `SIGTERM` or `SIGINT`.
+// TODO: Relax these bounds
+pub fn run_server<R: Resource + Debug + Default + Send>(threads: usize) -> Result<(), Error> {
+    ServerBuilder::new().threads(threads).build::<R>()?.run()
 }
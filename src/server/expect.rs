@@ -0,0 +1,176 @@
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use futures::Poll;
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use http;
+
+/// What hyper writes as soon as it read the head of a request with
+/// `Expect: 100-continue`, before the flow had a say.
+const CONTINUE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+/// Holds back the `100 Continue` of an HTTP/1 connection until the flow
+/// decided whether it wants the request body.
+#[derive(Clone, Default)]
+pub struct ContinueGate(Arc<Mutex<State>>);
+
+#[derive(Default)]
+struct State {
+    /// hyper is about to write a `100 Continue`.
+    due: bool,
+    /// The `100 Continue` hyper wrote, and how much of it went out since.
+    held: Option<usize>,
+    /// Whether the flow wants the body, once it decided.
+    decision: Option<bool>,
+    /// The connection task, to wake up when the held bytes can go out.
+    task: Option<Task>,
+}
+
+impl ContinueGate {
+    /// Starts gating the `100 Continue` for `request`, if it expects one.
+    ///
+    /// Returns the handle the flow sends it with.
+    pub fn expect<B>(&self, request: &http::Request<B>) -> Option<Continue> {
+        let expects = request.version() == http::Version::HTTP_11 && request.headers()
+            .get(http::header::EXPECT)
+            .is_some_and(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"));
+
+        if !expects {
+            return None;
+        }
+
+        *self.0.lock().unwrap() = State { due: true, ..State::default() };
+        Some(Continue(self.clone()))
+    }
+
+    /// Refuses the body if the flow didn't ask for it by the time the
+    /// response is ready.
+    ///
+    /// Returns whether the client is still waiting to send it, in which
+    /// case the connection can't be reused.
+    pub fn respond(&self) -> bool {
+        let mut state = self.0.lock().unwrap();
+
+        if state.decision.is_some() || !(state.due || state.held.is_some()) {
+            return false;
+        }
+
+        state.decision = Some(false);
+        state.held = None;
+        true
+    }
+}
+
+/// Lets the client send a request body it held back for
+/// `Expect: 100-continue`.
+///
+/// The server adds it to the extensions of such requests.
+#[derive(Clone)]
+pub struct Continue(ContinueGate);
+
+impl Continue {
+    pub fn send(&self) {
+        let mut state = (self.0).0.lock().unwrap();
+
+        if state.decision.is_none() {
+            state.decision = Some(true);
+
+            if let Some(task) = state.task.take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+/// A socket whose writes pass through a `ContinueGate`.
+pub struct Gated<I> {
+    pub io: I,
+    gate: ContinueGate,
+}
+
+impl<I> Gated<I> {
+    pub fn new(io: I, gate: ContinueGate) -> Gated<I> {
+        Gated { io, gate }
+    }
+}
+
+impl<I: Write> Gated<I> {
+    /// Writes a held `100 Continue` the flow released.
+    fn release(&mut self, state: &mut State) -> io::Result<()> {
+        if state.decision != Some(true) {
+            return Ok(());
+        }
+
+        while let Some(written) = state.held {
+            match self.io.write(&CONTINUE[written..])? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                n if written + n == CONTINUE.len() => state.held = None,
+                n => state.held = Some(written + n),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: Read + Write> Read for Gated<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let gate = self.gate.clone();
+        let mut state = gate.0.lock().unwrap();
+
+        match self.release(&mut state) {
+            // The client doesn't send the body before it got everything.
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            result => result?,
+        }
+
+        if state.held.is_some() {
+            state.task = Some(task::current());
+        }
+
+        drop(state);
+        self.io.read(buf)
+    }
+}
+
+impl<I: Write> Write for Gated<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let gate = self.gate.clone();
+        let mut state = gate.0.lock().unwrap();
+
+        if state.due && buf.starts_with(CONTINUE) {
+            state.due = false;
+
+            match state.decision {
+                Some(true) => {},
+                Some(false) => return Ok(CONTINUE.len()),
+                None => {
+                    state.held = Some(0);
+                    state.task = Some(task::current());
+                    return Ok(CONTINUE.len());
+                },
+            }
+        }
+
+        self.release(&mut state)?;
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let gate = self.gate.clone();
+        let mut state = gate.0.lock().unwrap();
+
+        self.release(&mut state)?;
+        self.io.flush()
+    }
+}
+
+impl<I: AsyncRead + AsyncWrite> AsyncRead for Gated<I> {}
+
+impl<I: AsyncWrite> AsyncWrite for Gated<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
@@ -0,0 +1,314 @@
+//! Serving `GerustService` over HTTP/2.
+//!
+//! hyper 0.11 only speaks HTTP/1, so HTTP/2 connections are handed to
+//! `h2` directly. Every stream runs through the same `HttpFlow` as an
+//! HTTP/1 request, and its response body is streamed back respecting
+//! HTTP/2 flow control.
+
+use std::cmp;
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+
+use bytes::Bytes;
+use futures::future::{self, Either};
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sync::mpsc;
+use h2::{self, Reason, RecvStream, SendStream};
+use h2::server::{self, Handshake, SendResponse};
+use http::{self, header};
+use hyper;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use resource::Resource;
+
+use super::{GerustService, TrackedBody};
+
+/// The client connection preface that starts every HTTP/2 connection.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Reads just enough of a connection to tell whether the client speaks
+/// HTTP/2 with prior knowledge.
+///
+/// Resolves to `true` for HTTP/2 along with the socket, which replays the
+/// bytes read so far.
+pub struct Preface<I> {
+    io: Option<I>,
+    buf: Vec<u8>,
+}
+
+impl<I> Preface<I> {
+    pub fn new(io: I) -> Preface<I> {
+        Preface { io: Some(io), buf: Vec::with_capacity(PREFACE.len()) }
+    }
+}
+
+impl<I: AsyncRead> Future for Preface<I> {
+    type Item = (bool, Rewind<I>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(bool, Rewind<I>), io::Error> {
+        while self.buf.len() < PREFACE.len() && PREFACE.starts_with(&self.buf) {
+            let mut chunk = [0; PREFACE.len()];
+            let wanted = PREFACE.len() - self.buf.len();
+
+            let read = match self.io.as_mut().expect("polled Preface after completion").read(&mut chunk[..wanted]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(e) => return Err(e),
+            };
+
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+
+        let http2 = self.buf == PREFACE;
+        let io = self.io.take().expect("polled Preface after completion");
+
+        Ok(Async::Ready((http2, Rewind::new(io, self.buf.split_off(0).into()))))
+    }
+}
+
+/// A socket that first returns bytes that were already read from it.
+pub struct Rewind<I> {
+    prefix: Bytes,
+    io: I,
+}
+
+impl<I> Rewind<I> {
+    pub fn new(io: I, prefix: Bytes) -> Rewind<I> {
+        Rewind { prefix, io }
+    }
+}
+
+impl<I: Read> Read for Rewind<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.prefix.is_empty() {
+            return self.io.read(buf);
+        }
+
+        let n = cmp::min(buf.len(), self.prefix.len());
+        buf[..n].copy_from_slice(&self.prefix.split_to(n));
+        Ok(n)
+    }
+}
+
+impl<I: Write> Write for Rewind<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I: AsyncRead> AsyncRead for Rewind<I> {}
+
+impl<I: AsyncWrite> AsyncWrite for Rewind<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+enum State<I> {
+    Handshaking(Handshake<I, Bytes>),
+    Serving(server::Connection<I, Bytes>),
+}
+
+/// An HTTP/2 connection, spawning a task per stream.
+pub struct Connection<I, R> where R: Resource + Default + Debug + Send {
+    state: State<I>,
+    service: GerustService<R>,
+    draining: bool,
+}
+
+impl<I, R> Connection<I, R>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
+{
+    pub fn new(io: I, service: GerustService<R>, max_header_size: Option<usize>) -> Connection<I, R> {
+        let mut builder = server::Builder::new();
+        if let Some(size) = max_header_size {
+            builder.max_header_list_size(size as u32);
+        }
+
+        Connection {
+            state: State::Handshaking(builder.handshake(io)),
+            service,
+            draining: false,
+        }
+    }
+
+    /// Sends a `GOAWAY` and closes the connection once the open streams
+    /// are answered.
+    pub fn graceful_shutdown(&mut self) {
+        self.draining = true;
+
+        if let State::Serving(ref mut connection) = self.state {
+            connection.graceful_shutdown();
+        }
+    }
+
+    fn serve_stream(&self, request: http::Request<RecvStream>, respond: SendResponse<Bytes>) {
+        let handle = self.service.handle.clone();
+        let (parts, received) = request.into_parts();
+
+        let body = if received.is_end_stream() {
+            hyper::Body::empty()
+        } else {
+            let (sender, body) = hyper::Body::pair();
+            handle.spawn(receive(received, sender));
+            body
+        };
+
+        let responding = self.service.respond(http::Request::from_parts(parts, body));
+        handle.spawn(send(responding, respond));
+    }
+}
+
+impl<I, R> Future for Connection<I, R>
+    where I: AsyncRead + AsyncWrite + 'static,
+          R: Resource + Default + Debug + Send
+{
+    type Item = ();
+    type Error = h2::Error;
+
+    fn poll(&mut self) -> Poll<(), h2::Error> {
+        loop {
+            let next = match self.state {
+                State::Handshaking(ref mut handshake) => {
+                    let mut connection = match handshake.poll()? {
+                        Async::Ready(connection) => connection,
+                        Async::NotReady => return Ok(Async::NotReady),
+                    };
+                    if self.draining {
+                        connection.graceful_shutdown();
+                    }
+                    State::Serving(connection)
+                },
+                State::Serving(ref mut connection) => match connection.poll()? {
+                    Async::Ready(Some((request, respond))) => {
+                        self.serve_stream(request, respond);
+                        continue;
+                    },
+                    Async::Ready(None) => return Ok(Async::Ready(())),
+                    Async::NotReady => return Ok(Async::NotReady),
+                },
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+/// Forwards a stream's request body to the body handed to the flow.
+fn receive(mut body: RecvStream, sender: mpsc::Sender<Result<hyper::Chunk, hyper::Error>>) -> impl Future<Item=(), Error=()> {
+    let mut release = body.release_capacity().clone();
+
+    let chunks = body.then(move |result| Ok(match result {
+        Ok(bytes) => {
+            let _ = release.release_capacity(bytes.len());
+            Ok(hyper::Chunk::from(bytes))
+        },
+        Err(e) => Err(hyper::Error::Io(io::Error::other(e))),
+    }));
+
+    sender.send_all(chunks).then(|_| Ok(()))
+}
+
+/// Sends the head of a response and then streams its body.
+fn send<F>(responding: F, mut respond: SendResponse<Bytes>) -> impl Future<Item=(), Error=()>
+    where F: Future<Item=http::Response<TrackedBody>, Error=hyper::Error>
+{
+    responding.then(move |result| {
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("HTTP/2 stream failed: {}", e);
+                respond.send_reset(Reason::INTERNAL_ERROR);
+                return Either::A(future::ok(()));
+            },
+        };
+
+        let (mut parts, body) = response.into_parts();
+
+        // Connection-specific headers are not allowed in HTTP/2.
+        for name in &[header::CONNECTION, header::TRANSFER_ENCODING, header::UPGRADE] {
+            parts.headers.remove(name);
+        }
+        parts.headers.remove("keep-alive");
+        parts.headers.remove("proxy-connection");
+
+        match respond.send_response(http::Response::from_parts(parts, ()), false) {
+            Ok(stream) => Either::B(SendBody { body, stream, pending: None }),
+            Err(e) => {
+                debug!("can't send HTTP/2 response: {}", e);
+                Either::A(future::ok(()))
+            },
+        }
+    })
+}
+
+/// Writes a response body as DATA frames, as the peer's window allows.
+struct SendBody {
+    body: TrackedBody,
+    stream: SendStream<Bytes>,
+    pending: Option<Bytes>,
+}
+
+impl SendBody {
+    fn poll_send(&mut self) -> Poll<(), h2::Error> {
+        loop {
+            if let Some(mut data) = self.pending.take() {
+                self.stream.reserve_capacity(data.len());
+
+                let capacity = match self.stream.poll_capacity()? {
+                    Async::Ready(Some(capacity)) => capacity,
+                    // The stream was reset, nobody reads the rest.
+                    Async::Ready(None) => return Ok(Async::Ready(())),
+                    Async::NotReady => {
+                        self.pending = Some(data);
+                        return Ok(Async::NotReady);
+                    },
+                };
+
+                let chunk = data.split_to(cmp::min(capacity, data.len()));
+                self.stream.send_data(chunk, false)?;
+
+                if !data.is_empty() {
+                    self.pending = Some(data);
+                }
+
+                continue;
+            }
+
+            match self.body.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    let data: Bytes = chunk.into();
+                    if !data.is_empty() {
+                        self.pending = Some(data);
+                    }
+                },
+                Ok(Async::Ready(None)) => {
+                    self.stream.send_data(Bytes::new(), true)?;
+                    return Ok(Async::Ready(()));
+                },
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    debug!("HTTP/2 response body failed: {}", e);
+                    self.stream.send_reset(Reason::INTERNAL_ERROR);
+                    return Ok(Async::Ready(()));
+                },
+            }
+        }
+    }
+}
+
+impl Future for SendBody {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        self.poll_send().map_err(|e| debug!("can't send HTTP/2 response body: {}", e))
+    }
+}
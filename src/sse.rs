@@ -0,0 +1,346 @@
+//! Server-Sent Events, long-lived `text/event-stream` responses.
+//!
+//! An output handler starts the stream on its response and hands the
+//! `EventSender` to whatever produces events, e.g. a thread:
+//!
+//! ```rust
+//! # extern crate gerust;
+//! # extern crate mime;
+//! # use std::thread;
+//! # use gerust::context::Context;
+//! # use gerust::flow::DelayedResponse;
+//! # use gerust::resource::{Resource, ProvidedPair, Deferred};
+//! # use gerust::response::Entity;
+//! # use gerust::sse::{self, Event};
+//! # #[derive(Default)]
+//! # struct Ticker;
+//! # impl Resource for Ticker {
+//! #     fn content_types_provided(&self, _: &Context) -> &'static [ProvidedPair<Self>] {
+//! #         &[ProvidedPair(mime::TEXT_EVENT_STREAM, Self::to_events)]
+//! #     }
+//! # }
+//! impl Ticker {
+//!     fn to_events(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+//!         let start = context.last_event_id().and_then(|id| id.parse().ok()).map_or(0, |id: u64| id + 1);
+//!         let mut events = sse::start(response).unwrap();
+//!
+//!         thread::spawn(move || {
+//!             for tick in start.. {
+//!                 // Fails once the client went away.
+//!                 if events.send_event(Event::new(format!("tick {}", tick)).id(tick.to_string())).is_err() {
+//!                     break;
+//!                 }
+//!             }
+//!         });
+//!
+//!         ().into()
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! The stream ends once every `EventSender` is dropped.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend};
+use http;
+
+use body::{self, Body};
+use chunk::Chunk;
+use response::{DelayedResponse, Error};
+
+/// Starts an event stream on `response`.
+///
+/// Sets `Content-Type: text/event-stream` and disables caching, then
+/// commits the response so the client sees the stream open right away.
+pub fn start<B: Body>(response: &mut DelayedResponse<B>) -> Result<EventSender<B>, Error> {
+    response
+        .header(http::header::CONTENT_TYPE, "text/event-stream")?
+        .header(http::header::CACHE_CONTROL, "no-cache")?;
+
+    response.sender().map(|sender| EventSender { sender })
+}
+
+/// A single event.
+///
+/// Multi-line data is sent as one `data` field per line; line breaks in
+/// the other fields are dropped, as they would end the field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new<D: Into<String>>(data: D) -> Event {
+        Event { data: data.into(), ..Event::default() }
+    }
+
+    /// The event type, dispatched to listeners of that name.
+    pub fn event<E: Into<String>>(mut self, event: E) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// The id the client sends back as `Last-Event-ID` when reconnecting.
+    pub fn id<I: Into<String>>(mut self, id: I) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// How long the client waits before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+
+        if let Some(ref id) = self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.trim_end_matches('\r'))?;
+        }
+
+        writeln!(f)
+    }
+}
+
+impl From<Event> for Chunk {
+    fn from(event: Event) -> Chunk {
+        event.to_string().into()
+    }
+}
+
+fn single_line(field: &str) -> String {
+    field.chars().filter(|&c| c != '\r' && c != '\n').collect()
+}
+
+/// Sends events to a client.
+///
+/// Cloned senders write to the same stream. Sending fails with
+/// `Error::Closed` once the client disconnected, so producers know to
+/// stop. `EventSender` is also a `Sink` of events for use in futures.
+pub struct EventSender<B: Body = ::Body> {
+    sender: body::Sender<B>,
+}
+
+impl<B: Body> EventSender<B> {
+    /// Sends `event`, blocking while the client is behind.
+    ///
+    /// Meant for producer threads; futures should use the `Sink`.
+    pub fn send_event(&mut self, event: Event) -> Result<(), Error> {
+        self.send_chunk(event.into())
+    }
+
+    /// Sends a comment, which clients ignore. Keeps idle connections
+    /// from timing out and notices disconnected clients.
+    pub fn comment(&mut self, comment: &str) -> Result<(), Error> {
+        let comment = comment.split('\n').fold(String::new(), |lines, line| lines + ":" + line.trim_end_matches('\r') + "\n");
+
+        self.send_chunk((comment + "\n").into())
+    }
+
+    /// Whether the client disconnected.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Sends an empty comment every `interval`, until the returned
+    /// `Heartbeat` is dropped or the client went away.
+    ///
+    /// The heartbeats of all streams come from one background thread.
+    /// A heartbeat is skipped while the client is behind on events. The
+    /// heartbeat holds a sender, so the stream doesn't end before it is
+    /// dropped. Without the thread, e.g. if it can't be spawned, there
+    /// are no heartbeats, which is logged.
+    pub fn heartbeat(&self, interval: Duration) -> Heartbeat {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let mut sender = self.sender.clone();
+        let beat = move || match sender.try_send(Ok(Chunk::from(":\n\n").into())) {
+            Ok(()) => true,
+            Err(e) => e.is_full(),
+        };
+
+        if !send(Command::Start(id, interval, Box::new(beat))) {
+            warn!("the heartbeat thread is gone, sending no heartbeats");
+        }
+
+        Heartbeat { id }
+    }
+
+    fn send_chunk(&mut self, chunk: Chunk) -> Result<(), Error> {
+        (&mut self.sender).send(Ok(chunk.into())).wait().map(|_| ()).map_err(|_| Error::Closed)
+    }
+}
+
+impl<B: Body> Clone for EventSender<B> {
+    fn clone(&self) -> Self {
+        EventSender { sender: self.sender.clone() }
+    }
+}
+
+impl<B: Body> fmt::Debug for EventSender<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventSender").field("closed", &self.is_closed()).finish()
+    }
+}
+
+impl<B: Body> Sink for EventSender<B> {
+    type SinkItem = Event;
+    type SinkError = Error;
+
+    fn start_send(&mut self, event: Event) -> StartSend<Event, Error> {
+        // Only serialize the event once the channel has room for it.
+        match self.sender.poll_ready() {
+            Ok(Async::Ready(())) => (),
+            Ok(Async::NotReady) => return Ok(AsyncSink::NotReady(event)),
+            Err(_) => return Err(Error::Closed),
+        }
+
+        match self.sender.start_send(Ok(Chunk::from(event).into())) {
+            Ok(AsyncSink::Ready) => Ok(AsyncSink::Ready),
+            Ok(AsyncSink::NotReady(_)) => unreachable!("the sender was ready"),
+            Err(_) => Err(Error::Closed),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Error> {
+        self.sender.poll_complete().map_err(|_| Error::Closed)
+    }
+
+    fn close(&mut self) -> Poll<(), Error> {
+        self.sender.close().map_err(|_| Error::Closed)
+    }
+}
+
+/// Stops the heartbeat of an `EventSender` when dropped.
+#[derive(Debug)]
+pub struct Heartbeat {
+    id: usize,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        send(Command::Stop(self.id));
+    }
+}
+
+/// Sends a heartbeat, returning whether the stream is still open.
+type Beat = Box<dyn FnMut() -> bool + Send>;
+
+enum Command {
+    Start(usize, Duration, Beat),
+    Stop(usize),
+}
+
+lazy_static! {
+    /// `None` if the heartbeat thread couldn't be spawned.
+    static ref HEARTBEATS: Option<Mutex<mpsc::Sender<Command>>> = {
+        let (commands, received) = mpsc::channel();
+
+        let spawned = thread::Builder::new()
+            .name("gerust-sse-heartbeat".to_string())
+            .spawn(move || heartbeats(received));
+
+        match spawned {
+            Ok(_) => Some(Mutex::new(commands)),
+            Err(e) => {
+                warn!("cannot spawn the heartbeat thread: {}", e);
+                None
+            },
+        }
+    };
+}
+
+/// Passes `command` to the heartbeat thread, returning whether it is there.
+fn send(command: Command) -> bool {
+    match *HEARTBEATS {
+        Some(ref commands) => commands.lock().unwrap_or_else(PoisonError::into_inner).send(command).is_ok(),
+        None => false,
+    }
+}
+
+/// Sends the heartbeats in the order they are due, taking new ones in
+/// between.
+fn heartbeats(commands: mpsc::Receiver<Command>) {
+    let mut beats: HashMap<usize, (Duration, Beat)> = HashMap::new();
+    // Stopped heartbeats stay queued until due, then are skipped.
+    let mut due: BinaryHeap<Reverse<(Instant, usize)>> = BinaryHeap::new();
+
+    loop {
+        let command = match due.peek() {
+            Some(&Reverse((at, _))) => match commands.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                Ok(command) => Some(command),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            },
+            None => match commands.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            },
+        };
+
+        match command {
+            Some(Command::Start(id, interval, beat)) => {
+                due.push(Reverse((Instant::now() + interval, id)));
+                beats.insert(id, (interval, beat));
+            },
+            Some(Command::Stop(id)) => {
+                beats.remove(&id);
+            },
+            None => {},
+        }
+
+        let now = Instant::now();
+        while let Some(&Reverse((at, id))) = due.peek() {
+            if at > now {
+                break;
+            }
+            due.pop();
+
+            let open = match beats.get_mut(&id) {
+                // A panicking beat ends its heartbeat, not the thread.
+                Some(&mut (interval, ref mut beat)) => match panic::catch_unwind(AssertUnwindSafe(beat)) {
+                    Ok(open) => open.then_some(interval),
+                    Err(_) => {
+                        warn!("a heartbeat panicked, stopping it");
+                        None
+                    },
+                },
+                None => continue,
+            };
+
+            match open {
+                Some(interval) => due.push(Reverse((now + interval, id))),
+                None => {
+                    beats.remove(&id);
+                },
+            }
+        }
+    }
+}
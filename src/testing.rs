@@ -0,0 +1,224 @@
+//! Testing `Resource` implementations.
+//!
+//! `RequestBuilder` and `execute` run the flow directly, without a server.
+//! `TestServer` runs a real one for end-to-end tests.
+//!
+//! ```
+//! # extern crate gerust;
+//! # extern crate http;
+//! # extern crate mime;
+//! # extern crate futures;
+//! # use gerust::context::Context;
+//! # use gerust::flow::DelayedResponse;
+//! # use gerust::resource::{Resource, ProvidedPair, Deferred};
+//! # use gerust::response::Entity;
+//! use gerust::testing::RequestBuilder;
+//!
+//! # #[derive(Debug)]
+//! # struct Hello;
+//! # impl Resource for Hello {
+//! #     fn content_types_provided(&self, _: &Context) -> &'static [ProvidedPair<Self>] {
+//! #         &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+//! #     }
+//! # }
+//! # impl Hello {
+//! #     fn to_text(&mut self, _: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+//! #         "Hello".into()
+//! #     }
+//! # }
+//! # fn main() {
+//! RequestBuilder::get("/hello")
+//!     .header("Accept", "text/plain")
+//!     .execute(Hello)
+//!     .assert_status(http::StatusCode::OK)
+//!     .assert_visited("g7")
+//!     .assert_body("Hello");
+//! # }
+//! ```
+
+use std::fmt::Debug;
+use std::str;
+
+use futures::{Future, Stream};
+use http::{self, HttpTryFrom};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+use chunk::Chunk;
+use flow::{self, Trace};
+use resource::Resource;
+use Body;
+
+mod server;
+
+pub use self::server::{ClientResponse, TestClient, TestServer};
+
+/// Builds requests to `execute` resources with.
+///
+/// # Panics
+///
+/// `build` and `execute` panic if a method, URI or header is invalid.
+#[derive(Debug)]
+pub struct RequestBuilder {
+    builder: http::request::Builder,
+    body: Chunk,
+}
+
+impl RequestBuilder {
+    pub fn new(method: http::Method, uri: &str) -> RequestBuilder {
+        let mut builder = http::Request::builder();
+        builder.method(method).uri(uri);
+
+        RequestBuilder { builder, body: Chunk::default() }
+    }
+
+    pub fn get(uri: &str) -> RequestBuilder {
+        RequestBuilder::new(http::Method::GET, uri)
+    }
+
+    pub fn head(uri: &str) -> RequestBuilder {
+        RequestBuilder::new(http::Method::HEAD, uri)
+    }
+
+    pub fn post(uri: &str) -> RequestBuilder {
+        RequestBuilder::new(http::Method::POST, uri)
+    }
+
+    pub fn put(uri: &str) -> RequestBuilder {
+        RequestBuilder::new(http::Method::PUT, uri)
+    }
+
+    pub fn delete(uri: &str) -> RequestBuilder {
+        RequestBuilder::new(http::Method::DELETE, uri)
+    }
+
+    pub fn options(uri: &str) -> RequestBuilder {
+        RequestBuilder::new(http::Method::OPTIONS, uri)
+    }
+
+    pub fn header<K, V>(&mut self, name: K, value: V) -> &mut Self
+        where HeaderName: HttpTryFrom<K>,
+              HeaderValue: HttpTryFrom<V>
+    {
+        self.builder.header(name, value);
+        self
+    }
+
+    /// Sets the request body. Doesn't set `Content-Length`, so tests can
+    /// check how resources deal with its absence.
+    pub fn body<C: Into<Chunk>>(&mut self, body: C) -> &mut Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn build(&mut self) -> http::Request<Body> {
+        let body = ::std::mem::take(&mut self.body);
+        self.builder.body(Body::from(body)).expect("invalid test request")
+    }
+
+    /// Builds the request and runs `resource` on it.
+    pub fn execute<R>(&mut self, resource: R) -> TestResponse
+        where R: Resource + Debug + Send
+    {
+        execute(resource, self.build())
+    }
+}
+
+/// Runs `resource` on `request`, waiting for the complete response.
+///
+/// # Panics
+///
+/// If the flow or the response body fails.
+pub fn execute<R>(resource: R, request: http::Request<Body>) -> TestResponse
+    where R: Resource + Debug + Send
+{
+    let response = flow::handle(resource, request).wait().expect("flow ended without a response");
+
+    let (parts, body) = response.into_parts();
+    let body = body.concat2().wait().expect("response body failed");
+    let trace = parts.extensions.get::<Trace>().cloned().unwrap_or_default();
+
+    TestResponse { status: parts.status, headers: parts.headers, body: body.to_vec(), trace }
+}
+
+/// A completely received response, along with the decisions leading to it.
+///
+/// The `assert_*` methods panic with the trace in the message, so a
+/// failing test shows where the flow went.
+#[derive(Debug)]
+pub struct TestResponse {
+    status: http::StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    trace: Trace,
+}
+
+impl TestResponse {
+    pub fn status(&self) -> http::StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The value of header `name`, `None` if missing or not valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body as text.
+    ///
+    /// # Panics
+    ///
+    /// If the body is not valid UTF-8.
+    pub fn text(&self) -> &str {
+        str::from_utf8(&self.body).expect("response body is not UTF-8")
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    pub fn assert_status(&self, status: http::StatusCode) -> &Self {
+        assert!(self.status == status, "expected status {}, got {}; trace: {:?}", status, self.status, self.trace.nodes());
+        self
+    }
+
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self {
+        assert!(self.header(name) == Some(value), "expected header {}: {}, got {:?}; trace: {:?}",
+                name, value, self.header(name), self.trace.nodes());
+        self
+    }
+
+    pub fn assert_body<T: AsRef<[u8]>>(&self, body: T) -> &Self {
+        assert!(self.body == body.as_ref(), "expected body {:?}, got {:?}",
+                String::from_utf8_lossy(body.as_ref()), String::from_utf8_lossy(&self.body));
+        self
+    }
+
+    pub fn assert_visited(&self, node: &str) -> &Self {
+        assert!(self.trace.visited(node), "expected the flow to visit {}; trace: {:?}", node, self.trace.nodes());
+        self
+    }
+
+    pub fn assert_not_visited(&self, node: &str) -> &Self {
+        assert!(!self.trace.visited(node), "expected the flow not to visit {}; trace: {:?}", node, self.trace.nodes());
+        self
+    }
+
+    /// Asserts the flow went through exactly `nodes`.
+    pub fn assert_trace(&self, nodes: &[&str]) -> &Self {
+        assert!(self.trace.nodes() == nodes, "expected trace {:?}, got {:?}", nodes, self.trace.nodes());
+        self
+    }
+
+    /// Asserts the response was decided at `node`.
+    pub fn assert_decided_at(&self, node: &str) -> &Self {
+        assert!(self.trace.last() == Some(node), "expected the flow to end at {}; trace: {:?}", node, self.trace.nodes());
+        self
+    }
+}
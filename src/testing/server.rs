@@ -0,0 +1,441 @@
+//! End-to-end testing against a real server on an ephemeral port.
+
+use std::fmt::Debug;
+use std::error;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::str;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use http;
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+use resource::Resource;
+use server::{Error, ServerBuilder, ServerHandle};
+
+/// A server running on its own thread, shut down when dropped.
+///
+/// ```
+/// # extern crate gerust;
+/// # extern crate http;
+/// # extern crate mime;
+/// # extern crate futures;
+/// # use gerust::context::Context;
+/// # use gerust::flow::DelayedResponse;
+/// # use gerust::resource::{Resource, ProvidedPair, Deferred};
+/// # use gerust::response::Entity;
+/// use gerust::testing::TestServer;
+///
+/// # #[derive(Debug, Default)]
+/// # struct Hello;
+/// # impl Resource for Hello {
+/// #     fn content_types_provided(&self, _: &Context) -> &'static [ProvidedPair<Self>] {
+/// #         &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+/// #     }
+/// # }
+/// # impl Hello {
+/// #     fn to_text(&mut self, _: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+/// #         "Hello".into()
+/// #     }
+/// # }
+/// # fn main() {
+/// let server = TestServer::start::<Hello>();
+/// let response = server.client().get("/hello").unwrap();
+///
+/// assert_eq!(response.status(), http::StatusCode::OK);
+/// assert_eq!(response.text(), "Hello");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TestServer {
+    addrs: Vec<SocketAddr>,
+    handle: ServerHandle,
+    thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl TestServer {
+    /// Serves `R` on `127.0.0.1` with a port picked by the OS.
+    ///
+    /// # Panics
+    ///
+    /// If the server can't be started.
+    pub fn start<R>() -> TestServer
+        where R: Resource + Debug + Default + Send
+    {
+        let mut builder = ServerBuilder::new();
+        builder.threads(2).shutdown_timeout(Duration::from_secs(5));
+
+        TestServer::with_builder::<R>(&builder)
+    }
+
+    /// Serves `R` as configured by `builder`, additionally listening on
+    /// `127.0.0.1` with a port picked by the OS. Signal handling is always
+    /// disabled.
+    ///
+    /// # Panics
+    ///
+    /// If the server can't be started.
+    pub fn with_builder<R>(builder: &ServerBuilder) -> TestServer
+        where R: Resource + Debug + Default + Send
+    {
+        let mut builder = builder.clone();
+        builder.bind(([127, 0, 0, 1], 0).into()).handle_signals(false);
+
+        let (sx, rx) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("gerust-test-server".into())
+            .spawn(move || {
+                let server = match builder.build::<R>().and_then(|server| server.local_addrs().map(|addrs| (server, addrs))) {
+                    Ok((server, addrs)) => {
+                        let _ = sx.send(Ok((addrs, server.handle())));
+                        server
+                    },
+                    Err(e) => {
+                        let _ = sx.send(Err(e));
+                        return Ok(());
+                    },
+                };
+
+                server.run()
+            })
+            .expect("failed to spawn the test server thread");
+
+        match rx.recv().expect("test server thread died") {
+            Ok((addrs, handle)) => TestServer { addrs, handle, thread: Some(thread) },
+            Err(e) => panic!("failed to start the test server: {}", e),
+        }
+    }
+
+    /// The `127.0.0.1` address of the server.
+    pub fn addr(&self) -> SocketAddr {
+        *self.addrs.iter()
+            .rev()
+            .find(|addr| addr.ip().is_loopback())
+            .expect("test server has no loopback address")
+    }
+
+    /// All TCP addresses the server listens on.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr().port()
+    }
+
+    /// The `http://` URL of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr(), path)
+    }
+
+    /// A client connecting to `addr`.
+    pub fn client(&self) -> TestClient {
+        TestClient::new(self.addr())
+    }
+
+    /// Shuts down gracefully and waits for the server to stop.
+    pub fn shutdown(mut self) -> Result<(), Error> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        self.handle.shutdown();
+
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => panic!("test server thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            if !thread::panicking() {
+                panic!("test server failed: {}", e);
+            }
+        }
+    }
+}
+
+/// A minimal blocking HTTP/1.1 client.
+///
+/// Keeps its connection alive between requests unless the server closes
+/// it, reconnecting as needed.
+#[derive(Debug)]
+pub struct TestClient {
+    addr: SocketAddr,
+    connection: Option<BufReader<TcpStream>>,
+    connections: usize,
+    timeout: Duration,
+}
+
+impl TestClient {
+    pub fn new(addr: SocketAddr) -> TestClient {
+        TestClient { addr, connection: None, connections: 0, timeout: Duration::from_secs(5) }
+    }
+
+    /// How long to wait for the server before failing with
+    /// `TimedOut` or `WouldBlock`. Five seconds by default.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The number of TCP connections opened so far.
+    pub fn connections(&self) -> usize {
+        self.connections
+    }
+
+    pub fn get(&mut self, path: &str) -> io::Result<ClientResponse> {
+        self.request("GET", path, &[], &[])
+    }
+
+    /// Sends a request with `headers` and `body`.
+    ///
+    /// `Host` is added, as is `Content-Length` for non-empty bodies unless
+    /// `headers` set it or `Transfer-Encoding`.
+    pub fn request(&mut self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> io::Result<ClientResponse> {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, self.addr);
+
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        let framed = headers.iter().any(|(name, _)| {
+            name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("transfer-encoding")
+        });
+        if !body.is_empty() && !framed {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+
+        head.push_str("\r\n");
+
+        let mut raw = head.into_bytes();
+        raw.extend_from_slice(body);
+
+        self.send_raw(&raw)
+    }
+
+    /// Writes `request` as is and reads the response, for requests the
+    /// other methods can't express.
+    pub fn send_raw(&mut self, request: &[u8]) -> io::Result<ClientResponse> {
+        let head = request.starts_with(b"HEAD ");
+
+        if self.connection.is_some() {
+            // The server may have closed the idle connection meanwhile.
+            match self.exchange(request, head) {
+                Err(ref e) if closed(e) => {},
+                result => return result,
+            }
+        }
+
+        self.exchange(request, head)
+    }
+
+    /// Closes the current connection, if any.
+    pub fn close(&mut self) {
+        self.connection = None;
+    }
+
+    fn exchange(&mut self, request: &[u8], head: bool) -> io::Result<ClientResponse> {
+        if self.connection.is_none() {
+            let stream = TcpStream::connect(self.addr)?;
+            stream.set_read_timeout(Some(self.timeout))?;
+            stream.set_write_timeout(Some(self.timeout))?;
+            self.connection = Some(BufReader::new(stream));
+            self.connections += 1;
+        }
+
+        let result = {
+            let connection = self.connection.as_mut().unwrap();
+            connection.get_mut().write_all(request).and_then(|_| read_response(connection, head))
+        };
+
+        match result {
+            Ok((response, true)) => Ok(response),
+            Ok((response, false)) => {
+                self.connection = None;
+                Ok(response)
+            },
+            Err(e) => {
+                self.connection = None;
+                Err(e)
+            },
+        }
+    }
+}
+
+/// A response as received over the wire.
+#[derive(Debug)]
+pub struct ClientResponse {
+    version: http::Version,
+    status: http::StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    chunks: Option<Vec<Vec<u8>>>,
+}
+
+impl ClientResponse {
+    pub fn version(&self) -> http::Version {
+        self.version
+    }
+
+    pub fn status(&self) -> http::StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The value of header `name`, `None` if missing or not valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// The body, with any chunked encoding removed.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body as text.
+    ///
+    /// # Panics
+    ///
+    /// If the body is not valid UTF-8.
+    pub fn text(&self) -> &str {
+        str::from_utf8(&self.body).expect("response body is not UTF-8")
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.chunks.is_some()
+    }
+
+    /// The chunks of a chunked body as they arrived, empty otherwise.
+    pub fn chunks(&self) -> &[Vec<u8>] {
+        self.chunks.as_ref().map_or(&[], |chunks| &chunks[..])
+    }
+}
+
+fn invalid<E: Into<Box<dyn error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Whether `error` means the server closed the connection.
+fn closed(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset |
+                           io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    if line.ends_with("\r\n") {
+        line.truncate(line.len() - 2);
+        Ok(line)
+    } else {
+        Err(invalid("line not terminated by CRLF"))
+    }
+}
+
+/// Reads a response, skipping interim ones. Also returns whether the
+/// connection can be reused.
+fn read_response<R: BufRead>(reader: &mut R, head: bool) -> io::Result<(ClientResponse, bool)> {
+    loop {
+        let line = read_line(reader)?;
+        let mut parts = line.splitn(3, ' ');
+
+        let version = match parts.next() {
+            Some("HTTP/1.1") => http::Version::HTTP_11,
+            Some("HTTP/1.0") => http::Version::HTTP_10,
+            _ => return Err(invalid(format!("invalid status line {:?}", line))),
+        };
+        let status = parts.next()
+            .and_then(|code| http::StatusCode::from_bytes(code.as_bytes()).ok())
+            .ok_or_else(|| invalid(format!("invalid status line {:?}", line)))?;
+
+        let mut headers = HeaderMap::new();
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+
+            let colon = line.find(':').ok_or_else(|| invalid(format!("invalid header {:?}", line)))?;
+            let name = HeaderName::from_bytes(&line.as_bytes()[..colon]).map_err(invalid)?;
+            let value = HeaderValue::from_str(line[colon + 1..].trim()).map_err(invalid)?;
+            headers.append(name, value);
+        }
+
+        if status.is_informational() && status != http::StatusCode::SWITCHING_PROTOCOLS {
+            continue;
+        }
+
+        let mut keep_alive = match headers.get(header::CONNECTION).and_then(|value| value.to_str().ok()) {
+            Some(value) if value.eq_ignore_ascii_case("close") => false,
+            Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+            _ => version == http::Version::HTTP_11,
+        };
+
+        let chunked = headers.get_all(header::TRANSFER_ENCODING).iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.to_ascii_lowercase().contains("chunked"));
+
+        let mut body = Vec::new();
+        let mut chunks = None;
+
+        let bodyless = head || status.is_informational() || status == http::StatusCode::NO_CONTENT ||
+            status == http::StatusCode::NOT_MODIFIED;
+
+        if bodyless {
+            // Nothing to read.
+        } else if chunked {
+            let mut received = Vec::new();
+
+            loop {
+                let line = read_line(reader)?;
+                let size = line.split(';').next().unwrap_or("").trim();
+                let size = usize::from_str_radix(size, 16).map_err(|_| invalid(format!("invalid chunk size {:?}", line)))?;
+
+                if size == 0 {
+                    // Skip the trailers.
+                    while !read_line(reader)?.is_empty() {}
+                    break;
+                }
+
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk)?;
+                if !chunk.ends_with(b"\r\n") {
+                    return Err(invalid("chunk not terminated by CRLF"));
+                }
+                chunk.truncate(size);
+
+                body.extend_from_slice(&chunk);
+                received.push(chunk);
+            }
+
+            chunks = Some(received);
+        } else if let Some(length) = headers.get(header::CONTENT_LENGTH) {
+            let length = length.to_str().ok()
+                .and_then(|length| length.parse().ok())
+                .ok_or_else(|| invalid("invalid Content-Length"))?;
+
+            body.resize(length, 0);
+            reader.read_exact(&mut body)?;
+        } else {
+            reader.read_to_end(&mut body)?;
+            keep_alive = false;
+        }
+
+        let response = ClientResponse { version, status, headers, body, chunks };
+        return Ok((response, keep_alive));
+    }
+}
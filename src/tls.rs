@@ -0,0 +1,155 @@
+//! TLS termination for the built-in server.
+//!
+//! Only available with the `tls` cargo feature.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::{NoClientAuth, ResolvesServerCert, ServerConfig, SignatureScheme};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use tokio_rustls::TlsAcceptor;
+use webpki;
+
+/// Errors raised while loading certificates and keys.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading a certificate or key file failed.
+    Io(PathBuf, io::Error),
+    /// A file holds no usable PEM certificate or private key.
+    InvalidPem(PathBuf),
+    /// A certificate doesn't fit its key or host name.
+    Certificate(PathBuf, String),
+    /// Neither a default nor an SNI certificate was configured.
+    NoCertificate,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref path, ref e) => write!(f, "can't read {}: {}", path.display(), e),
+            Error::InvalidPem(ref path) => write!(f, "no usable PEM data in {}", path.display()),
+            Error::Certificate(ref path, ref reason) => write!(f, "invalid certificate {}: {}", path.display(), reason),
+            Error::NoCertificate => write!(f, "no certificate configured"),
+        }
+    }
+}
+
+/// Certificates to present, loaded from PEM files when the server is built.
+///
+/// Clients announcing a host name via SNI get the matching certificate,
+/// all others the default one.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    default: Option<(PathBuf, PathBuf)>,
+    hosts: Vec<(String, PathBuf, PathBuf)>,
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig::default()
+    }
+
+    /// Sets the default certificate chain and its private key.
+    pub fn certificate<C, K>(&mut self, cert: C, key: K) -> &mut Self
+        where C: Into<PathBuf>, K: Into<PathBuf>
+    {
+        self.default = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Adds a certificate chain and key presented to clients asking for
+    /// `hostname`.
+    pub fn sni_certificate<C, K>(&mut self, hostname: &str, cert: C, key: K) -> &mut Self
+        where C: Into<PathBuf>, K: Into<PathBuf>
+    {
+        self.hosts.push((hostname.to_lowercase(), cert.into(), key.into()));
+        self
+    }
+
+    pub(crate) fn acceptor(&self, http2: bool) -> Result<TlsAcceptor, Error> {
+        if self.default.is_none() && self.hosts.is_empty() {
+            return Err(Error::NoCertificate);
+        }
+
+        let default = match self.default {
+            Some((ref cert, ref key)) => Some(load(cert, key)?),
+            None => None,
+        };
+
+        let mut hosts = HashMap::new();
+
+        for (hostname, cert, key) in &self.hosts {
+            let certified = load(cert, key)?;
+
+            let name = webpki::DNSNameRef::try_from_ascii_str(hostname)
+                .map_err(|_| Error::Certificate(cert.clone(), format!("{} is not a valid host name", hostname)))?;
+
+            certified.cross_check_end_entity_cert(Some(name))
+                .map_err(|e| Error::Certificate(cert.clone(), e.to_string()))?;
+
+            hosts.insert(hostname.clone(), certified);
+        }
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = Arc::new(Certificates { default, hosts });
+        if http2 {
+            config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        } else {
+            config.set_protocols(&[b"http/1.1".to_vec()]);
+        }
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn load(cert: &Path, key: &Path) -> Result<CertifiedKey, Error> {
+    let chain = pemfile::certs(&mut reader(cert)?)
+        .map_err(|_| Error::InvalidPem(cert.to_path_buf()))?;
+
+    if chain.is_empty() {
+        return Err(Error::InvalidPem(cert.to_path_buf()));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut reader(key)?)
+        .map_err(|_| Error::InvalidPem(key.to_path_buf()))?;
+
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut reader(key)?)
+            .map_err(|_| Error::InvalidPem(key.to_path_buf()))?;
+    }
+
+    let key = keys.first()
+        .and_then(|key| sign::any_supported_type(key).ok())
+        .ok_or_else(|| Error::InvalidPem(key.to_path_buf()))?;
+
+    Ok(CertifiedKey::new(chain, Arc::new(key)))
+}
+
+fn reader(path: &Path) -> Result<BufReader<File>, Error> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::Io(path.to_path_buf(), e))
+}
+
+/// Picks the certificate by SNI host name.
+struct Certificates {
+    default: Option<CertifiedKey>,
+    hosts: HashMap<String, CertifiedKey>,
+}
+
+impl ResolvesServerCert for Certificates {
+    fn resolve(&self, server_name: Option<webpki::DNSNameRef>, _sigschemes: &[SignatureScheme]) -> Option<CertifiedKey> {
+        server_name
+            .and_then(|name| {
+                let name: &str = name.into();
+                self.hosts.get(&name.to_lowercase())
+            })
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
@@ -0,0 +1,88 @@
+//! Serving resources as a `tower_service::Service`, so timeouts,
+//! concurrency limits and tracing from the tower ecosystem can be layered
+//! around the flow.
+//!
+//! Only available with the `tower` cargo feature.
+
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use futures::{Async, Poll};
+use futures_cpupool::CpuPool;
+use http;
+use tower_service::Service;
+
+use body::Body;
+use flow::{self, Flow, FlowError, HttpFlow};
+use resource::Resource;
+
+/// A `Service` running a fresh resource from `factory` for every request.
+///
+/// The factory sees the request, so it can also act as a dispatcher by
+/// returning a resource depending on, say, the path.
+///
+/// ```
+/// # extern crate gerust;
+/// # extern crate http;
+/// # extern crate tower_service;
+/// # use gerust::context::Context;
+/// # use gerust::resource::{Resource, ProvidedPair};
+/// use gerust::tower::ResourceService;
+/// use tower_service::Service;
+///
+/// # #[derive(Default, Debug)]
+/// # struct Orders;
+/// # impl Resource for Orders {
+/// #     fn content_types_provided(&self, _: &Context) -> &'static [ProvidedPair<Self>] { &[] }
+/// # }
+/// # fn main() {
+/// let mut service = ResourceService::new(|_: &http::Request<gerust::Body>| Orders::default());
+///
+/// let request = http::Request::get("/orders").body(gerust::Body::empty()).unwrap();
+/// let response = service.call(request);
+/// # }
+/// ```
+pub struct ResourceService<F, B = ::Body> {
+    factory: F,
+    pool: Arc<CpuPool>,
+    body: PhantomData<fn(B)>,
+}
+
+impl<F, B> ResourceService<F, B> {
+    /// Runs the flows on the pool shared with `flow::handle`.
+    pub fn new(factory: F) -> ResourceService<F, B> {
+        ResourceService::with_pool(factory, flow::default_pool())
+    }
+
+    /// Runs the flows on `pool`.
+    pub fn with_pool(factory: F, pool: Arc<CpuPool>) -> ResourceService<F, B> {
+        ResourceService { factory, pool, body: PhantomData }
+    }
+}
+
+impl<F: Clone, B> Clone for ResourceService<F, B> {
+    fn clone(&self) -> ResourceService<F, B> {
+        ResourceService::with_pool(self.factory.clone(), self.pool.clone())
+    }
+}
+
+impl<F, R, B> Service<http::Request<B>> for ResourceService<F, B>
+    where F: FnMut(&http::Request<B>) -> R,
+          R: Resource<B> + Debug + Send,
+          B: Body
+{
+    type Response = http::Response<B>;
+    type Error = FlowError;
+    type Future = <HttpFlow<B> as Flow>::Future;
+
+    /// Always ready, flows queue up on the pool.
+    fn poll_ready(&mut self) -> Poll<(), FlowError> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let resource = (self.factory)(&request);
+        HttpFlow::new(self.pool.clone()).handle(resource, request)
+    }
+}
@@ -0,0 +1,162 @@
+//! Request bodies without a declared length, limited while they stream.
+//!
+//! The body is passed on to the resource by a pump on a background
+//! reactor, apart from the flow's pool, so a handler reading it
+//! synchronously can't block what feeds it.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use futures::{future, Async, AsyncSink, Future, Poll, Sink};
+use futures::sync::oneshot;
+use futures_cpupool::CpuPool;
+use tokio_core::reactor::{Core, Remote};
+
+use body::{self, Body};
+use error::Error;
+
+lazy_static! {
+    /// Runs the pumps, `None` if the reactor couldn't be started.
+    static ref PUMPS: Option<Remote> = start();
+}
+
+fn start() -> Option<Remote> {
+    let (sx, rx) = mpsc::channel();
+
+    let spawned = thread::Builder::new()
+        .name("gerust-upload".to_string())
+        .spawn(move || {
+            let mut core = match Core::new() {
+                Ok(core) => core,
+                Err(e) => return warn!("cannot start the upload reactor: {}", e),
+            };
+
+            let _ = sx.send(core.remote());
+            let _ = core.run(future::empty::<(), ()>());
+        });
+
+    if let Err(e) = spawned {
+        warn!("cannot spawn the upload thread: {}", e);
+    }
+
+    rx.recv().ok()
+}
+
+/// An upload passed on to the resource.
+pub(crate) struct Upload {
+    exceeded: Arc<AtomicBool>,
+    /// Whether the resource read past the limit, once the pump is done.
+    outcome: oneshot::Receiver<bool>,
+}
+
+impl Upload {
+    /// Replaces `body` with one passed on from it, which fails once more
+    /// than `limit` bytes arrived.
+    ///
+    /// Pumps on `pool` if the background reactor isn't available.
+    pub fn start<B: Body>(body: &mut B, limit: Option<u64>, pool: &CpuPool) -> Upload {
+        let (sender, limited) = B::pair();
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let (done, outcome) = oneshot::channel();
+
+        let pump = Pump {
+            body: ::std::mem::replace(body, limited),
+            sender,
+            limit,
+            received: 0,
+            exceeded: exceeded.clone(),
+            done: Some(done),
+        };
+
+        match *PUMPS {
+            Some(ref remote) => remote.spawn(move |_| pump),
+            None => pool.spawn(pump).forget(),
+        }
+
+        Upload { exceeded, outcome }
+    }
+
+    /// Whether the body grew larger than the limit, read or not.
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::SeqCst)
+    }
+
+    /// Resolves to whether the resource read past the limit, once the
+    /// body is done.
+    pub fn outcome(self) -> impl Future<Item=bool, Error=Error> + Send {
+        self.outcome.then(|read| Ok(read.unwrap_or(false)))
+    }
+}
+
+/// Moves the chunks of a body to the one the resource reads, as long as
+/// it takes them.
+struct Pump<B: Body> {
+    body: B,
+    sender: body::Sender<B>,
+    limit: Option<u64>,
+    received: u64,
+    exceeded: Arc<AtomicBool>,
+    done: Option<oneshot::Sender<bool>>,
+}
+
+impl<B: Body> Pump<B> {
+    fn finish(&mut self, read_past_limit: bool) -> Poll<(), ()> {
+        if let Some(done) = self.done.take() {
+            let _ = done.send(read_past_limit);
+        }
+
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<B: Body> Future for Pump<B> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let ready = self.sender.poll_ready();
+
+            if self.exceeded.load(Ordering::SeqCst) {
+                // The error was sent, wait until it was read or the body dropped.
+                return match ready {
+                    Ok(Async::Ready(())) => self.finish(true),
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(_) => self.finish(false),
+                };
+            }
+
+            match ready {
+                Ok(Async::Ready(())) => {},
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // The resource dropped the body, so the rest isn't needed.
+                Err(_) => return self.finish(false),
+            }
+
+            let item = match self.body.poll() {
+                Ok(Async::Ready(Some(chunk))) => {
+                    self.received += chunk.as_ref().len() as u64;
+
+                    match self.limit {
+                        Some(limit) if self.received > limit => {
+                            self.exceeded.store(true, Ordering::SeqCst);
+                            Err(Error::PayloadTooLarge.into())
+                        },
+                        _ => Ok(chunk),
+                    }
+                },
+                Ok(Async::Ready(None)) => return self.finish(false),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => Err(e),
+            };
+
+            let failed = item.is_err();
+            match self.sender.start_send(item) {
+                Ok(AsyncSink::Ready) if !failed || self.exceeded.load(Ordering::SeqCst) => {},
+                _ => return self.finish(false),
+            }
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! WebSocket connections (RFC 6455).
+//!
+//! A resource accepts the handshake in `Resource::accept_websocket`,
+//! which the flow asks right after authorization (B8 and B7), and returns
+//! a `Handler` taking over the connection once `101 Switching Protocols`
+//! was sent:
+//!
+//! ```rust
+//! # extern crate gerust;
+//! # extern crate futures;
+//! # use futures::{Future, Sink, Stream};
+//! # use gerust::context::Context;
+//! # use gerust::websocket::{self, Handler, Message, WebSocket};
+//! fn accept_websocket(context: &Context) -> Option<Handler> {
+//!     Some(websocket::handler(|socket: WebSocket| {
+//!         // Echoes text messages.
+//!         let (sink, stream) = socket.split();
+//!         let texts = stream.filter(|message| match *message {
+//!             Message::Text(_) => true,
+//!             _ => false,
+//!         });
+//!
+//!         texts.forward(sink).map(|_| ()).map_err(|_| ())
+//!     }))
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! Only HTTP/1.1 connections of the server can be upgraded.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+
+use base64;
+use bytes::{BufMut, BytesMut};
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use sha1_smol::Sha1;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::{Decoder, Encoder, Framed};
+
+/// Appended to the client's key to compute `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages longer than this close the connection.
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Takes over an upgraded connection, running on the server's event loop.
+pub type Handler = Box<dyn FnOnce(WebSocket) -> Box<dyn Future<Item=(), Error=()>> + Send>;
+
+/// Boxes a closure into a `Handler`.
+pub fn handler<F, T>(handler: F) -> Handler
+    where F: FnOnce(WebSocket) -> T + Send + 'static,
+          T: IntoFuture<Item=(), Error=()>,
+          T::Future: 'static
+{
+    Box::new(move |socket| Box::new(handler(socket).into_future()) as Box<dyn Future<Item=(), Error=()>>)
+}
+
+/// The `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(GUID.as_bytes());
+
+    base64::encode(&sha1.digest().bytes())
+}
+
+/// Whether `key` is a valid `Sec-WebSocket-Key`, 16 bytes in base64.
+pub(crate) fn is_valid_key(key: &str) -> bool {
+    base64::decode(key.trim()).map(|key| key.len() == 16).unwrap_or(false)
+}
+
+/// Hands the handler to the server along with the `101` response.
+pub(crate) struct Upgrade(Mutex<Option<Handler>>);
+
+impl Upgrade {
+    pub(crate) fn new(handler: Handler) -> Upgrade {
+        Upgrade(Mutex::new(Some(handler)))
+    }
+
+    pub(crate) fn take(&self) -> Option<Handler> {
+        self.0.lock().ok().and_then(|mut handler| handler.take())
+    }
+}
+
+/// A message sent or received over a `WebSocket`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Answered with a `Pong` automatically.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The closing handshake, with a status code and reason if given.
+    /// A received `Close` is echoed automatically and ends the stream.
+    Close(Option<(u16, String)>),
+}
+
+/// The IO of an upgraded connection.
+pub trait Io: AsyncRead + AsyncWrite {}
+
+impl<T: AsyncRead + AsyncWrite> Io for T {}
+
+/// The server end of a WebSocket connection, a `Stream` and `Sink` of
+/// `Message`s.
+///
+/// Fragmented messages are reassembled. Protocol violations end the
+/// stream with an `InvalidData` error.
+pub struct WebSocket {
+    framed: Framed<Box<dyn Io>, Codec>,
+    /// A fragmented message being received.
+    fragments: Option<(u8, Vec<u8>)>,
+    /// Automatic replies not written yet.
+    replies: VecDeque<Frame>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    /// Speaks WebSocket over `io`, after the handshake took place.
+    pub fn new<I: AsyncRead + AsyncWrite + 'static>(io: I) -> WebSocket {
+        let io: Box<dyn Io> = Box::new(io);
+
+        WebSocket {
+            framed: io.framed(Codec),
+            fragments: None,
+            replies: VecDeque::new(),
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    fn message(&mut self, opcode: u8, payload: Vec<u8>) -> Result<Message, io::Error> {
+        match opcode {
+            TEXT => String::from_utf8(payload).map(Message::Text).map_err(|_| invalid("text message is not UTF-8")),
+            _ => Ok(Message::Binary(payload)),
+        }
+    }
+
+    fn reply(&mut self, frame: Frame) {
+        if !self.close_sent {
+            self.close_sent = frame.opcode == CLOSE;
+            self.replies.push_back(frame);
+        }
+    }
+
+    fn flush(&mut self) -> Poll<(), io::Error> {
+        while let Some(frame) = self.replies.pop_front() {
+            if let AsyncSink::NotReady(frame) = self.framed.start_send(frame)? {
+                self.replies.push_front(frame);
+                return Ok(Async::NotReady);
+            }
+        }
+
+        self.framed.poll_complete()
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("close_sent", &self.close_sent)
+            .field("close_received", &self.close_received)
+            .finish()
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Message>, io::Error> {
+        loop {
+            // Replies are best effort while reading; the sink flushes the rest.
+            self.flush()?;
+
+            if self.close_received {
+                return Ok(Async::Ready(None));
+            }
+
+            let frame = match self.framed.poll()? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+
+            let Frame { fin, opcode, payload } = frame;
+
+            let message = match opcode {
+                CONTINUATION => {
+                    let (opcode, mut message) = self.fragments.take().ok_or_else(|| invalid("unexpected continuation frame"))?;
+                    if message.len() + payload.len() > MAX_MESSAGE_SIZE {
+                        return Err(invalid("message too long"));
+                    }
+                    message.extend_from_slice(&payload);
+
+                    if !fin {
+                        self.fragments = Some((opcode, message));
+                        continue;
+                    }
+                    self.message(opcode, message)?
+                },
+                TEXT | BINARY if self.fragments.is_some() => return Err(invalid("expected a continuation frame")),
+                TEXT | BINARY if !fin => {
+                    self.fragments = Some((opcode, payload));
+                    continue;
+                },
+                TEXT | BINARY => self.message(opcode, payload)?,
+                PING => {
+                    self.reply(Frame { fin: true, opcode: PONG, payload: payload.clone() });
+                    Message::Ping(payload)
+                },
+                PONG => Message::Pong(payload),
+                CLOSE => {
+                    self.close_received = true;
+                    let close = parse_close(&payload)?;
+
+                    // Echoes the status code, as the closing handshake asks.
+                    let echo = close.as_ref().map(|&(code, _)| code.to_be_bytes().to_vec()).unwrap_or_default();
+                    self.reply(Frame { fin: true, opcode: CLOSE, payload: echo });
+                    self.flush()?;
+
+                    Message::Close(close)
+                },
+                _ => return Err(invalid("unknown opcode")),
+            };
+
+            return Ok(Async::Ready(Some(message)));
+        }
+    }
+}
+
+impl Sink for WebSocket {
+    type SinkItem = Message;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, message: Message) -> StartSend<Message, io::Error> {
+        if self.close_sent {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "the WebSocket is closing"));
+        }
+
+        if self.flush()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(message));
+        }
+
+        let (opcode, payload) = match message.clone() {
+            Message::Text(text) => (TEXT, text.into_bytes()),
+            Message::Binary(data) => (BINARY, data),
+            Message::Ping(data) => (PING, data),
+            Message::Pong(data) => (PONG, data),
+            Message::Close(close) => {
+                let payload = close.map(|(code, reason)| {
+                    let mut payload = code.to_be_bytes().to_vec();
+                    payload.extend_from_slice(reason.as_bytes());
+                    payload
+                });
+                (CLOSE, payload.unwrap_or_default())
+            },
+        };
+
+        match self.framed.start_send(Frame { fin: true, opcode, payload })? {
+            AsyncSink::Ready => {
+                self.close_sent = opcode == CLOSE;
+                Ok(AsyncSink::Ready)
+            },
+            AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(message)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.flush()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        if self.flush()?.is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+
+        self.framed.close()
+    }
+}
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Reads masked client frames and writes unmasked server frames.
+struct Codec;
+
+impl Decoder for Codec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        if buffer.len() < 2 {
+            return Ok(None);
+        }
+
+        let (first, second) = (buffer[0], buffer[1]);
+        let fin = first & 0x80 != 0;
+        let opcode = first & 0x0F;
+
+        if first & 0x70 != 0 {
+            return Err(invalid("reserved bits are set"));
+        }
+        if second & 0x80 == 0 {
+            return Err(invalid("client frames must be masked"));
+        }
+
+        let (length, offset) = match second & 0x7F {
+            126 if buffer.len() >= 4 => (u64::from(u16::from_be_bytes([buffer[2], buffer[3]])), 4),
+            127 if buffer.len() >= 10 => {
+                let mut length = [0; 8];
+                length.copy_from_slice(&buffer[2..10]);
+                (u64::from_be_bytes(length), 10)
+            },
+            126 | 127 => return Ok(None),
+            length => (u64::from(length), 2),
+        };
+
+        if opcode >= CLOSE && (length > 125 || !fin) {
+            return Err(invalid("invalid control frame"));
+        }
+        if length > MAX_MESSAGE_SIZE as u64 {
+            return Err(invalid("message too long"));
+        }
+
+        let length = length as usize;
+        if buffer.len() < offset + 4 + length {
+            buffer.reserve(offset + 4 + length - buffer.len());
+            return Ok(None);
+        }
+
+        let frame = buffer.split_to(offset + 4 + length);
+        let mask = [frame[offset], frame[offset + 1], frame[offset + 2], frame[offset + 3]];
+        let payload = frame[offset + 4..].iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect();
+
+        Ok(Some(Frame { fin, opcode, payload }))
+    }
+}
+
+impl Encoder for Codec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, buffer: &mut BytesMut) -> Result<(), io::Error> {
+        let length = frame.payload.len();
+        buffer.reserve(length + 10);
+
+        buffer.put_u8(if frame.fin { 0x80 } else { 0 } | frame.opcode);
+        if length < 126 {
+            buffer.put_u8(length as u8);
+        } else if length <= usize::from(u16::MAX) {
+            buffer.put_u8(126);
+            buffer.put_slice(&(length as u16).to_be_bytes());
+        } else {
+            buffer.put_u8(127);
+            buffer.put_slice(&(length as u64).to_be_bytes());
+        }
+        buffer.put_slice(&frame.payload);
+
+        Ok(())
+    }
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<(u16, String)>, io::Error> {
+    match payload.len() {
+        0 => Ok(None),
+        1 => Err(invalid("truncated close frame")),
+        _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let reason = String::from_utf8(payload[2..].to_vec()).map_err(|_| invalid("close reason is not UTF-8"))?;
+            Ok(Some((code, reason)))
+        },
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
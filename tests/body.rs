@@ -0,0 +1,113 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+extern crate futures_cpupool;
+
+use std::sync::Arc;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::{mpsc, oneshot};
+use futures_cpupool::CpuPool;
+
+use gerust::body::{self, Body};
+use gerust::chunk::Chunk;
+use gerust::context::Context;
+use gerust::error::Error;
+use gerust::flow::{DelayedResponse, Flow, HttpFlow};
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+
+/// A body that lives in memory, without any HTTP stack behind it.
+#[derive(Debug)]
+enum MemoryBody {
+    Once(Option<Chunk>),
+    Channel(mpsc::Receiver<Result<Chunk, Error>>),
+}
+
+impl From<Chunk> for MemoryBody {
+    fn from(chunk: Chunk) -> MemoryBody {
+        MemoryBody::Once(Some(chunk))
+    }
+}
+
+impl Stream for MemoryBody {
+    type Item = Chunk;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Error> {
+        match *self {
+            MemoryBody::Once(ref mut chunk) => Ok(Async::Ready(chunk.take())),
+            MemoryBody::Channel(ref mut receiver) => match receiver.poll() {
+                Ok(Async::Ready(Some(Ok(chunk)))) => Ok(Async::Ready(Some(chunk))),
+                Ok(Async::Ready(Some(Err(e)))) => Err(e),
+                Ok(Async::Ready(None)) | Err(()) => Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+            },
+        }
+    }
+}
+
+impl Body for MemoryBody {
+    type Chunk = Chunk;
+    type Error = Error;
+
+    fn empty() -> MemoryBody {
+        MemoryBody::Once(None)
+    }
+
+    fn pair() -> (body::Sender<MemoryBody>, MemoryBody) {
+        let (sender, receiver) = mpsc::channel(0);
+        (sender, MemoryBody::Channel(receiver))
+    }
+}
+
+#[derive(Default, Debug)]
+struct HelloResource;
+
+impl Resource<MemoryBody> for HelloResource {
+    fn content_types_provided(&self, _context: &Context<MemoryBody>) -> &'static [ProvidedPair<Self, MemoryBody>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl HelloResource {
+    fn to_text(&mut self, _context: &Context<MemoryBody>, _response: &mut DelayedResponse<MemoryBody>) -> Deferred<Self, Entity> {
+        "Hello, World!".into()
+    }
+}
+
+fn execute(request: http::Request<MemoryBody>) -> http::Response<MemoryBody> {
+    let (sx, rx) = oneshot::channel();
+
+    let mut flow = HttpFlow::<MemoryBody>::new(Arc::new(CpuPool::new(2)));
+    flow.execute(HelloResource, request, sx);
+
+    rx.wait().unwrap()
+}
+
+fn read(body: MemoryBody) -> Vec<u8> {
+    body.concat2().wait().unwrap().to_vec()
+}
+
+#[test]
+fn test_flow_with_memory_body() {
+    let request = http::Request::get("/").body(MemoryBody::empty()).unwrap();
+
+    let response = execute(request);
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(read(response.into_body()), b"Hello, World!");
+}
+
+#[test]
+fn test_halt_with_memory_body() {
+    let request = http::Request::delete("/").body(MemoryBody::empty()).unwrap();
+
+    let response = execute(request);
+
+    assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(read(response.into_body()), b"Method Not Allowed");
+}
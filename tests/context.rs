@@ -0,0 +1,174 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate tokio_core;
+extern crate http;
+extern crate hyper;
+extern crate futures;
+extern crate futures_cpupool;
+
+use futures::{Future, Stream};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, AcceptedPair, ProvidedPair, Deferred};
+use gerust::response::Entity;
+
+mod helper;
+
+#[derive(Default, Debug)]
+struct GuardedResource {
+    greeting: Option<String>,
+}
+
+impl Resource for GuardedResource {
+    fn forbidden(&mut self, context: &Context) -> Deferred<Self, bool> {
+        (!context.headers().contains_key("X-Api-Key")).into()
+    }
+
+    fn resource_exists(&mut self, context: &Context) -> Deferred<Self, bool> {
+        self.greeting = context.uri().path().split('/').nth(1).map(|name| format!("Hello, {}!", name));
+
+        self.greeting.is_some().into()
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl GuardedResource {
+    fn to_text(&mut self, _context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        let greeting = self.greeting.take().unwrap();
+        let _ = response.write(greeting);
+        ().into()
+    }
+}
+
+#[test]
+fn test_forbidden_without_header() {
+    let req = http::request::Builder::new()
+        .method(http::method::Method::GET)
+        .uri("/World")
+        .body("".into())
+        .unwrap();
+
+    let response = helper::execute(GuardedResource::default(), req);
+
+    assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn test_state_cached_between_decisions() {
+    let req = http::request::Builder::new()
+        .method(http::method::Method::GET)
+        .uri("/World")
+        .header("X-Api-Key", "secret")
+        .body("".into())
+        .unwrap();
+
+    let response = helper::execute(GuardedResource::default(), req);
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let body = response.into_parts().1.concat2().wait().unwrap();
+    assert_eq!(&body[..], b"Hello, World!");
+}
+
+/// Creates orders on POST, optionally below a configured base URI.
+#[derive(Default, Debug)]
+struct OrdersResource {
+    base_uri: Option<&'static str>,
+}
+
+impl Resource for OrdersResource {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::POST]
+    }
+
+    fn post_is_create(&mut self, _context: &Context) -> bool {
+        true
+    }
+
+    fn create_path(&mut self, _context: &Context) -> String {
+        "orders/1".to_string()
+    }
+
+    fn base_uri(&self, _context: &Context) -> Option<http::Uri> {
+        self.base_uri.map(|uri| uri.parse().unwrap())
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::TEXT_PLAIN, Self::from_text)]
+    }
+}
+
+impl OrdersResource {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        ().into()
+    }
+
+    fn from_text(&mut self, _context: &mut Context, _response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        ().into()
+    }
+}
+
+fn create_order(resource: OrdersResource, scheme: Option<http::uri::Scheme>) -> http::Response<gerust::Body> {
+    let mut req = http::request::Builder::new()
+        .method(http::method::Method::POST)
+        .uri("/")
+        .header("Host", "example.com")
+        .header("Content-Type", "text/plain")
+        .header("Content-Length", "0")
+        .body("".into())
+        .unwrap();
+
+    if let Some(scheme) = scheme {
+        req.extensions_mut().insert(scheme);
+    }
+
+    helper::execute(resource, req)
+}
+
+#[test]
+fn test_location_resolved_against_host() {
+    let response = create_order(OrdersResource::default(), None);
+
+    assert_eq!(response.status(), http::StatusCode::CREATED);
+    assert_eq!(response.headers()["Location"], "http://example.com/orders/1");
+}
+
+#[test]
+fn test_location_reflects_scheme() {
+    let response = create_order(OrdersResource::default(), Some(http::uri::Scheme::HTTPS));
+
+    assert_eq!(response.headers()["Location"], "https://example.com/orders/1");
+}
+
+#[test]
+fn test_location_resolved_against_resource_base_uri() {
+    let resource = OrdersResource { base_uri: Some("https://api.example.com/v1/") };
+
+    let response = create_order(resource, None);
+
+    assert_eq!(response.headers()["Location"], "https://api.example.com/v1/orders/1");
+}
+
+#[test]
+fn test_base_uri() {
+    let req: http::Request<gerust::Body> = http::request::Builder::new()
+        .uri("/some/path")
+        .header("Host", "example.com:8080")
+        .body("".into())
+        .unwrap();
+
+    let context = Context::new(req);
+
+    assert_eq!(context.scheme(), &http::uri::Scheme::HTTP);
+    assert_eq!(context.base_uri().unwrap(), "http://example.com:8080/");
+}
@@ -0,0 +1,809 @@
+//! Drives a configurable resource through every decision of the flow,
+//! after webmachine's decision spec.
+
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate hyper;
+extern crate futures;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::{Method, StatusCode};
+use http::header::HeaderValue;
+use hyper::header::HttpDate;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, AcceptedPair, Deferred, Encoder};
+use gerust::response::Entity;
+use gerust::testing::{RequestBuilder, TestResponse};
+
+const ALL_METHODS: &[Method] = &[Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::DELETE, Method::OPTIONS];
+
+/// Answers every callback as configured.
+#[derive(Debug)]
+struct Decisions {
+    service_available: bool,
+    uri_too_long: bool,
+    allowed_methods: &'static [Method],
+    malformed_request: bool,
+    authorized: bool,
+    forbidden: bool,
+    valid_content_headers: bool,
+    known_content_type: bool,
+    valid_entity_length: bool,
+    languages: Vec<HeaderValue>,
+    charsets: Vec<HeaderValue>,
+    gzip: bool,
+    exists: bool,
+    etag: Option<&'static str>,
+    last_modified: Option<SystemTime>,
+    moved_permanently: Option<&'static str>,
+    moved_temporarily: Option<&'static str>,
+    previously_existed: bool,
+    allow_missing_post: bool,
+    delete_resource: bool,
+    delete_completed: bool,
+    post_is_create: bool,
+    redirect: bool,
+    is_conflict: bool,
+    multiple_choices: bool,
+    provides_nothing: bool,
+}
+
+impl Default for Decisions {
+    fn default() -> Decisions {
+        Decisions {
+            service_available: true,
+            uri_too_long: false,
+            allowed_methods: ALL_METHODS,
+            malformed_request: false,
+            authorized: true,
+            forbidden: false,
+            valid_content_headers: true,
+            known_content_type: true,
+            valid_entity_length: true,
+            languages: Vec::new(),
+            charsets: Vec::new(),
+            gzip: false,
+            exists: true,
+            etag: None,
+            last_modified: None,
+            moved_permanently: None,
+            moved_temporarily: None,
+            previously_existed: false,
+            allow_missing_post: false,
+            delete_resource: true,
+            delete_completed: true,
+            post_is_create: false,
+            redirect: false,
+            is_conflict: false,
+            multiple_choices: false,
+            provides_nothing: false,
+        }
+    }
+}
+
+impl Resource for Decisions {
+    fn service_available(&mut self, _context: &Context) -> bool {
+        self.service_available
+    }
+
+    fn uri_too_long(&mut self, _context: &Context) -> bool {
+        self.uri_too_long
+    }
+
+    fn allowed_methods(&self, _context: &Context) -> &'static [Method] {
+        self.allowed_methods
+    }
+
+    fn malformed_request(&mut self, _context: &Context) -> bool {
+        self.malformed_request
+    }
+
+    fn is_authorized(&mut self, _context: &Context) -> Deferred<Self, bool> {
+        self.authorized.into()
+    }
+
+    fn forbidden(&mut self, _context: &Context) -> Deferred<Self, bool> {
+        self.forbidden.into()
+    }
+
+    fn valid_content_headers<'a, I>(&mut self, _context: &Context, mut headers: I) -> bool
+        where I: Iterator<Item=(&'a http::header::HeaderName, &'a http::header::HeaderValue)>
+    {
+        // Rejects a content header we don't know, if configured to.
+        self.valid_content_headers || !headers.any(|(name, _)| name == "content-foo")
+    }
+
+    fn known_content_type(&mut self, _context: &Context, _content_type: &http::header::HeaderValue) -> bool {
+        self.known_content_type
+    }
+
+    fn valid_entity_length(&mut self, _context: &Context, _len: u64) -> bool {
+        self.valid_entity_length
+    }
+
+    fn languages_provided(&self, _context: &Context) -> &[HeaderValue] {
+        &self.languages
+    }
+
+    fn charsets_provided(&self, _context: &Context) -> &[HeaderValue] {
+        &self.charsets
+    }
+
+    fn encodings_provided(&self, _context: &Context) -> &'static [(&'static str, Encoder<Self>)] {
+        if self.gzip {
+            return &[("gzip", Self::gzip)];
+        }
+
+        &[]
+    }
+
+    fn resource_exists(&mut self, _context: &Context) -> Deferred<Self, bool> {
+        self.exists.into()
+    }
+
+    fn generate_etag(&mut self, _context: &Context) -> Option<http::header::HeaderValue> {
+        self.etag.map(http::header::HeaderValue::from_static)
+    }
+
+    fn last_modified(&mut self, _context: &Context) -> Option<SystemTime> {
+        self.last_modified
+    }
+
+    fn moved_permanently(&mut self, _context: &Context) -> Option<http::Uri> {
+        self.moved_permanently.map(|uri| uri.parse().unwrap())
+    }
+
+    fn moved_temporarily(&mut self, _context: &Context) -> Option<http::Uri> {
+        self.moved_temporarily.map(|uri| uri.parse().unwrap())
+    }
+
+    fn previously_existed(&mut self, _context: &Context) -> bool {
+        self.previously_existed
+    }
+
+    fn allow_missing_post(&mut self, _context: &Context) -> bool {
+        self.allow_missing_post
+    }
+
+    fn delete_resource(&mut self, _context: &Context) -> Deferred<Self, bool> {
+        self.delete_resource.into()
+    }
+
+    fn delete_completed(&mut self, _context: &Context) -> bool {
+        self.delete_completed
+    }
+
+    fn post_is_create(&mut self, _context: &Context) -> bool {
+        self.post_is_create
+    }
+
+    fn create_path(&mut self, _context: &Context) -> String {
+        "/things/1".to_string()
+    }
+
+    fn process_post(&mut self, context: &mut Context, response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        self.from_text(context, response)
+    }
+
+    fn is_conflict(&mut self, _context: &Context) -> bool {
+        self.is_conflict
+    }
+
+    fn multiple_choices(&mut self, _context: &Context) -> bool {
+        self.multiple_choices
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        if self.provides_nothing {
+            return &[];
+        }
+
+        &[ProvidedPair(mime::TEXT_HTML, Self::to_html), ProvidedPair(mime::TEXT_PLAIN, Self::to_html)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::TEXT_PLAIN, Self::from_text)]
+    }
+}
+
+impl Decisions {
+    fn to_html(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "<p>thing</p>".into()
+    }
+
+    fn from_text(&mut self, _context: &mut Context, response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        if self.redirect {
+            response.redirect().unwrap();
+        }
+
+        ().into()
+    }
+
+    fn gzip(&self, _context: &Context) {}
+}
+
+/// A request with an empty body, announced as such where a body is expected.
+fn request(method: Method) -> RequestBuilder {
+    let has_body = method == Method::POST || method == Method::PUT || method == Method::DELETE;
+
+    let mut builder = RequestBuilder::new(method, "/things/1");
+    builder.header("Host", "example.com");
+    if has_body {
+        builder.header("Content-Length", "0");
+    }
+
+    builder
+}
+
+/// A request with an empty body in a content type the resource accepts.
+fn send(method: Method) -> RequestBuilder {
+    let mut builder = request(method);
+    builder.header("Content-Type", "text/plain");
+    builder
+}
+
+fn get() -> RequestBuilder {
+    request(Method::GET)
+}
+
+fn missing() -> Decisions {
+    Decisions { exists: false, ..Decisions::default() }
+}
+
+fn http_date(time: SystemTime) -> String {
+    HttpDate::from(time).to_string()
+}
+
+/// A last modification date without sub-second precision.
+fn modified() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_500_000_000)
+}
+
+fn assert_halted(response: &TestResponse, status: StatusCode, node: &str) {
+    response.assert_status(status).assert_decided_at(node);
+}
+
+// B13 to B3: service, request and method checks.
+
+#[test]
+fn test_b13_service_unavailable() {
+    let response = get().execute(Decisions { service_available: false, ..Decisions::default() });
+    assert_halted(&response, StatusCode::SERVICE_UNAVAILABLE, "b13");
+}
+
+#[test]
+fn test_b12_unknown_method() {
+    let response = RequestBuilder::new(Method::from_bytes(b"MOVE").unwrap(), "/").execute(Decisions::default());
+    assert_halted(&response, StatusCode::NOT_IMPLEMENTED, "b12");
+}
+
+#[test]
+fn test_b11_uri_too_long() {
+    let response = get().execute(Decisions { uri_too_long: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::URI_TOO_LONG, "b11");
+}
+
+#[test]
+fn test_b10_method_not_allowed_lists_allowed_methods() {
+    let response = send(Method::PUT).execute(Decisions { allowed_methods: &[Method::GET, Method::HEAD], ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::METHOD_NOT_ALLOWED, "b10");
+    response.assert_header("Allow", "GET, HEAD");
+}
+
+#[test]
+fn test_b9_malformed_request() {
+    let response = get().execute(Decisions { malformed_request: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::BAD_REQUEST, "b9");
+}
+
+#[test]
+fn test_b8_unauthorized() {
+    let response = get().execute(Decisions { authorized: false, ..Decisions::default() });
+    assert_halted(&response, StatusCode::UNAUTHORIZED, "b8");
+}
+
+#[test]
+fn test_b7_forbidden() {
+    let response = get().execute(Decisions { forbidden: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::FORBIDDEN, "b7");
+}
+
+#[test]
+fn test_b6_invalid_content_headers() {
+    let response = send(Method::PUT)
+        .header("Content-Foo", "bar")
+        .execute(Decisions { valid_content_headers: false, ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::NOT_IMPLEMENTED, "b6");
+}
+
+#[test]
+fn test_b5_unknown_content_type() {
+    let response = send(Method::PUT).execute(Decisions { known_content_type: false, ..Decisions::default() });
+    assert_halted(&response, StatusCode::UNSUPPORTED_MEDIA_TYPE, "b5");
+}
+
+#[test]
+fn test_b4_entity_too_large() {
+    let response = send(Method::POST)
+        .header("Content-Length", "1000000")
+        .execute(Decisions { valid_entity_length: false, ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::PAYLOAD_TOO_LARGE, "b4");
+}
+
+#[test]
+fn test_b3_options() {
+    let response = request(Method::OPTIONS).execute(Decisions::default());
+    assert_halted(&response, StatusCode::OK, "b3");
+}
+
+// C3 to F7: content negotiation.
+
+#[test]
+fn test_c3_defaults_to_first_content_type() {
+    get().execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_visited("c3")
+        .assert_not_visited("c4");
+}
+
+#[test]
+fn test_c4_acceptable_content_type() {
+    get().header("Accept", "text/plain")
+        .execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_visited("c4");
+}
+
+#[test]
+fn test_c4_content_type_not_acceptable() {
+    let response = get().header("Accept", "image/png, text/html;q=0").execute(Decisions::default());
+    assert_halted(&response, StatusCode::NOT_ACCEPTABLE, "c4");
+}
+
+#[test]
+fn test_c3_nothing_provided() {
+    let response = get().execute(Decisions { provides_nothing: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::INTERNAL_SERVER_ERROR, "o18");
+
+    // Requests without a representation in the response don't need one.
+    send(Method::PUT)
+        .execute(Decisions { provides_nothing: true, ..Decisions::default() })
+        .assert_status(StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_c4_nothing_provided() {
+    let response = get().header("Accept", "text/html").execute(Decisions { provides_nothing: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::NOT_ACCEPTABLE, "c4");
+}
+
+#[test]
+fn test_c4_malformed_accept() {
+    let response = get().header("Accept", "text/html;q=high").execute(Decisions::default());
+    assert_halted(&response, StatusCode::BAD_REQUEST, "c4");
+}
+
+#[test]
+fn test_d5_language_not_acceptable() {
+    let response = get().header("Accept-Language", "de").execute(Decisions { languages: vec![HeaderValue::from_static("en")], ..Decisions::default() });
+    assert_halted(&response, StatusCode::NOT_ACCEPTABLE, "d5");
+}
+
+#[test]
+fn test_d5_acceptable_language() {
+    get().header("Accept-Language", "en")
+        .execute(Decisions { languages: vec![HeaderValue::from_static("en")], ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("d5");
+}
+
+#[test]
+fn test_d5_any_language_without_languages_provided() {
+    get().header("Accept-Language", "de")
+        .execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_visited("d5");
+}
+
+#[test]
+fn test_e6_any_charset_without_charsets_provided() {
+    get().header("Accept-Charset", "utf-8")
+        .execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_visited("e6")
+        .assert_visited("f6");
+}
+
+#[test]
+fn test_e6_charset_not_acceptable() {
+    let response = get().header("Accept-Charset", "iso-8859-5").execute(Decisions { charsets: vec![HeaderValue::from_static("utf-8")], ..Decisions::default() });
+    assert_halted(&response, StatusCode::NOT_ACCEPTABLE, "e6");
+}
+
+#[test]
+fn test_e6_acceptable_charset() {
+    get().header("Accept-Charset", "utf-8")
+        .execute(Decisions { charsets: vec![HeaderValue::from_static("utf-8")], ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("e6");
+}
+
+#[test]
+fn test_f7_accept_encoding() {
+    get().header("Accept-Encoding", "gzip")
+        .execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_visited("f7");
+}
+
+#[test]
+fn test_f7_provided_encoding() {
+    get().header("Accept-Encoding", "gzip, identity;q=0")
+        .execute(Decisions { gzip: true, ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("f7");
+}
+
+#[test]
+fn test_f7_identity_not_acceptable() {
+    let response = get().header("Accept-Encoding", "gzip, identity;q=0").execute(Decisions::default());
+    assert_halted(&response, StatusCode::NOT_ACCEPTABLE, "f7");
+}
+
+#[test]
+fn test_f7_malformed_accept_encoding() {
+    let response = get().header("Accept-Encoding", "gzip;q=high").execute(Decisions::default());
+    assert_halted(&response, StatusCode::BAD_REQUEST, "f7");
+}
+
+// G7 to K13: existence and preconditions.
+
+#[test]
+fn test_g9_if_match_any() {
+    get().header("If-Match", "*")
+        .execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_visited("g9")
+        .assert_not_visited("g11");
+}
+
+#[test]
+fn test_g11_if_match_etag() {
+    get().header("If-Match", "\"a\", \"b\"")
+        .execute(Decisions { etag: Some("\"b\""), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("g11");
+}
+
+#[test]
+fn test_g11_if_match_other_etag() {
+    let response = get().header("If-Match", "\"a\"").execute(Decisions { etag: Some("\"b\""), ..Decisions::default() });
+    assert_halted(&response, StatusCode::PRECONDITION_FAILED, "g11");
+}
+
+#[test]
+fn test_g11_if_match_weak_etag() {
+    let response = get().header("If-Match", "W/\"b\"").execute(Decisions { etag: Some("W/\"b\""), ..Decisions::default() });
+    assert_halted(&response, StatusCode::PRECONDITION_FAILED, "g11");
+}
+
+#[test]
+fn test_h7_if_match_any_on_missing_resource() {
+    let response = get().header("If-Match", "*").execute(missing());
+    assert_halted(&response, StatusCode::PRECONDITION_FAILED, "h7");
+}
+
+#[test]
+fn test_h11_invalid_if_unmodified_since() {
+    get().header("If-Unmodified-Since", "yesterday")
+        .execute(Decisions { last_modified: Some(modified()), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("h11")
+        .assert_not_visited("h12");
+}
+
+#[test]
+fn test_h12_modified_since() {
+    let response = get()
+        .header("If-Unmodified-Since", &*http_date(modified() - Duration::from_secs(60)))
+        .execute(Decisions { last_modified: Some(modified()), ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::PRECONDITION_FAILED, "h12");
+}
+
+#[test]
+fn test_h12_unmodified_since() {
+    get().header("If-Unmodified-Since", &*http_date(modified()))
+        .execute(Decisions { last_modified: Some(modified()), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("h12");
+}
+
+#[test]
+fn test_i4_put_to_moved_resource() {
+    let response = send(Method::PUT)
+        .execute(Decisions { moved_permanently: Some("http://example.com/things/2"), ..missing() });
+
+    assert_halted(&response, StatusCode::MOVED_PERMANENTLY, "i4");
+    response.assert_header("Location", "http://example.com/things/2");
+}
+
+#[test]
+fn test_j18_if_none_match_any() {
+    let response = get().header("If-None-Match", "*").execute(Decisions { etag: Some("\"a\""), ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::NOT_MODIFIED, "j18");
+    response.assert_header("ETag", "\"a\"").assert_body("");
+}
+
+#[test]
+fn test_j18_if_none_match_any_on_put() {
+    let response = send(Method::PUT).header("If-None-Match", "*").execute(Decisions::default());
+    assert_halted(&response, StatusCode::PRECONDITION_FAILED, "j18");
+}
+
+#[test]
+fn test_k5_moved_permanently() {
+    let response = get().execute(Decisions {
+        previously_existed: true,
+        moved_permanently: Some("http://example.com/things/2"),
+        ..missing()
+    });
+
+    assert_halted(&response, StatusCode::MOVED_PERMANENTLY, "k5");
+    response.assert_header("Location", "http://example.com/things/2");
+}
+
+#[test]
+fn test_k13_if_none_match_etag() {
+    let response = get().header("If-None-Match", "\"a\", W/\"b\"").execute(Decisions { etag: Some("\"b\""), ..Decisions::default() });
+    assert_halted(&response, StatusCode::NOT_MODIFIED, "j18");
+    response.assert_visited("k13");
+}
+
+#[test]
+fn test_k13_if_none_match_other_etag() {
+    get().header("If-None-Match", "\"a\"")
+        .execute(Decisions { etag: Some("\"b\""), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("k13")
+        .assert_visited("l13");
+}
+
+// L5 to L17: missing resources and If-Modified-Since.
+
+#[test]
+fn test_l5_moved_temporarily() {
+    let response = get().execute(Decisions {
+        previously_existed: true,
+        moved_temporarily: Some("http://example.com/things/3"),
+        ..missing()
+    });
+
+    assert_halted(&response, StatusCode::TEMPORARY_REDIRECT, "l5");
+    response.assert_header("Location", "http://example.com/things/3");
+}
+
+#[test]
+fn test_l7_not_found() {
+    let response = get().execute(missing());
+
+    assert_halted(&response, StatusCode::NOT_FOUND, "l7");
+    response.assert_trace(&["b13", "b12", "b11", "b10", "b9", "b8", "b7", "b6", "b5", "b4", "b3",
+                            "c3", "d4", "e5", "f6", "g7", "h7", "i7", "k7", "l7"]);
+}
+
+#[test]
+fn test_l14_invalid_if_modified_since() {
+    get().header("If-Modified-Since", "yesterday")
+        .execute(Decisions { last_modified: Some(modified()), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("l14")
+        .assert_not_visited("l15");
+}
+
+#[test]
+fn test_l15_if_modified_since_in_the_future() {
+    get().header("If-Modified-Since", &*http_date(SystemTime::now() + Duration::from_secs(86400)))
+        .execute(Decisions { last_modified: Some(modified()), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("l15")
+        .assert_not_visited("l17");
+}
+
+#[test]
+fn test_l17_not_modified() {
+    let response = get()
+        .header("If-Modified-Since", &*http_date(modified()))
+        .execute(Decisions { last_modified: Some(modified() - Duration::from_millis(1500)), ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::NOT_MODIFIED, "l17");
+    response.assert_body("");
+}
+
+#[test]
+fn test_l17_modified() {
+    get().header("If-Modified-Since", &*http_date(modified() - Duration::from_secs(60)))
+        .execute(Decisions { last_modified: Some(modified()), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_visited("l17");
+}
+
+// M5 to N11: gone resources, deletion and POST.
+
+#[test]
+fn test_m5_gone() {
+    let response = get().execute(Decisions { previously_existed: true, ..missing() });
+    assert_halted(&response, StatusCode::GONE, "m5");
+}
+
+#[test]
+fn test_m7_post_to_missing_resource() {
+    let response = send(Method::POST).execute(missing());
+    assert_halted(&response, StatusCode::NOT_FOUND, "m7");
+}
+
+#[test]
+fn test_m7_allow_missing_post() {
+    send(Method::POST)
+        .execute(Decisions { allow_missing_post: true, ..missing() })
+        .assert_status(StatusCode::NO_CONTENT)
+        .assert_visited("m7")
+        .assert_visited("n11");
+}
+
+#[test]
+fn test_m20_delete_failed() {
+    let response = request(Method::DELETE).execute(Decisions { delete_resource: false, ..Decisions::default() });
+    assert_halted(&response, StatusCode::INTERNAL_SERVER_ERROR, "m20");
+}
+
+#[test]
+fn test_m20b_delete_accepted() {
+    let response = request(Method::DELETE).execute(Decisions { delete_completed: false, ..Decisions::default() });
+    assert_halted(&response, StatusCode::ACCEPTED, "m20b");
+}
+
+#[test]
+fn test_m20b_deleted() {
+    let response = request(Method::DELETE).execute(Decisions::default());
+
+    assert_halted(&response, StatusCode::NO_CONTENT, "o20");
+    response.assert_visited("m20b").assert_body("");
+}
+
+#[test]
+fn test_n5_post_to_gone_resource() {
+    let response = send(Method::POST).execute(Decisions { previously_existed: true, ..missing() });
+    assert_halted(&response, StatusCode::GONE, "n5");
+}
+
+#[test]
+fn test_n5_allow_missing_post() {
+    send(Method::POST)
+        .execute(Decisions { previously_existed: true, allow_missing_post: true, ..missing() })
+        .assert_status(StatusCode::NO_CONTENT)
+        .assert_visited("n5")
+        .assert_visited("n11");
+}
+
+#[test]
+fn test_n11_post_is_create() {
+    let response = send(Method::POST).execute(Decisions { post_is_create: true, ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::CREATED, "p11");
+    response.assert_visited("n11").assert_header("Location", "http://example.com/things/1");
+}
+
+#[test]
+fn test_n11_post_is_create_redirect() {
+    let response = send(Method::POST).execute(Decisions { post_is_create: true, redirect: true, ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::SEE_OTHER, "n11");
+    response.assert_header("Location", "http://example.com/things/1");
+}
+
+#[test]
+fn test_n11_process_post_redirect_without_location() {
+    let response = send(Method::POST).execute(Decisions { redirect: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::INTERNAL_SERVER_ERROR, "n11");
+}
+
+#[test]
+fn test_n11_post_is_create_unsupported_content_type() {
+    let response = request(Method::POST)
+        .header("Content-Type", "application/json")
+        .execute(Decisions { post_is_create: true, ..Decisions::default() });
+
+    assert_halted(&response, StatusCode::UNSUPPORTED_MEDIA_TYPE, "n11");
+}
+
+#[test]
+fn test_n11_post_is_create_content_type_parameters() {
+    request(Method::POST)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .execute(Decisions { post_is_create: true, ..Decisions::default() })
+        .assert_status(StatusCode::CREATED)
+        .assert_header("Location", "http://example.com/things/1");
+}
+
+#[test]
+fn test_n11_process_post() {
+    let response = send(Method::POST).execute(Decisions::default());
+
+    assert_halted(&response, StatusCode::NO_CONTENT, "o20");
+    response.assert_visited("n11").assert_visited("p11");
+}
+
+// O14 to P11: PUT and responses.
+
+#[test]
+fn test_o14_conflict() {
+    let response = send(Method::PUT).execute(Decisions { is_conflict: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::CONFLICT, "o14");
+}
+
+#[test]
+fn test_o14_put() {
+    let response = send(Method::PUT).execute(Decisions::default());
+
+    assert_halted(&response, StatusCode::NO_CONTENT, "o20");
+    response.assert_visited("o14").assert_visited("p11");
+}
+
+#[test]
+fn test_o14_put_unsupported_content_type() {
+    let response = request(Method::PUT)
+        .header("Content-Type", "application/json")
+        .execute(Decisions::default());
+
+    assert_halted(&response, StatusCode::UNSUPPORTED_MEDIA_TYPE, "o14");
+}
+
+#[test]
+fn test_o18_validators() {
+    get().execute(Decisions { etag: Some("\"a\""), last_modified: Some(modified()), ..Decisions::default() })
+        .assert_status(StatusCode::OK)
+        .assert_decided_at("o18")
+        .assert_header("ETag", "\"a\"")
+        .assert_header("Last-Modified", &http_date(modified()))
+        .assert_body("<p>thing</p>");
+}
+
+#[test]
+fn test_o18_multiple_choices() {
+    let response = get().execute(Decisions { multiple_choices: true, ..Decisions::default() });
+    assert_halted(&response, StatusCode::MULTIPLE_CHOICES, "o18");
+}
+
+#[test]
+fn test_p3_conflict() {
+    let response = send(Method::PUT).execute(Decisions { is_conflict: true, ..missing() });
+    assert_halted(&response, StatusCode::CONFLICT, "p3");
+}
+
+#[test]
+fn test_p3_put_to_missing_resource() {
+    let response = send(Method::PUT).execute(missing());
+
+    assert_halted(&response, StatusCode::NO_CONTENT, "o20");
+    response.assert_trace(&["b13", "b12", "b11", "b10", "b9", "b8", "b7", "b6", "b5", "b4", "b3",
+                            "c3", "d4", "e5", "f6", "g7", "h7", "i7", "i4", "p3", "p11", "o20"]);
+}
+
+#[test]
+fn test_successful_get() {
+    get().execute(Decisions::default())
+        .assert_status(StatusCode::OK)
+        .assert_trace(&["b13", "b12", "b11", "b10", "b9", "b8", "b7", "b6", "b5", "b4", "b3",
+                        "c3", "d4", "e5", "f6", "g7", "g8", "h10", "i12", "l13", "m16", "n16", "o16", "o18"]);
+}
@@ -0,0 +1,238 @@
+#![cfg(all(feature = "json", feature = "multipart"))]
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate hyper;
+extern crate futures;
+#[macro_use]
+extern crate serde_derive;
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::process;
+use std::thread;
+
+use futures::{Future, Sink};
+
+use gerust::context::Context;
+use gerust::decode::{self, Multipart};
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, AcceptedPair, ProvidedPair, Handles, Deferred};
+use gerust::response::Entity;
+use gerust::testing::{self, RequestBuilder, TestResponse};
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    id: u32,
+    title: String,
+}
+
+#[derive(Default, Debug)]
+struct Orders {
+    order: Option<Order>,
+}
+
+impl Resource for Orders {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::POST]
+    }
+
+    fn post_is_create(&mut self, _context: &Context) -> bool {
+        true
+    }
+
+    fn create_path(&mut self, _context: &Context) -> String {
+        let order = self.order.as_ref().unwrap();
+        format!("/orders/{}/{}", order.id, order.title)
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[
+            AcceptedPair(mime::APPLICATION_JSON, decode::json),
+            AcceptedPair(mime::APPLICATION_WWW_FORM_URLENCODED, decode::form),
+        ]
+    }
+}
+
+impl Orders {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "".into()
+    }
+}
+
+impl Handles for Orders {
+    type Item = Order;
+
+    fn handle(&mut self, order: Order) {
+        self.order = Some(order);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Photo {
+    title: String,
+    year: u16,
+}
+
+#[derive(Default, Debug)]
+struct Photos {
+    location: Option<String>,
+}
+
+impl Resource for Photos {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::POST]
+    }
+
+    fn post_is_create(&mut self, _context: &Context) -> bool {
+        true
+    }
+
+    fn create_path(&mut self, _context: &Context) -> String {
+        self.location.take().unwrap()
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::MULTIPART_FORM_DATA, decode::multipart)]
+    }
+}
+
+impl Photos {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "".into()
+    }
+}
+
+impl Handles for Photos {
+    type Item = Multipart<Photo>;
+
+    fn handle(&mut self, upload: Multipart<Photo>) {
+        let mut files = Vec::new();
+
+        for file in upload.files {
+            let mut content = String::new();
+            file.open().unwrap().read_to_string(&mut content).unwrap();
+
+            let kept = env::temp_dir().join(format!("gerust-photo-{}-{}", process::id(), file.filename().unwrap()));
+            files.push(format!("{}:{}:{}:{}", file.name(), file.filename().unwrap(), file.content_type().unwrap(), content.escape_default()));
+            file.persist(kept).unwrap();
+        }
+
+        self.location = Some(format!("/photos/{}/{}/{}", upload.fields.title, upload.fields.year, files.join(",")));
+    }
+}
+
+fn post<R: Resource + Send + std::fmt::Debug>(resource: R, content_type: &str, body: &str) -> TestResponse {
+    RequestBuilder::post("/")
+        .header("Content-Type", content_type)
+        .header("Content-Length", body.len().to_string())
+        .body(body.to_string())
+        .execute(resource)
+}
+
+/// Posts `body` in chunks of three bytes.
+fn post_slowly<R: Resource + Send + std::fmt::Debug>(resource: R, content_type: &str, body: &str) -> TestResponse {
+    let mut request = RequestBuilder::post("/").header("Content-Type", content_type).build();
+    let (sender, chunked) = hyper::Body::pair();
+    *request.body_mut() = chunked;
+
+    let chunks: Vec<_> = body.as_bytes().chunks(3).map(|chunk| Ok(hyper::Chunk::from(chunk.to_vec()))).collect();
+    let feeder = thread::spawn(move || {
+        let _ = sender.send_all(futures::stream::iter_ok(chunks)).wait();
+    });
+
+    let response = testing::execute(resource, request);
+    feeder.join().unwrap();
+    response
+}
+
+fn form_data(boundary: &str, parts: &[(&str, &str)]) -> String {
+    let mut body = "preamble".to_string();
+
+    for &(headers, content) in parts {
+        body.push_str(&format!("\r\n--{}\r\n{}\r\n\r\n{}", boundary, headers, content));
+    }
+
+    body + &format!("\r\n--{}--\r\nepilogue", boundary)
+}
+
+#[test]
+fn test_json() {
+    post(Orders::default(), "application/json", r#"{"id": 7, "title": "Tea"}"#)
+        .assert_status(http::StatusCode::CREATED)
+        .assert_header("Location", "/orders/7/Tea");
+}
+
+#[test]
+fn test_invalid_json() {
+    let response = post(Orders::default(), "application/json", r#"{"id": 7"#);
+
+    response.assert_status(http::StatusCode::BAD_REQUEST);
+    assert!(response.text().starts_with("invalid JSON: "), "{}", response.text());
+
+    post(Orders::default(), "application/json", r#"{"id": 7}"#)
+        .assert_status(http::StatusCode::BAD_REQUEST)
+        .assert_body("invalid JSON: missing field `title` at line 1 column 9");
+}
+
+#[test]
+fn test_form() {
+    post(Orders::default(), "application/x-www-form-urlencoded", "id=8&title=Green+Tea")
+        .assert_status(http::StatusCode::CREATED)
+        .assert_header("Location", "/orders/8/Green Tea");
+
+    post(Orders::default(), "application/x-www-form-urlencoded", "id=eight&title=Tea")
+        .assert_status(http::StatusCode::BAD_REQUEST)
+        .assert_body("invalid form: invalid digit found in string");
+}
+
+#[test]
+fn test_multipart() {
+    let body = form_data("XyZ", &[
+        ("Content-Disposition: form-data; name=\"title\"", "Sunset"),
+        ("content-disposition: form-data; name=year", "2017"),
+        ("Content-Disposition: form-data; name=\"photo\"; filename=\"sun \\\"1\\\".txt\"\r\nContent-Type: text/plain",
+         "not a boundary:\r\n--Xy-ish\r\n--Xy"),
+    ]);
+
+    let response = post_slowly(Photos::default(), "multipart/form-data; boundary=XyZ", &body);
+    response.assert_status(http::StatusCode::CREATED);
+
+    let filename = "sun \"1\".txt";
+    let content = "not a boundary:\r\n--Xy-ish\r\n--Xy";
+    let expected = format!("/photos/Sunset/2017/photo:{}:text/plain:{}", filename, content.escape_default());
+    assert_eq!(response.header("Location"), Some(&*expected));
+
+    let kept = env::temp_dir().join(format!("gerust-photo-{}-{}", process::id(), filename));
+    assert_eq!(fs::read_to_string(&kept).unwrap(), content);
+    fs::remove_file(kept).unwrap();
+}
+
+#[test]
+fn test_invalid_multipart() {
+    let reject = |content_type: &str, body: &str, reason: &str| {
+        post(Photos::default(), content_type, body)
+            .assert_status(http::StatusCode::BAD_REQUEST)
+            .assert_body(reason);
+    };
+
+    let title = ("Content-Disposition: form-data; name=\"title\"", "Sunset");
+
+    reject("multipart/form-data", "", "missing multipart boundary");
+    reject("multipart/form-data; boundary=XyZ", "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nSun",
+           "invalid multipart body: body ended early");
+    reject("multipart/form-data; boundary=XyZ", &form_data("XyZ", &[("Content-Type: text/plain", "x")]),
+           "invalid multipart body: part without Content-Disposition");
+    reject("multipart/form-data; boundary=XyZ", &form_data("XyZ", &[title]), "invalid form: missing field `year`");
+}
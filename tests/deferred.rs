@@ -0,0 +1,114 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate tokio_core;
+extern crate http;
+extern crate hyper;
+extern crate futures;
+extern crate futures_cpupool;
+
+use std::thread;
+use std::time::Duration;
+
+use futures::{future, Future, Stream};
+use futures::sync::oneshot;
+
+use gerust::context::Context;
+use gerust::error::Error;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+
+mod helper;
+
+/// Loads its entity from a "backend" that answers on another thread.
+#[derive(Default, Debug)]
+struct SlowResource {
+    entity: Option<String>,
+}
+
+fn load(id: &str) -> oneshot::Receiver<Option<String>> {
+    let (sx, rx) = oneshot::channel();
+    let id = id.to_string();
+
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        let _ = sx.send(Some(format!("entity at {}", id)));
+    });
+
+    rx
+}
+
+impl Resource for SlowResource {
+    fn is_authorized(&mut self, context: &Context) -> Deferred<Self, bool> {
+        let authorized = context.headers().get(http::header::AUTHORIZATION).is_some();
+
+        Deferred::future(future::ok(authorized))
+    }
+
+    fn forbidden(&mut self, context: &Context) -> Deferred<Self, bool> {
+        if context.uri().path() == "/broken" {
+            Deferred::future(future::err(Error::DefaultError))
+        } else {
+            false.into()
+        }
+    }
+
+    fn resource_exists(&mut self, context: &Context) -> Deferred<Self, bool> {
+        let loading = load(context.uri().path()).map_err(|_| Error::DefaultError);
+
+        Deferred::apply(loading.map(|entity| move |this: &mut Self| {
+            this.entity = entity;
+            this.entity.is_some()
+        }))
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl SlowResource {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        let entity = self.entity.take().unwrap();
+
+        Entity::stream(futures::stream::once(Ok(entity))).into()
+    }
+}
+
+fn request(path: &str) -> http::request::Builder {
+    let mut builder = http::request::Builder::new();
+    builder.method(http::method::Method::GET).uri(path);
+    builder
+}
+
+#[test]
+fn test_deferred_unauthorized() {
+    let req = request("/orders/1").body("".into()).unwrap();
+
+    let response = helper::execute(SlowResource::default(), req);
+
+    assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_deferred_error_is_internal_server_error() {
+    let req = request("/broken").header("Authorization", "Token t").body("".into()).unwrap();
+
+    let response = helper::execute(SlowResource::default(), req);
+
+    assert_eq!(response.status(), http::StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn test_deferred_entity_and_body() {
+    let req = request("/orders/1").header("Authorization", "Token t").body("".into()).unwrap();
+
+    let response = helper::execute(SlowResource::default(), req);
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+
+    let body = response.into_parts().1.concat2().wait().unwrap();
+    assert_eq!(&body[..], b"entity at /orders/1");
+}
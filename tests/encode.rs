@@ -0,0 +1,90 @@
+#![cfg(all(feature = "json", feature = "cbor", feature = "msgpack", feature = "yaml"))]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+#[macro_use]
+extern crate lazy_static;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_cbor;
+extern crate rmp_serde;
+extern crate serde_yaml;
+
+use gerust::context::Context;
+use gerust::encode;
+use gerust::resource::{Resource, ProvidedPair, Represents};
+use gerust::testing::{RequestBuilder, TestResponse};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Order {
+    id: u32,
+    title: String,
+    tags: Vec<String>,
+}
+
+#[derive(Default, Debug)]
+struct OrderResource;
+
+lazy_static! {
+    static ref PROVIDED: [ProvidedPair<OrderResource>; 4] = [
+        ProvidedPair(mime::APPLICATION_JSON, encode::json),
+        ProvidedPair(encode::APPLICATION_CBOR.clone(), encode::cbor),
+        ProvidedPair(encode::APPLICATION_MSGPACK.clone(), encode::msgpack),
+        ProvidedPair(encode::APPLICATION_YAML.clone(), encode::yaml),
+    ];
+}
+
+impl Resource for OrderResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &*PROVIDED
+    }
+}
+
+impl Represents for OrderResource {
+    type Representation = Order;
+
+    fn represent(&mut self, _context: &Context) -> Order {
+        order()
+    }
+}
+
+fn order() -> Order {
+    Order { id: 7, title: "Tea".to_string(), tags: vec!["green".to_string()] }
+}
+
+fn get(accept: &str) -> TestResponse {
+    let response = RequestBuilder::get("/orders/7").header("Accept", accept).execute(OrderResource);
+    response.assert_status(http::StatusCode::OK).assert_header("Content-Type", accept);
+    response
+}
+
+#[test]
+fn test_json() {
+    let response = get("application/json");
+    assert_eq!(response.text(), r#"{"id":7,"title":"Tea","tags":["green"]}"#);
+}
+
+#[test]
+fn test_cbor() {
+    assert_eq!(serde_cbor::from_slice::<Order>(get("application/cbor").body()).unwrap(), order());
+}
+
+#[test]
+fn test_msgpack() {
+    assert_eq!(rmp_serde::from_slice::<Order>(get("application/msgpack").body()).unwrap(), order());
+}
+
+#[test]
+fn test_yaml() {
+    assert_eq!(serde_yaml::from_slice::<Order>(get("application/yaml").body()).unwrap(), order());
+}
+
+#[test]
+fn test_first_provider_is_the_default() {
+    RequestBuilder::get("/orders/7")
+        .execute(OrderResource)
+        .assert_header("Content-Type", "application/json");
+}
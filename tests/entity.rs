@@ -0,0 +1,172 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+
+use futures::stream;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::RequestBuilder;
+
+#[derive(Default, Debug)]
+struct EntityResource {
+    file: Option<PathBuf>,
+}
+
+impl Resource for EntityResource {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::GET, http::Method::HEAD]
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl EntityResource {
+    fn to_text(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        match context.uri().path() {
+            "/string" => format!("Hello, {}!", "World").into(),
+            "/bytes" => vec![1u8, 2, 3].into(),
+            "/misreported" => Entity::from(vec![1u8, 2, 3]).length(10).into(),
+            "/stream" => Entity::stream(stream::iter_ok(vec!["one ", "two ", "three"])).into(),
+            "/sized" => Entity::stream(stream::iter_ok(vec!["one ", "two"])).length(7).into(),
+            "/mixed" => {
+                response.write("written ").unwrap();
+                "returned".into()
+            },
+            "/file" => Entity::open(self.file.as_ref().unwrap()).unwrap().into(),
+            "/seeked" => {
+                let mut file = File::open(self.file.as_ref().unwrap()).unwrap();
+                file.seek(SeekFrom::Start(100)).unwrap();
+                Entity::file(file).unwrap().into()
+            },
+            _ => ().into(),
+        }
+    }
+}
+
+/// A temporary file larger than a single file chunk.
+struct TempFile {
+    path: PathBuf,
+    content: Vec<u8>,
+}
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        let path = env::temp_dir().join(format!("gerust-entity-{}-{}", process::id(), name));
+        let content: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+        File::create(&path).unwrap().write_all(&content).unwrap();
+
+        TempFile { path, content }
+    }
+
+    fn resource(&self) -> EntityResource {
+        EntityResource { file: Some(self.path.clone()) }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn test_string_sets_content_length() {
+    RequestBuilder::get("/string")
+        .execute(EntityResource::default())
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Content-Length", "13")
+        .assert_body("Hello, World!");
+}
+
+#[test]
+fn test_bytes() {
+    RequestBuilder::get("/bytes")
+        .execute(EntityResource::default())
+        .assert_header("Content-Length", "3")
+        .assert_body([1, 2, 3]);
+}
+
+#[test]
+fn test_length_of_bytes_is_kept() {
+    RequestBuilder::get("/misreported")
+        .execute(EntityResource::default())
+        .assert_header("Content-Length", "3")
+        .assert_body([1, 2, 3]);
+}
+
+#[test]
+fn test_stream_has_no_content_length() {
+    let response = RequestBuilder::get("/stream").execute(EntityResource::default());
+
+    response.assert_body("one two three");
+    assert_eq!(response.header("Content-Length"), None);
+}
+
+#[test]
+fn test_stream_with_length() {
+    RequestBuilder::get("/sized")
+        .execute(EntityResource::default())
+        .assert_header("Content-Length", "7")
+        .assert_body("one two");
+}
+
+#[test]
+fn test_returned_body_follows_written_chunks() {
+    let response = RequestBuilder::get("/mixed").execute(EntityResource::default());
+
+    response.assert_body("written returned");
+    assert_eq!(response.header("Content-Length"), None);
+}
+
+#[test]
+fn test_empty_entity() {
+    RequestBuilder::get("/nothing")
+        .execute(EntityResource::default())
+        .assert_status(http::StatusCode::OK)
+        .assert_body("");
+}
+
+#[test]
+fn test_file_is_streamed() {
+    let file = TempFile::new("file");
+
+    RequestBuilder::get("/file")
+        .execute(file.resource())
+        .assert_header("Content-Length", "150000")
+        .assert_body(&file.content);
+}
+
+#[test]
+fn test_file_from_position() {
+    let file = TempFile::new("seeked");
+
+    RequestBuilder::get("/seeked")
+        .execute(file.resource())
+        .assert_header("Content-Length", "149900")
+        .assert_body(&file.content[100..]);
+}
+
+#[test]
+fn test_head_gets_length_without_body() {
+    let file = TempFile::new("head");
+
+    RequestBuilder::head("/file")
+        .execute(file.resource())
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Content-Length", "150000")
+        .assert_body("");
+}
@@ -0,0 +1,136 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, AcceptedPair, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::TestServer;
+
+#[derive(Default, Debug)]
+struct Upload;
+
+impl Resource for Upload {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::PUT]
+    }
+
+    fn is_authorized(&mut self, context: &Context) -> Deferred<Self, bool> {
+        context.headers().contains_key(http::header::AUTHORIZATION).into()
+    }
+
+    fn known_content_type(&mut self, _context: &Context, content_type: &http::header::HeaderValue) -> bool {
+        content_type == "text/plain"
+    }
+
+    fn valid_entity_length(&mut self, _context: &Context, len: u64) -> bool {
+        len <= 100
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::TEXT_PLAIN, Self::from_text)]
+    }
+}
+
+impl Upload {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "".into()
+    }
+
+    fn from_text(&mut self, context: &mut Context, _response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        let body = context.take_body().concat2()
+            .map_err(|_| gerust::error::Error::DefaultError)
+            .and_then(|body| if &body[..] == b"hello" { Ok(()) } else { Err(gerust::error::Error::DefaultError) });
+
+        Deferred::future(body)
+    }
+}
+
+/// Sends the head of an upload of "hello" that waits for `100 Continue`.
+fn upload(server: &TestServer, headers: &str) -> BufReader<TcpStream> {
+    let stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(&stream, "PUT /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n{}\r\n", headers).unwrap();
+
+    BufReader::new(stream)
+}
+
+/// Reads a response head, returning its status line.
+fn read_head(reader: &mut BufReader<TcpStream>) -> String {
+    let mut status = String::new();
+    reader.read_line(&mut status).unwrap();
+
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+    }
+
+    status.trim_end().to_string()
+}
+
+fn assert_rejected(headers: &str, status: &str) {
+    let server = TestServer::start::<Upload>();
+    let mut reader = upload(&server, headers);
+
+    assert_eq!(read_head(&mut reader), status);
+
+    // The body never comes, so the connection is closed.
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(!String::from_utf8_lossy(&rest).contains("100 Continue"));
+}
+
+#[test]
+fn test_continue_after_checks() {
+    let server = TestServer::start::<Upload>();
+    let mut reader = upload(&server, "Authorization: yes\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n");
+
+    assert_eq!(read_head(&mut reader), "HTTP/1.1 100 Continue");
+
+    reader.get_mut().write_all(b"hello").unwrap();
+    assert_eq!(read_head(&mut reader), "HTTP/1.1 204 No Content");
+}
+
+#[test]
+fn test_unauthorized_without_continue() {
+    assert_rejected("Content-Type: text/plain\r\nContent-Length: 5\r\n", "HTTP/1.1 401 Unauthorized");
+}
+
+#[test]
+fn test_unknown_content_type_without_continue() {
+    assert_rejected("Authorization: yes\r\nContent-Type: image/png\r\nContent-Length: 5\r\n", "HTTP/1.1 415 Unsupported Media Type");
+}
+
+#[test]
+fn test_too_large_without_continue() {
+    assert_rejected("Authorization: yes\r\nContent-Type: text/plain\r\nContent-Length: 5000\r\n", "HTTP/1.1 413 Payload Too Large");
+}
+
+#[test]
+fn test_requests_without_expect_are_unaffected() {
+    let server = TestServer::start::<Upload>();
+    let stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(&stream, "PUT /upload HTTP/1.1\r\nHost: localhost\r\nAuthorization: yes\r\n\
+                     Content-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+
+    let mut reader = BufReader::new(stream);
+    assert_eq!(read_head(&mut reader), "HTTP/1.1 204 No Content");
+}
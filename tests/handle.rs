@@ -0,0 +1,90 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate hyper;
+extern crate futures;
+
+use futures::{future, Future, Stream};
+
+use gerust::context::Context;
+use gerust::flow::{self, DelayedResponse};
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+
+#[derive(Default, Debug)]
+struct GreetingResource;
+
+impl Resource for GreetingResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl GreetingResource {
+    fn to_text(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        let greeting = format!("Hello from {}", context.uri().path());
+        let _ = response.write(greeting);
+        ().into()
+    }
+}
+
+/// A tiny "framework" that mounts gerust below `/api`.
+fn app(request: http::Request<hyper::Body>) -> Box<dyn Future<Item=http::Response<hyper::Body>, Error=()>> {
+    if request.uri().path().starts_with("/api/") {
+        Box::new(flow::handle(GreetingResource, request).map_err(|_| ()))
+    } else {
+        let response = http::Response::builder().status(404).body(hyper::Body::empty()).unwrap();
+        Box::new(future::ok(response))
+    }
+}
+
+fn get(path: &str) -> http::Request<hyper::Body> {
+    http::Request::get(path).body(hyper::Body::empty()).unwrap()
+}
+
+fn read(body: hyper::Body) -> String {
+    String::from_utf8(body.concat2().wait().unwrap().to_vec()).unwrap()
+}
+
+#[test]
+fn test_handle() {
+    let response = flow::handle(GreetingResource, get("/greeting")).wait().unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(read(response.into_body()), "Hello from /greeting");
+}
+
+#[test]
+fn test_handle_halts() {
+    let request = http::Request::post("/greeting").body(hyper::Body::empty()).unwrap();
+
+    let response = flow::handle(GreetingResource, request).wait().unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[test]
+fn test_mounted_in_another_framework() {
+    let response = app(get("/api/orders")).wait().unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(read(response.into_body()), "Hello from /api/orders");
+
+    let response = app(get("/elsewhere")).wait().unwrap();
+    assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_concurrent_flows() {
+    let responses = (0..16).map(|i| flow::handle(GreetingResource, get(&format!("/{}", i))));
+
+    let bodies = future::join_all(responses)
+        .and_then(|responses| future::join_all(responses.into_iter().map(|response| response.into_body().concat2().map_err(|_| flow::FlowError))))
+        .wait()
+        .unwrap();
+
+    for (i, body) in bodies.iter().enumerate() {
+        assert_eq!(&body[..], format!("Hello from /{}", i).as_bytes());
+    }
+}
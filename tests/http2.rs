@@ -0,0 +1,184 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+extern crate bytes;
+extern crate h2;
+extern crate tokio_core;
+
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Future, Sink, Stream};
+use futures::sync::oneshot;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Core;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::server::{Error, ServerBuilder, ServerHandle};
+
+/// More than the default HTTP/2 flow control window of 64 KiB.
+const BIG_CHUNKS: usize = 20;
+const CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Default, Debug)]
+struct StreamingResource;
+
+impl Resource for StreamingResource {
+    fn resource_exists(&mut self, context: &Context) -> Deferred<Self, bool> {
+        match context.uri().path() {
+            "/slow" => {
+                let (sx, rx) = oneshot::channel();
+
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(200));
+                    let _ = sx.send(true);
+                });
+
+                Deferred::future(rx.map_err(|_| gerust::error::Error::DefaultError))
+            },
+            _ => true.into(),
+        }
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl StreamingResource {
+    fn to_text(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        if context.uri().path() == "/big" {
+            let sender = response.sender().unwrap();
+
+            // Streams from another thread, so the body outlives this callback.
+            thread::spawn(move || {
+                let chunks = (0..BIG_CHUNKS).map(|i| Ok(Ok(vec![b'a' + i as u8; CHUNK_SIZE].into())));
+                let _ = sender.send_all(futures::stream::iter_result(chunks)).wait();
+            });
+        } else {
+            let _ = response.write("Hello, World!");
+        }
+
+        ().into()
+    }
+}
+
+fn start(builder: ServerBuilder) -> (SocketAddr, ServerHandle, JoinHandle<Result<(), Error>>) {
+    let (sx, rx) = mpsc::channel();
+
+    let thread = thread::spawn(move || {
+        let server = builder.build::<StreamingResource>().unwrap();
+        sx.send((server.local_addrs().unwrap()[0], server.handle())).unwrap();
+        server.run()
+    });
+
+    let (addr, handle) = rx.recv().unwrap();
+    (addr, handle, thread)
+}
+
+fn localhost() -> ServerBuilder {
+    ServerBuilder::new().bind(([127, 0, 0, 1], 0).into()).clone()
+}
+
+/// Requests `path` with HTTP/2 prior knowledge.
+fn get(addr: SocketAddr, path: &str) -> Result<(http::response::Parts, Bytes), h2::Error> {
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let uri = format!("http://localhost{}", path);
+
+    let socket = std::net::TcpStream::connect(addr).unwrap();
+    let socket = TcpStream::from_stream(socket, &handle).unwrap();
+
+    let response = h2::client::handshake(socket)
+        .and_then(move |(mut client, connection)| {
+            handle.spawn(connection.map_err(|_| ()));
+
+            let request = http::Request::get(uri).body(()).unwrap();
+            let (response, _) = client.send_request(request, true).unwrap();
+            response
+        })
+        .and_then(|response| {
+            let (parts, mut body) = response.into_parts();
+            let mut release = body.release_capacity().clone();
+
+            body.fold(Vec::new(), move |mut received, chunk| {
+                let _ = release.release_capacity(chunk.len());
+                received.extend_from_slice(&chunk);
+                Ok::<_, h2::Error>(received)
+            }).map(|received| (parts, received.into()))
+        });
+
+    core.run(response)
+}
+
+#[test]
+fn test_prior_knowledge() {
+    let (addr, _, _) = start(localhost());
+
+    let (parts, body) = get(addr, "/").unwrap();
+
+    assert_eq!(parts.status, http::StatusCode::OK);
+    assert_eq!(parts.version, http::Version::HTTP_2);
+    assert_eq!(&body[..], b"Hello, World!");
+}
+
+#[test]
+fn test_streams_body_beyond_flow_control_window() {
+    let (addr, _, _) = start(localhost());
+
+    let (parts, body) = get(addr, "/big").unwrap();
+
+    assert_eq!(parts.status, http::StatusCode::OK);
+    assert_eq!(body.len(), BIG_CHUNKS * CHUNK_SIZE);
+
+    for (i, chunk) in body.chunks(CHUNK_SIZE).enumerate() {
+        assert!(chunk.iter().all(|&b| b == b'a' + i as u8), "chunk {} is garbled", i);
+    }
+}
+
+#[test]
+fn test_http1_on_the_same_port() {
+    use std::io::{Read, Write};
+
+    let (addr, _, _) = start(localhost());
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+}
+
+#[test]
+fn test_http2_disabled() {
+    let (addr, _, _) = start(localhost().http2(false).clone());
+
+    assert!(get(addr, "/").is_err());
+}
+
+#[test]
+fn test_shutdown_drains_streams() {
+    let (addr, handle, thread) = start(localhost());
+
+    let client = thread::spawn(move || get(addr, "/slow"));
+
+    thread::sleep(Duration::from_millis(50));
+    handle.shutdown();
+
+    let (parts, body) = client.join().unwrap().unwrap();
+    assert_eq!(parts.status, http::StatusCode::OK);
+    assert_eq!(&body[..], b"Hello, World!");
+
+    thread.join().unwrap().unwrap();
+}
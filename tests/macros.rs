@@ -0,0 +1,135 @@
+#![cfg(feature = "macros")]
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate http;
+
+use std::fmt::{Debug, Display};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource;
+use gerust::resource::{Resource, Deferred};
+use gerust::response::Entity;
+use gerust::testing::RequestBuilder;
+
+#[derive(Debug, Default)]
+struct Note {
+    text: String,
+}
+
+#[resource(methods(GET, PUT), provides("text/csv" = csv::to_csv), languages("en", "de"), charsets("utf-8"))]
+impl Resource for Note {
+    fn resource_exists(&mut self, _context: &Context) -> Deferred<Self, bool> {
+        (!self.text.is_empty()).into()
+    }
+
+    #[provides("text/plain")]
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        self.text.clone().into()
+    }
+
+    #[provides("text/html")]
+    #[provides("application/xhtml+xml")]
+    fn to_html(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        format!("<p>{}</p>", self.text).into()
+    }
+
+    #[accepts("text/plain")]
+    fn from_text(&mut self, _context: &mut Context, response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        response.header("Accepted-By", "from_text").unwrap();
+        ().into()
+    }
+}
+
+mod csv {
+    use super::*;
+
+    pub fn to_csv(note: &mut Note, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Note, Entity> {
+        format!("text\n{}\n", note.text).into()
+    }
+}
+
+/// Generic, so the lists can't be plain statics.
+#[derive(Debug)]
+struct Value<T> {
+    value: T,
+}
+
+#[resource(charsets("iso-8859-1"))]
+impl<T> Resource for Value<T> where T: Display + Debug + Send + 'static {
+    #[provides("text/plain")]
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        self.value.to_string().into()
+    }
+}
+
+fn note() -> Note {
+    Note { text: "Tea".to_string() }
+}
+
+#[test]
+fn test_provides_in_declared_order() {
+    RequestBuilder::get("/").execute(note())
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Content-Type", "text/plain")
+        .assert_body("Tea");
+
+    for &(accept, body) in &[("text/html", "<p>Tea</p>"), ("application/xhtml+xml", "<p>Tea</p>"), ("text/csv", "text\nTea\n")] {
+        RequestBuilder::get("/").header("Accept", accept).execute(note())
+            .assert_status(http::StatusCode::OK)
+            .assert_header("Content-Type", accept)
+            .assert_body(body);
+    }
+}
+
+#[test]
+fn test_accepts() {
+    RequestBuilder::put("/")
+        .header("Content-Type", "text/plain")
+        .header("Content-Length", "5")
+        .body("Sugar")
+        .execute(note())
+        .assert_status(http::StatusCode::NO_CONTENT)
+        .assert_header("Accepted-By", "from_text");
+
+    RequestBuilder::put("/")
+        .header("Content-Type", "application/json")
+        .header("Content-Length", "2")
+        .body("{}")
+        .execute(note())
+        .assert_status(http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[test]
+fn test_methods() {
+    RequestBuilder::delete("/").execute(note())
+        .assert_status(http::StatusCode::METHOD_NOT_ALLOWED)
+        .assert_header("Allow", "GET, PUT");
+}
+
+#[test]
+fn test_languages_and_charsets() {
+    RequestBuilder::get("/").header("Accept-Language", "de").header("Accept-Charset", "utf-8").execute(note())
+        .assert_status(http::StatusCode::OK);
+
+    RequestBuilder::get("/").header("Accept-Language", "fr").execute(note())
+        .assert_status(http::StatusCode::NOT_ACCEPTABLE);
+
+    RequestBuilder::get("/").header("Accept-Charset", "iso-8859-1").execute(note())
+        .assert_status(http::StatusCode::NOT_ACCEPTABLE);
+}
+
+#[test]
+fn test_generic_resource() {
+    RequestBuilder::get("/").header("Accept-Charset", "iso-8859-1").execute(Value { value: 7 })
+        .assert_status(http::StatusCode::OK)
+        .assert_body("7");
+
+    RequestBuilder::get("/").execute(Value { value: "seven" })
+        .assert_status(http::StatusCode::OK)
+        .assert_body("seven");
+
+    RequestBuilder::get("/").header("Accept-Charset", "utf-8").execute(Value { value: 7.5 })
+        .assert_status(http::StatusCode::NOT_ACCEPTABLE);
+}
@@ -0,0 +1,325 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+extern crate hyper;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::stream;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::range;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::{RequestBuilder, TestResponse};
+
+const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug)]
+struct Export {
+    ranges: bool,
+    file: Option<PathBuf>,
+}
+
+impl Export {
+    fn new() -> Export {
+        Export { ranges: true, file: None }
+    }
+}
+
+impl Resource for Export {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::GET, http::Method::HEAD]
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn accept_ranges(&mut self, _context: &Context) -> bool {
+        self.ranges
+    }
+
+    fn generate_etag(&mut self, _context: &Context) -> Option<http::header::HeaderValue> {
+        Some(http::header::HeaderValue::from_static("\"v1\""))
+    }
+
+    fn last_modified(&mut self, _context: &Context) -> Option<SystemTime> {
+        Some(modified())
+    }
+}
+
+impl Export {
+    fn to_text(&mut self, context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        match context.uri().path() {
+            "/stream" => {
+                let chunks = stream::iter_ok(vec!["abcdefg", "hijklmn", "opqrst", "uvwxyz"]);
+                Entity::stream(chunks).length(26).into()
+            },
+            "/unsized" => Entity::stream(stream::iter_ok(vec![ALPHABET])).into(),
+            "/empty" => "".into(),
+            "/misreported" => Entity::from(ALPHABET).length(100).into(),
+            "/file" => Entity::open(self.file.as_ref().unwrap()).unwrap().into(),
+            _ => ALPHABET.into(),
+        }
+    }
+}
+
+fn modified() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_500_000_000)
+}
+
+fn get(path: &str, range: &str) -> TestResponse {
+    RequestBuilder::get(path).header("Range", range).execute(Export::new())
+}
+
+/// The boundary of a `multipart/byteranges` response.
+fn boundary(response: &TestResponse) -> String {
+    let content_type = response.header("Content-Type").unwrap();
+    assert!(content_type.starts_with("multipart/byteranges; boundary="), "{}", content_type);
+
+    content_type["multipart/byteranges; boundary=".len()..].to_string()
+}
+
+fn multipart(boundary: &str, parts: &[(&str, &str)]) -> String {
+    let mut body = String::new();
+
+    for (i, &(content_range, data)) in parts.iter().enumerate() {
+        if i > 0 {
+            body.push_str("\r\n");
+        }
+        body.push_str(&format!("--{}\r\nContent-Type: text/plain\r\nContent-Range: {}\r\n\r\n{}", boundary, content_range, data));
+    }
+
+    body + &format!("\r\n--{}--\r\n", boundary)
+}
+
+#[test]
+fn test_parse() {
+    let parse = |value| {
+        range::parse(&http::header::HeaderValue::from_static(value), 26)
+            .map(|ranges| ranges.into_iter().map(|range| (range.start, range.end)).collect::<Vec<_>>())
+    };
+
+    assert_eq!(parse("bytes=0-4"), Ok(vec![(0, 5)]));
+    assert_eq!(parse("bytes=20-"), Ok(vec![(20, 26)]));
+    assert_eq!(parse("bytes=-3"), Ok(vec![(23, 26)]));
+    assert_eq!(parse("bytes=-30"), Ok(vec![(0, 26)]));
+    assert_eq!(parse("bytes=10-99"), Ok(vec![(10, 26)]));
+    assert_eq!(parse("Bytes= 0-1 , 30-40, 5-5"), Ok(vec![(0, 2), (5, 6)]));
+    assert_eq!(parse("bytes=20-21,0-4,3-9,10-11"), Ok(vec![(20, 22), (0, 12)]));
+    assert_eq!(parse("bytes=5-6,0-1,2-4"), Ok(vec![(0, 7)]));
+    assert_eq!(parse("bytes=0-,-5,0-3"), Ok(vec![(0, 26)]));
+
+    assert_eq!(parse("bytes=26-"), Err(range::Error::Unsatisfiable));
+    assert_eq!(parse("bytes=-0"), Err(range::Error::Unsatisfiable));
+    assert_eq!(range::parse(&http::header::HeaderValue::from_static("bytes=-5"), 0), Err(range::Error::Unsatisfiable));
+    assert_eq!(range::parse(&http::header::HeaderValue::from_static("bytes=0-"), 0), Err(range::Error::Unsatisfiable));
+
+    for invalid in &["bytes=", "bytes=5-1", "bytes=a-b", "bytes=1", "items=0-1", "bytes=+1-2", "0-1"] {
+        assert_eq!(parse(invalid), Err(range::Error::Invalid), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_full_body_advertises_ranges() {
+    RequestBuilder::get("/")
+        .execute(Export::new())
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Accept-Ranges", "bytes")
+        .assert_header("Content-Length", "26")
+        .assert_body(ALPHABET);
+}
+
+#[test]
+fn test_single_range() {
+    get("/", "bytes=0-4")
+        .assert_status(http::StatusCode::PARTIAL_CONTENT)
+        .assert_header("Content-Range", "bytes 0-4/26")
+        .assert_header("Content-Length", "5")
+        .assert_header("ETag", "\"v1\"")
+        .assert_body("abcde");
+}
+
+#[test]
+fn test_suffix_and_open_ranges() {
+    get("/", "bytes=-3").assert_header("Content-Range", "bytes 23-25/26").assert_body("xyz");
+    get("/", "bytes=20-").assert_header("Content-Range", "bytes 20-25/26").assert_body("uvwxyz");
+    get("/", "bytes=24-100").assert_header("Content-Range", "bytes 24-25/26").assert_body("yz");
+}
+
+#[test]
+fn test_multiple_ranges() {
+    let response = get("/", "bytes=0-1, 24-");
+    let boundary = boundary(&response);
+    let body = multipart(&boundary, &[("bytes 0-1/26", "ab"), ("bytes 24-25/26", "yz")]);
+
+    response
+        .assert_status(http::StatusCode::PARTIAL_CONTENT)
+        .assert_header("Content-Length", &body.len().to_string())
+        .assert_body(&body);
+    assert_eq!(response.header("Content-Range"), None);
+}
+
+#[test]
+fn test_overlapping_ranges_are_coalesced() {
+    let header = vec!["0-"; range::MAX_RANGES].join(",");
+
+    get("/", &format!("bytes={}", header))
+        .assert_status(http::StatusCode::PARTIAL_CONTENT)
+        .assert_header("Content-Range", "bytes 0-25/26")
+        .assert_body(ALPHABET);
+}
+
+#[test]
+fn test_too_many_ranges_are_ignored() {
+    let header = (0..range::MAX_RANGES + 1).map(|i| format!("{}-{}", i * 2, i * 2)).collect::<Vec<_>>().join(",");
+
+    get("/", &format!("bytes={}", header))
+        .assert_status(http::StatusCode::OK)
+        .assert_body(ALPHABET);
+
+    get("/", &format!("bytes={}", vec!["0-"; 300].join(",")))
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Content-Length", "26");
+}
+
+#[test]
+fn test_unsatisfiable_range() {
+    get("/", "bytes=26-")
+        .assert_status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+        .assert_header("Content-Range", "bytes */26");
+}
+
+#[test]
+fn test_empty_representation_is_unsatisfiable() {
+    get("/empty", "bytes=-5")
+        .assert_status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+        .assert_header("Content-Range", "bytes */0");
+}
+
+#[test]
+fn test_length_of_bytes_is_kept() {
+    get("/misreported", "bytes=20-")
+        .assert_status(http::StatusCode::PARTIAL_CONTENT)
+        .assert_header("Content-Range", "bytes 20-25/26")
+        .assert_body("uvwxyz");
+
+    get("/misreported", "bytes=50-")
+        .assert_status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+        .assert_header("Content-Range", "bytes */26");
+}
+
+#[test]
+fn test_invalid_range_is_ignored() {
+    get("/", "bytes=5-1")
+        .assert_status(http::StatusCode::OK)
+        .assert_body(ALPHABET);
+}
+
+#[test]
+fn test_ranges_need_opt_in() {
+    let response = RequestBuilder::get("/")
+        .header("Range", "bytes=0-4")
+        .execute(Export { ranges: false, file: None });
+
+    response.assert_status(http::StatusCode::OK).assert_body(ALPHABET);
+    assert_eq!(response.header("Accept-Ranges"), None);
+}
+
+#[test]
+fn test_head_ignores_range() {
+    RequestBuilder::head("/")
+        .header("Range", "bytes=0-4")
+        .execute(Export::new())
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Accept-Ranges", "bytes")
+        .assert_header("Content-Length", "26");
+}
+
+#[test]
+fn test_if_range_etag() {
+    let request = |condition| {
+        RequestBuilder::get("/").header("Range", "bytes=0-4").header("If-Range", condition).execute(Export::new())
+    };
+
+    request("\"v1\"").assert_status(http::StatusCode::PARTIAL_CONTENT).assert_body("abcde");
+    request("\"v2\"").assert_status(http::StatusCode::OK).assert_body(ALPHABET);
+    request("W/\"v1\"").assert_status(http::StatusCode::OK).assert_body(ALPHABET);
+}
+
+#[test]
+fn test_if_range_date() {
+    let request = |date: SystemTime| {
+        RequestBuilder::get("/")
+            .header("Range", "bytes=0-4")
+            .header("If-Range", hyper::header::HttpDate::from(date).to_string())
+            .execute(Export::new())
+    };
+
+    request(modified()).assert_status(http::StatusCode::PARTIAL_CONTENT).assert_body("abcde");
+    request(modified() - Duration::from_secs(60)).assert_status(http::StatusCode::OK).assert_body(ALPHABET);
+}
+
+#[test]
+fn test_stream_ranges() {
+    get("/stream", "bytes=5-15")
+        .assert_status(http::StatusCode::PARTIAL_CONTENT)
+        .assert_header("Content-Range", "bytes 5-15/26")
+        .assert_body("fghijklmnop");
+
+    let response = get("/stream", "bytes=1-2,6-7,25-");
+    let boundary = boundary(&response);
+    response.assert_body(multipart(&boundary, &[("bytes 1-2/26", "bc"), ("bytes 6-7/26", "gh"), ("bytes 25-25/26", "z")]));
+}
+
+#[test]
+fn test_unordered_stream_ranges_are_ignored() {
+    get("/stream", "bytes=20-,0-1")
+        .assert_status(http::StatusCode::OK)
+        .assert_body(ALPHABET);
+}
+
+#[test]
+fn test_unknown_length_is_not_ranged() {
+    get("/unsized", "bytes=0-4")
+        .assert_status(http::StatusCode::OK)
+        .assert_body(ALPHABET);
+}
+
+#[test]
+fn test_file_ranges() {
+    let path = env::temp_dir().join(format!("gerust-range-{}", process::id()));
+    let content: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    File::create(&path).unwrap().write_all(&content).unwrap();
+
+    let request = |range| {
+        RequestBuilder::get("/file").header("Range", range).execute(Export { ranges: true, file: Some(path.clone()) })
+    };
+
+    request("bytes=100000-")
+        .assert_status(http::StatusCode::PARTIAL_CONTENT)
+        .assert_header("Content-Range", "bytes 100000-199999/200000")
+        .assert_header("Content-Length", "100000")
+        .assert_body(&content[100_000..]);
+
+    let response = request("bytes=150000-150009,10-19");
+    let boundary = boundary(&response);
+    let mut expected = format!("--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes 150000-150009/200000\r\n\r\n", boundary).into_bytes();
+    expected.extend_from_slice(&content[150_000..150_010]);
+    expected.extend_from_slice(format!("\r\n--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes 10-19/200000\r\n\r\n", boundary).as_bytes());
+    expected.extend_from_slice(&content[10..20]);
+    expected.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    response.assert_body(&expected);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,134 @@
+extern crate gerust;
+extern crate mime;
+extern crate http;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, AcceptedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::RequestBuilder;
+
+/// Serves a file in the type its extension names and the language its
+/// name ends in, with the lists built when the resource is created.
+struct File {
+    methods: Vec<http::Method>,
+    provided: Vec<ProvidedPair<File>>,
+    accepted: Vec<AcceptedPair<File>>,
+    languages: Vec<http::header::HeaderValue>,
+    charsets: Vec<http::header::HeaderValue>,
+}
+
+impl std::fmt::Debug for File {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("File").field("methods", &self.methods).finish()
+    }
+}
+
+impl File {
+    fn new(name: &str, writable: bool) -> File {
+        let mime = match name.rsplit('.').next() {
+            Some("html") => mime::TEXT_HTML,
+            Some("css") => mime::TEXT_CSS,
+            _ => mime::APPLICATION_OCTET_STREAM,
+        };
+
+        // `index.de.html` is in German, everything else in English.
+        let mut parts = name.rsplit('.').skip(1);
+        let language = match (parts.next(), parts.next()) {
+            (Some(language), Some(_)) => language,
+            _ => "en",
+        };
+
+        let mut methods = vec![http::Method::GET, http::Method::HEAD];
+        let mut accepted = Vec::new();
+        if writable {
+            methods.push(http::Method::PUT);
+            accepted.push(AcceptedPair(mime.clone(), File::write));
+        }
+
+        File {
+            methods,
+            provided: vec![ProvidedPair(mime, File::read)],
+            accepted,
+            languages: vec![http::header::HeaderValue::from_str(language).unwrap()],
+            charsets: vec![http::header::HeaderValue::from_static("utf-8")],
+        }
+    }
+
+    fn read(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "content".into()
+    }
+
+    fn write(&mut self, _context: &mut Context, _response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        ().into()
+    }
+}
+
+impl Resource for File {
+    fn allowed_methods(&self, _context: &Context) -> &[http::Method] {
+        &self.methods
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &[ProvidedPair<Self>] {
+        &self.provided
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &[AcceptedPair<Self>] {
+        &self.accepted
+    }
+
+    fn languages_provided(&self, _context: &Context) -> &[http::header::HeaderValue] {
+        &self.languages
+    }
+
+    fn charsets_provided(&self, _context: &Context) -> &[http::header::HeaderValue] {
+        &self.charsets
+    }
+}
+
+#[test]
+fn test_provided_type_per_instance() {
+    RequestBuilder::get("/style.css").execute(File::new("style.css", false))
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Content-Type", "text/css")
+        .assert_body("content");
+
+    RequestBuilder::get("/index.html").header("Accept", "text/html").execute(File::new("index.html", false))
+        .assert_status(http::StatusCode::OK)
+        .assert_header("Content-Type", "text/html");
+
+    RequestBuilder::get("/index.html").header("Accept", "text/css").execute(File::new("index.html", false))
+        .assert_status(http::StatusCode::NOT_ACCEPTABLE);
+}
+
+#[test]
+fn test_methods_and_accepted_types_per_instance() {
+    let put = |file: File, content_type: &str| {
+        RequestBuilder::put("/index.html")
+            .header("Content-Type", content_type)
+            .header("Content-Length", "3")
+            .body("<p>")
+            .execute(file)
+    };
+
+    put(File::new("index.html", false), "text/html")
+        .assert_status(http::StatusCode::METHOD_NOT_ALLOWED)
+        .assert_header("Allow", "GET, HEAD");
+    put(File::new("index.html", true), "text/html")
+        .assert_status(http::StatusCode::NO_CONTENT);
+    put(File::new("index.html", true), "text/css")
+        .assert_status(http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[test]
+fn test_languages_and_charsets_per_instance() {
+    RequestBuilder::get("/index.html").header("Accept-Language", "en").header("Accept-Charset", "utf-8")
+        .execute(File::new("index.html", false))
+        .assert_status(http::StatusCode::OK);
+
+    RequestBuilder::get("/index.html").header("Accept-Language", "de").execute(File::new("index.html", false))
+        .assert_status(http::StatusCode::NOT_ACCEPTABLE);
+
+    RequestBuilder::get("/index.de.html").header("Accept-Language", "de").execute(File::new("index.de.html", false))
+        .assert_status(http::StatusCode::OK);
+}
@@ -0,0 +1,156 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use futures::{stream, Future};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, AcceptedPair, Deferred};
+use gerust::response::Entity;
+use gerust::response::Error;
+use gerust::testing::RequestBuilder;
+
+#[derive(Default, Debug)]
+struct WritingResource;
+
+impl Resource for WritingResource {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::GET, http::Method::PUT]
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::TEXT_PLAIN, Self::from_text)]
+    }
+}
+
+impl WritingResource {
+    fn to_text(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        match context.uri().path() {
+            "/chunks" => {
+                for chunk in &["one ", "two ", "three"] {
+                    response.write(*chunk).unwrap();
+                }
+            },
+            "/headers" => {
+                response.header("X-Answer", "42").unwrap().status(http::StatusCode::ACCEPTED).unwrap();
+                response.write("with headers").unwrap();
+            },
+            "/committed" => {
+                response.write("written").unwrap();
+                assert_eq!(response.header("X-Late", "1").err(), Some(Error::Committed));
+                assert_eq!(response.status(http::StatusCode::ACCEPTED).err(), Some(Error::Committed));
+                assert!(response.headers().is_none());
+            },
+            "/finished" => {
+                response.write("done").unwrap();
+                response.finish().unwrap();
+                assert_eq!(response.write("more"), Err(Error::Finished));
+                let written = response.write_all(stream::iter_ok::<_, gerust::error::Error>(vec!["more"])).wait();
+                assert!(matches!(written, Err(gerust::error::Error::Response(Error::Finished))));
+            },
+            "/invalid" => {
+                assert_eq!(response.header("X-Bad", "line\nbreak").err(), Some(Error::InvalidHeader));
+                response.write("still fine").unwrap();
+            },
+            "/full" => {
+                let written = (0..100).take_while(|_| response.write("x").is_ok()).count();
+                assert!(written < 100);
+                assert_eq!(response.write("x"), Err(Error::Full));
+                return Entity::stream(stream::iter_ok(vec![format!(" {}", written)])).into();
+            },
+            "/stream" => {
+                response.write("head ").unwrap();
+                let chunks = stream::iter_ok((0..100).map(|i| format!("{} ", i)));
+                return Entity::stream(chunks).into();
+            },
+            _ => {},
+        }
+
+        ().into()
+    }
+
+    fn from_text(&mut self, _context: &mut Context, response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        response.header("Location", "/things/1").unwrap();
+        ().into()
+    }
+}
+
+#[test]
+fn test_every_chunk_is_delivered() {
+    RequestBuilder::get("/chunks")
+        .execute(WritingResource)
+        .assert_status(http::StatusCode::OK)
+        .assert_body("one two three");
+}
+
+#[test]
+fn test_handler_sets_status_and_headers() {
+    RequestBuilder::get("/headers")
+        .execute(WritingResource)
+        .assert_status(http::StatusCode::ACCEPTED)
+        .assert_header("X-Answer", "42")
+        .assert_body("with headers");
+}
+
+#[test]
+fn test_head_is_final_once_committed() {
+    RequestBuilder::get("/committed")
+        .execute(WritingResource)
+        .assert_status(http::StatusCode::OK)
+        .assert_body("written");
+}
+
+#[test]
+fn test_no_writes_after_finish() {
+    RequestBuilder::get("/finished").execute(WritingResource).assert_body("done");
+}
+
+#[test]
+fn test_invalid_header() {
+    RequestBuilder::get("/invalid").execute(WritingResource).assert_body("still fine");
+}
+
+#[test]
+fn test_write_all() {
+    let expected = (0..100).fold("head ".to_string(), |body, i| format!("{}{} ", body, i));
+
+    RequestBuilder::get("/stream").execute(WritingResource).assert_body(expected);
+}
+
+#[test]
+fn test_write_reports_full_buffer() {
+    let response = RequestBuilder::get("/full").execute(WritingResource);
+    let mut parts = response.text().split(' ');
+    let written = parts.next().unwrap();
+    let count: usize = parts.next().unwrap().parse().unwrap();
+
+    assert!(count > 0);
+    assert_eq!(written, "x".repeat(count));
+}
+
+#[test]
+fn test_empty_body_completes() {
+    RequestBuilder::get("/nothing")
+        .execute(WritingResource)
+        .assert_status(http::StatusCode::OK)
+        .assert_body("");
+}
+
+#[test]
+fn test_accept_handler_sets_location() {
+    RequestBuilder::put("/things/1")
+        .header("Content-Type", "text/plain")
+        .header("Content-Length", "0")
+        .execute(WritingResource)
+        .assert_status(http::StatusCode::CREATED)
+        .assert_header("Location", "/things/1")
+        .assert_decided_at("p11");
+}
@@ -0,0 +1,297 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use futures::sync::oneshot;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::server::{Error, ServerBuilder, ServerHandle};
+
+#[derive(Default, Debug)]
+struct HelloResource;
+
+impl Resource for HelloResource {
+    fn resource_exists(&mut self, context: &Context) -> Deferred<Self, bool> {
+        match context.uri().path() {
+            "/stuck" => Deferred::future(future::empty()),
+            "/slow" => {
+                let (sx, rx) = oneshot::channel();
+
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(200));
+                    let _ = sx.send(true);
+                });
+
+                Deferred::future(rx.map_err(|_| gerust::error::Error::DefaultError))
+            },
+            _ => true.into(),
+        }
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl HelloResource {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "Hello, World!".into()
+    }
+}
+
+fn localhost() -> SocketAddr {
+    ([127, 0, 0, 1], 0).into()
+}
+
+/// Runs the configured server on its own thread.
+fn start(builder: ServerBuilder) -> (Vec<SocketAddr>, ServerHandle, JoinHandle<Result<(), Error>>) {
+    let (sx, rx) = mpsc::channel();
+
+    let thread = thread::spawn(move || {
+        let server = builder.build::<HelloResource>().unwrap();
+        sx.send((server.local_addrs().unwrap(), server.handle())).unwrap();
+        server.run()
+    });
+
+    let (addrs, handle) = rx.recv().unwrap();
+    (addrs, handle, thread)
+}
+
+fn spawn(builder: ServerBuilder) -> Vec<SocketAddr> {
+    start(builder).0
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+}
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = connect(addr);
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_zero_threads_is_an_error() {
+    let result = ServerBuilder::new().bind(localhost()).threads(0).build::<HelloResource>();
+
+    match result {
+        Err(Error::Config(_)) => {},
+        _ => panic!("expected a configuration error"),
+    }
+}
+
+#[test]
+fn test_tiny_max_header_size_is_an_error() {
+    let result = ServerBuilder::new().bind(localhost()).max_header_size(100).build::<HelloResource>();
+
+    match result {
+        Err(Error::Config(_)) => {},
+        _ => panic!("expected a configuration error"),
+    }
+}
+
+#[test]
+fn test_bind_error_is_returned() {
+    let taken = std::net::TcpListener::bind(localhost()).unwrap();
+
+    let result = ServerBuilder::new().bind(taken.local_addr().unwrap()).build::<HelloResource>();
+
+    match result {
+        Err(Error::Io(_)) => {},
+        _ => panic!("expected an I/O error"),
+    }
+}
+
+#[test]
+fn test_serves_on_all_addresses() {
+    let addrs = spawn(ServerBuilder::new().bind(localhost()).bind(localhost()).threads(2).clone());
+
+    assert_eq!(addrs.len(), 2);
+
+    for addr in addrs {
+        let response = get(addr, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("Hello, World!"), "{}", response);
+    }
+}
+
+#[test]
+fn test_request_timeout() {
+    let addrs = spawn(ServerBuilder::new().bind(localhost()).request_timeout(Duration::from_millis(50)).clone());
+
+    let response = get(addrs[0], "/stuck");
+
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+}
+
+#[test]
+fn test_header_read_timeout() {
+    let addrs = spawn(ServerBuilder::new().bind(localhost()).header_read_timeout(Duration::from_millis(50)).clone());
+
+    let mut stream = connect(addrs[0]);
+    write!(stream, "GET / HTTP/1.1\r\nHost: loc").unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    assert!(response.is_empty());
+}
+
+#[test]
+fn test_header_read_timeout_spares_slow_responses() {
+    let addrs = spawn(ServerBuilder::new()
+        .bind(localhost())
+        .header_read_timeout(Duration::from_millis(50))
+        .request_timeout(Duration::from_millis(200))
+        .clone());
+
+    let response = get(addrs[0], "/stuck");
+
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+}
+
+#[test]
+fn test_without_keep_alive() {
+    let addrs = spawn(ServerBuilder::new().bind(localhost()).keep_alive(false).clone());
+
+    let mut stream = connect(addrs[0]);
+    write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+    // Without keep-alive the server closes the connection, ending the read.
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+}
+
+#[test]
+fn test_max_header_size() {
+    let addrs = spawn(ServerBuilder::new().bind(localhost()).max_header_size(8192).clone());
+
+    let mut stream = connect(addrs[0]);
+    let padding = "a".repeat(32 * 1024);
+    let _ = write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\nX-Padding: {}\r\n\r\n", padding);
+
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+
+    assert!(!response.starts_with(b"HTTP/1.1 200 OK"));
+}
+
+#[test]
+fn test_shutdown_stops_accepting() {
+    let (addrs, handle, thread) = start(ServerBuilder::new().bind(localhost()).clone());
+
+    assert!(get(addrs[0], "/").starts_with("HTTP/1.1 200 OK"));
+
+    handle.shutdown();
+    thread.join().unwrap().unwrap();
+
+    assert!(TcpStream::connect(addrs[0]).is_err());
+}
+
+#[test]
+fn test_shutdown_drains_in_flight_requests() {
+    let (addrs, handle, thread) = start(ServerBuilder::new().bind(localhost()).clone());
+
+    let addr = addrs[0];
+    let client = thread::spawn(move || get(addr, "/slow"));
+
+    thread::sleep(Duration::from_millis(50));
+    handle.shutdown();
+
+    let response = client.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    thread.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_shutdown_closes_idle_connections() {
+    let (addrs, handle, thread) = start(ServerBuilder::new().bind(localhost()).clone());
+
+    let mut idle = connect(addrs[0]);
+
+    let started = Instant::now();
+    handle.shutdown();
+    thread.join().unwrap().unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let mut rest = Vec::new();
+    let _ = idle.read_to_end(&mut rest);
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_shutdown_timeout() {
+    let (addrs, handle, thread) = start(ServerBuilder::new()
+        .bind(localhost())
+        .shutdown_timeout(Duration::from_millis(100))
+        .clone());
+
+    let addr = addrs[0];
+    let client = thread::spawn(move || get(addr, "/stuck"));
+
+    thread::sleep(Duration::from_millis(50));
+    handle.shutdown();
+    thread.join().unwrap().unwrap();
+
+    // The stuck request is cut off without a response.
+    assert_eq!(client.join().unwrap(), "");
+}
+
+#[test]
+fn test_run_until() {
+    let (sx, rx) = oneshot::channel::<()>();
+
+    let thread = thread::spawn(move || {
+        let server = ServerBuilder::new().bind(localhost()).build::<HelloResource>().unwrap();
+        server.run_until(rx.map_err(|_| ()))
+    });
+
+    sx.send(()).unwrap();
+    thread.join().unwrap().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let path = std::env::temp_dir().join(format!("gerust-test-{}.sock", std::process::id()));
+
+    let (addrs, handle, thread) = start(ServerBuilder::new().bind_unix(path.clone()).clone());
+    assert!(addrs.is_empty());
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    handle.shutdown();
+    thread.join().unwrap().unwrap();
+
+    assert!(!path.exists());
+}
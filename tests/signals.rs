@@ -0,0 +1,52 @@
+#![cfg(unix)]
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate tokio_signal;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use tokio_signal::unix::libc;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::server::ServerBuilder;
+
+#[derive(Default, Debug)]
+struct EmptyResource;
+
+impl Resource for EmptyResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl EmptyResource {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        ().into()
+    }
+}
+
+// Signals hit the whole process, so this lives in its own test binary.
+#[test]
+fn test_sigterm_shuts_down() {
+    let (sx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let server = ServerBuilder::new().bind(([127, 0, 0, 1], 0).into()).build::<EmptyResource>().unwrap();
+        sx.send(server.run()).unwrap();
+    });
+
+    // Give the server time to register its signal handlers.
+    thread::sleep(Duration::from_millis(200));
+
+    unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+
+    let result = rx.recv_timeout(Duration::from_secs(5)).expect("server did not stop on SIGTERM");
+    assert!(result.is_ok());
+}
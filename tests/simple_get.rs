@@ -1,3 +1,5 @@
+#![allow(clippy::wrong_self_convention)]
+
 extern crate gerust;
 extern crate mime;
 extern crate tokio_core;
@@ -6,9 +8,10 @@ extern crate hyper;
 extern crate futures;
 extern crate futures_cpupool;
 
-use futures::Sink;
 
-use gerust::resource::{Resource, ProvidedPair};
+use gerust::context::Context;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
 
 mod helper;
 
@@ -16,26 +19,26 @@ mod helper;
 struct GetResource;
 
 impl Resource for GetResource {
-    fn allowed_methods(&self) -> &'static [http::Method] {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
         use http::method::Method;
 
         &[Method::GET, Method::HEAD]
     }
 
-    fn content_types_provided(&self) -> &'static [ProvidedPair<Self>] {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
         &[ProvidedPair(mime::TEXT_HTML, Self::to_html)]
     }
 }
 
 impl GetResource {
-    fn to_html(&mut self, response: &mut gerust::flow::DelayedResponse) -> () {
-        response.response_body().start_send(Ok("Hello, World!".into()));
+    fn to_html(&mut self, _context: &Context, _response: &mut gerust::flow::DelayedResponse) -> Deferred<Self, Entity> {
+        "Hello, World!".into()
     }
 }
 
 #[test]
 fn test_without_accept_header() {
-    let resource = GetResource::default();
+    let resource = GetResource;
 
     let req = http::request::Builder::new()
         .method(http::method::Method::GET)
@@ -49,7 +52,7 @@ fn test_without_accept_header() {
 
 #[test]
 fn test_with_accept_header() {
-    let resource = GetResource::default();
+    let resource = GetResource;
 
     let req = http::request::Builder::new()
         .method(http::method::Method::GET)
@@ -64,7 +67,7 @@ fn test_with_accept_header() {
 
 #[test]
 fn test_head() {
-    let resource = GetResource::default();
+    let resource = GetResource;
 
     let req = http::request::Builder::new()
         .method(http::method::Method::HEAD)
@@ -79,7 +82,7 @@ fn test_head() {
 
 #[test]
 fn test_not_acceptable_post() {
-    let resource = GetResource::default();
+    let resource = GetResource;
 
     let req = http::request::Builder::new()
         .method(http::method::Method::POST)
@@ -0,0 +1,96 @@
+#![cfg(unix)]
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate libc;
+
+use std::env;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc;
+use std::thread;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::server::ServerBuilder;
+
+#[derive(Default, Debug)]
+struct EmptyResource;
+
+impl Resource for EmptyResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl EmptyResource {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        ().into()
+    }
+}
+
+fn request<S: Read + Write>(mut stream: S) -> String {
+    write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// Moves `fd` to `target`, like systemd does before starting us.
+fn pass_as(fd: i32, target: i32) {
+    if fd == target {
+        return;
+    }
+
+    unsafe {
+        assert_eq!(libc::fcntl(target, libc::F_GETFD), -1, "file descriptor {} is already in use", target);
+        assert_eq!(libc::dup2(fd, target), target);
+        libc::close(fd);
+    }
+}
+
+// Socket activation claims fixed file descriptors, so this lives in its
+// own test binary.
+#[test]
+fn test_listen_fds() {
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+
+    let path = env::temp_dir().join(format!("gerust-activation-{}.sock", std::process::id()));
+    let unix = UnixListener::bind(&path).unwrap();
+
+    pass_as(tcp.into_raw_fd(), 3);
+    pass_as(unix.into_raw_fd(), 4);
+
+    env::set_var("LISTEN_PID", std::process::id().to_string());
+    env::set_var("LISTEN_FDS", "2");
+
+    let (sx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let server = ServerBuilder::new().socket_activation(true).build::<EmptyResource>().unwrap();
+        sx.send((server.local_addrs().unwrap(), server.handle())).unwrap();
+        server.run().unwrap();
+    });
+
+    let (addrs, handle) = rx.recv().unwrap();
+
+    // Only the inherited sockets, not the default address.
+    assert_eq!(addrs, vec![tcp_addr]);
+    assert!(env::var("LISTEN_FDS").is_err());
+
+    let response = request(TcpStream::connect(tcp_addr).unwrap());
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    let response = request(UnixStream::connect(&path).unwrap());
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    handle.shutdown();
+    let _ = std::fs::remove_file(&path);
+}
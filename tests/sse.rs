@@ -0,0 +1,169 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Sink, stream};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::sse::{self, Event};
+use gerust::testing::{RequestBuilder, TestServer};
+
+static PRODUCER_STOPPED: AtomicBool = AtomicBool::new(false);
+static HEARTBEAT_STOPPED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default, Debug)]
+struct Ticker;
+
+impl Resource for Ticker {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_EVENT_STREAM, Self::to_events)]
+    }
+}
+
+impl Ticker {
+    fn to_events(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        let start = context.last_event_id().and_then(|id| id.parse().ok()).map_or(0, |id: u32| id + 1);
+        let mut events = sse::start(response).unwrap();
+
+        match context.uri().path() {
+            "/ticks" => {
+                thread::spawn(move || {
+                    for tick in start..start + 3 {
+                        events.send_event(Event::new(format!("tick {}", tick)).id(tick.to_string())).unwrap();
+                    }
+                });
+            },
+            "/sink" => {
+                let ticks = stream::iter_ok((0..2).map(|tick| Event::new(tick.to_string()).event("tick")));
+                return Deferred::future(events.send_all(ticks).map(|_| Entity::empty()).map_err(|_| gerust::error::Error::DefaultError));
+            },
+            "/forever" => {
+                thread::spawn(move || {
+                    while events.send_event(Event::new("tick")).is_ok() {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    PRODUCER_STOPPED.store(true, Ordering::SeqCst);
+                });
+            },
+            "/stopped-heartbeat" => {
+                thread::spawn(move || {
+                    let heartbeat = events.heartbeat(Duration::from_secs(60));
+                    events.send_event(Event::new("done")).unwrap();
+                    drop(heartbeat);
+                });
+            },
+            "/heartbeat" => {
+                thread::spawn(move || {
+                    let _heartbeat = events.heartbeat(Duration::from_millis(10));
+                    while !events.is_closed() {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    HEARTBEAT_STOPPED.store(true, Ordering::SeqCst);
+                });
+            },
+            _ => {},
+        }
+
+        ().into()
+    }
+}
+
+/// Opens a stream, reads until `expected` arrived twice and disconnects.
+fn read_until(server: &TestServer, path: &str, expected: &str) {
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+    while String::from_utf8_lossy(&received).matches(expected).count() < 2 {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0, "stream ended early");
+        received.extend_from_slice(&buffer[..read]);
+    }
+}
+
+fn wait_for(flag: &AtomicBool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    while !flag.load(Ordering::SeqCst) {
+        assert!(Instant::now() < deadline, "the producer didn't notice the disconnect");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_event_format() {
+    assert_eq!(Event::new("hello").to_string(), "data: hello\n\n");
+    assert_eq!(Event::new("one\ntwo\r\nthree").to_string(), "data: one\ndata: two\ndata: three\n\n");
+    assert_eq!(
+        Event::new("").event("update").id("7").retry(Duration::from_secs(3)).to_string(),
+        "event: update\nid: 7\nretry: 3000\ndata: \n\n"
+    );
+    assert_eq!(Event::new("x").event("bad\nname").id("1\r\n").to_string(), "event: badname\nid: 1\ndata: x\n\n");
+}
+
+#[test]
+fn test_event_stream() {
+    let server = TestServer::start::<Ticker>();
+    let response = server.client().get("/ticks").unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.header("content-type"), Some("text/event-stream"));
+    assert_eq!(response.header("cache-control"), Some("no-cache"));
+    assert!(response.is_chunked());
+    assert_eq!(response.text(), "id: 0\ndata: tick 0\n\nid: 1\ndata: tick 1\n\nid: 2\ndata: tick 2\n\n");
+}
+
+#[test]
+fn test_last_event_id() {
+    RequestBuilder::get("/ticks")
+        .header("Last-Event-ID", "41")
+        .execute(Ticker)
+        .assert_body("id: 42\ndata: tick 42\n\nid: 43\ndata: tick 43\n\nid: 44\ndata: tick 44\n\n");
+}
+
+#[test]
+fn test_sink() {
+    RequestBuilder::get("/sink")
+        .execute(Ticker)
+        .assert_header("Content-Type", "text/event-stream")
+        .assert_body("event: tick\ndata: 0\n\nevent: tick\ndata: 1\n\n");
+}
+
+#[test]
+fn test_producer_stops_on_disconnect() {
+    let server = TestServer::start::<Ticker>();
+
+    read_until(&server, "/forever", "data: tick\n\n");
+
+    wait_for(&PRODUCER_STOPPED);
+}
+
+#[test]
+fn test_stream_ends_once_heartbeat_is_dropped() {
+    RequestBuilder::get("/stopped-heartbeat")
+        .execute(Ticker)
+        .assert_body("data: done\n\n");
+}
+
+#[test]
+fn test_heartbeat() {
+    let server = TestServer::start::<Ticker>();
+
+    read_until(&server, "/heartbeat", ":\n\n");
+
+    wait_for(&HEARTBEAT_STOPPED);
+}
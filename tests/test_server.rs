@@ -0,0 +1,147 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use futures::{Future, Sink};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::server::ServerBuilder;
+use gerust::testing::TestServer;
+
+#[derive(Default, Debug)]
+struct EchoResource;
+
+impl Resource for EchoResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl EchoResource {
+    fn to_text(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        match context.uri().path() {
+            "/stream" => {
+                let sender = response.sender().unwrap();
+
+                thread::spawn(move || {
+                    let mut sender = sender;
+                    for part in &["one", "two", "three"] {
+                        sender = match sender.send(Ok((*part).into())).wait() {
+                            Ok(sender) => sender,
+                            Err(_) => return,
+                        };
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                });
+            },
+            "/echo" => {
+                let greeting = context.headers().get("x-greeting")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("none")
+                    .to_string();
+                let _ = response.write(greeting);
+            },
+            _ => {
+                let _ = response.write("Hello, World!");
+            },
+        }
+
+        ().into()
+    }
+}
+
+#[test]
+fn test_ephemeral_port() {
+    let server = TestServer::start::<EchoResource>();
+
+    assert_ne!(server.port(), 0);
+    assert_eq!(server.url("/hello"), format!("http://127.0.0.1:{}/hello", server.port()));
+
+    let response = server.client().get("/hello").unwrap();
+    assert_eq!(response.version(), http::Version::HTTP_11);
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.text(), "Hello, World!");
+}
+
+#[test]
+fn test_keep_alive() {
+    let server = TestServer::start::<EchoResource>();
+    let mut client = server.client();
+
+    for _ in 0..3 {
+        assert_eq!(client.get("/").unwrap().text(), "Hello, World!");
+    }
+
+    assert_eq!(client.connections(), 1);
+}
+
+#[test]
+fn test_keep_alive_disabled() {
+    let mut builder = ServerBuilder::new();
+    builder.threads(1).keep_alive(false);
+    let server = TestServer::with_builder::<EchoResource>(&builder);
+    let mut client = server.client();
+
+    for _ in 0..3 {
+        assert_eq!(client.get("/").unwrap().status(), http::StatusCode::OK);
+    }
+
+    assert_eq!(client.connections(), 3);
+}
+
+#[test]
+fn test_chunked_streaming() {
+    let server = TestServer::start::<EchoResource>();
+
+    let response = server.client().get("/stream").unwrap();
+
+    assert!(response.is_chunked());
+    assert_eq!(response.header("content-length"), None);
+    assert_eq!(response.chunks().to_vec(), vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+    assert_eq!(response.text(), "onetwothree");
+}
+
+#[test]
+fn test_header_parsing() {
+    let server = TestServer::start::<EchoResource>();
+    let mut client = server.client();
+
+    let response = client.send_raw(b"GET /echo HTTP/1.1\r\nhost: localhost\r\nX-GREETING:   hi there\r\n\r\n").unwrap();
+    assert_eq!(response.text(), "hi there");
+
+    let response = client.request("HEAD", "/echo", &[], &[]).unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert!(response.body().is_empty());
+
+    let response = client.request("GET", "/echo", &[("X-Greeting", "again")], &[]).unwrap();
+    assert_eq!(response.text(), "again");
+    assert_eq!(client.connections(), 1);
+}
+
+#[test]
+fn test_shutdown_on_drop() {
+    let server = TestServer::start::<EchoResource>();
+    let addr = server.addr();
+    assert!(server.client().get("/").is_ok());
+
+    drop(server);
+
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn test_explicit_shutdown() {
+    let server = TestServer::start::<EchoResource>();
+
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,84 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::{self, RequestBuilder};
+
+#[derive(Default, Debug)]
+struct PageResource;
+
+impl Resource for PageResource {
+    fn forbidden(&mut self, context: &Context) -> Deferred<Self, bool> {
+        (context.uri().path() == "/secret").into()
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_HTML, Self::to_html)]
+    }
+}
+
+impl PageResource {
+    fn to_html(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "<h1>Page</h1>".into()
+    }
+}
+
+#[test]
+fn test_collects_status_headers_and_body() {
+    let response = RequestBuilder::get("/page").header("Accept", "text/html").execute(PageResource);
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.text(), "<h1>Page</h1>");
+}
+
+#[test]
+fn test_trace_of_a_successful_get() {
+    RequestBuilder::get("/page")
+        .execute(PageResource)
+        .assert_status(http::StatusCode::OK)
+        .assert_trace(&["b13", "b12", "b11", "b10", "b9", "b8", "b7", "b6", "b5", "b4", "b3",
+                        "c3", "d4", "e5", "f6", "g7", "g8", "h10", "i12", "l13", "m16", "n16", "o16", "o18"]);
+}
+
+#[test]
+fn test_trace_of_a_halt() {
+    RequestBuilder::get("/secret")
+        .execute(PageResource)
+        .assert_status(http::StatusCode::FORBIDDEN)
+        .assert_body("Forbidden")
+        .assert_visited("b8")
+        .assert_not_visited("b6")
+        .assert_decided_at("b7");
+}
+
+#[test]
+fn test_method_not_allowed() {
+    RequestBuilder::delete("/page")
+        .execute(PageResource)
+        .assert_status(http::StatusCode::METHOD_NOT_ALLOWED)
+        .assert_decided_at("b10");
+}
+
+#[test]
+fn test_execute_a_built_request() {
+    let request = RequestBuilder::post("/page").body("name=page").build();
+    assert_eq!(request.method(), http::Method::POST);
+
+    testing::execute(PageResource, request)
+        .assert_status(http::StatusCode::METHOD_NOT_ALLOWED)
+        .assert_decided_at("b10");
+}
+
+#[test]
+#[should_panic(expected = "expected the flow to visit n11")]
+fn test_failed_assertion_shows_trace() {
+    RequestBuilder::get("/page").execute(PageResource).assert_visited("n11");
+}
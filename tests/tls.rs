@@ -0,0 +1,194 @@
+#![cfg(feature = "tls")]
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+extern crate rustls;
+extern crate webpki;
+extern crate h2;
+extern crate tokio_core;
+extern crate tokio_rustls;
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use futures::{Future, Stream};
+use tokio_core::reactor::Core;
+use tokio_rustls::TlsConnector;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::server::{Error, ServerBuilder};
+use gerust::tls::{self, TlsConfig};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+/// Answers with the base URI it sees.
+#[derive(Default, Debug)]
+struct EchoResource;
+
+impl Resource for EchoResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl EchoResource {
+    fn to_text(&mut self, context: &Context, response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        let base_uri = context.base_uri().map(|uri| uri.to_string()).unwrap_or_default();
+        let _ = response.write(base_uri);
+        ().into()
+    }
+}
+
+fn fixture(name: &str) -> String {
+    format!("{}/{}", FIXTURES, name)
+}
+
+fn tls_config() -> TlsConfig {
+    TlsConfig::new()
+        .certificate(fixture("localhost.crt"), fixture("localhost.key"))
+        .sni_certificate("other.test", fixture("other.test.crt"), fixture("other.test.key"))
+        .clone()
+}
+
+fn start() -> SocketAddr {
+    let (sx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let server = ServerBuilder::new()
+            .bind_tls(([127, 0, 0, 1], 0).into(), tls_config())
+            .build::<EchoResource>()
+            .unwrap();
+
+        sx.send(server.local_addrs().unwrap()[0]).unwrap();
+        server.run().unwrap();
+    });
+
+    rx.recv().unwrap()
+}
+
+fn client_config() -> rustls::ClientConfig {
+    let mut config = rustls::ClientConfig::new();
+    config.root_store.add_pem_file(&mut BufReader::new(File::open(fixture("ca.crt")).unwrap())).unwrap();
+    config
+}
+
+/// Requests `/` over TLS, verifying the server as `hostname`.
+fn get(addr: SocketAddr, hostname: &str) -> io::Result<String> {
+    let config = client_config();
+
+    let name = webpki::DNSNameRef::try_from_ascii_str(hostname).unwrap();
+    let session = rustls::ClientSession::new(&Arc::new(config), name);
+    let mut stream = rustls::StreamOwned::new(session, TcpStream::connect(addr)?);
+
+    write!(stream, "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", hostname)?;
+
+    let mut response = Vec::new();
+    let mut buf = [0; 4096];
+
+    // The server closes the connection without a close_notify alert.
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(ref e) if !response.is_empty() && e.kind() == io::ErrorKind::ConnectionAborted => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(String::from_utf8(response).unwrap())
+}
+
+#[test]
+fn test_https_base_uri() {
+    let addr = start();
+
+    let response = get(addr, "localhost").unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.contains("https://localhost/"), "{}", response);
+}
+
+#[test]
+fn test_sni_selects_certificate() {
+    let addr = start();
+
+    let response = get(addr, "other.test").unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+}
+
+#[test]
+fn test_unknown_host_gets_default_certificate() {
+    let addr = start();
+
+    // The default certificate is for `localhost`, so verification fails.
+    assert!(get(addr, "unknown.test").is_err());
+}
+
+#[test]
+fn test_missing_certificate_file() {
+    let tls = TlsConfig::new().certificate(fixture("missing.crt"), fixture("localhost.key")).clone();
+
+    let result = ServerBuilder::new().bind_tls(([127, 0, 0, 1], 0).into(), tls).build::<EchoResource>();
+
+    match result {
+        Err(Error::Tls(tls::Error::Io(..))) => {},
+        _ => panic!("expected an I/O error"),
+    }
+}
+
+#[test]
+fn test_sni_certificate_must_match_host() {
+    let tls = TlsConfig::new().sni_certificate("wrong.test", fixture("other.test.crt"), fixture("other.test.key")).clone();
+
+    let result = ServerBuilder::new().bind_tls(([127, 0, 0, 1], 0).into(), tls).build::<EchoResource>();
+
+    match result {
+        Err(Error::Tls(tls::Error::Certificate(..))) => {},
+        _ => panic!("expected a certificate error"),
+    }
+}
+
+#[test]
+fn test_alpn_negotiates_http2() {
+    let addr = start();
+
+    let mut config = client_config();
+    config.set_protocols(&[b"h2".to_vec()]);
+
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+
+    let socket = tokio_core::net::TcpStream::from_stream(TcpStream::connect(addr).unwrap(), &handle).unwrap();
+    let name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+
+    let response = TlsConnector::from(Arc::new(config)).connect(name, socket)
+        .map_err(h2::Error::from)
+        .and_then(h2::client::handshake)
+        .and_then(move |(mut client, connection)| {
+            handle.spawn(connection.map_err(|_| ()));
+
+            let request = http::Request::get("https://localhost/").body(()).unwrap();
+            let (response, _) = client.send_request(request, true).unwrap();
+            response
+        })
+        .and_then(|response| {
+            let (parts, body) = response.into_parts();
+            body.concat2().map(move |body| (parts, body))
+        });
+
+    let (parts, body) = core.run(response).unwrap();
+
+    assert_eq!(parts.version, http::Version::HTTP_2);
+    assert_eq!(parts.status, http::StatusCode::OK);
+    assert_eq!(&body[..], b"https://localhost/");
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "tower")]
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate hyper;
+extern crate futures;
+extern crate futures_cpupool;
+extern crate tower_service;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{Future, Poll, Stream};
+use futures_cpupool::CpuPool;
+use tower_service::Service;
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::tower::ResourceService;
+
+#[derive(Debug)]
+struct NamedResource {
+    name: &'static str,
+}
+
+impl Resource for NamedResource {
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl NamedResource {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        self.name.into()
+    }
+}
+
+/// Picks the resource by path.
+fn dispatch(request: &http::Request<hyper::Body>) -> NamedResource {
+    if request.uri().path().starts_with("/orders") {
+        NamedResource { name: "orders" }
+    } else {
+        NamedResource { name: "index" }
+    }
+}
+
+/// A middleware counting the requests passing through it.
+struct Counting<S> {
+    inner: S,
+    count: Arc<AtomicUsize>,
+}
+
+impl<S, Request> Service<Request> for Counting<S> where S: Service<Request> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: Request) -> S::Future {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.inner.call(request)
+    }
+}
+
+fn get(path: &str) -> http::Request<hyper::Body> {
+    http::Request::get(path).body(hyper::Body::empty()).unwrap()
+}
+
+fn body(response: http::Response<hyper::Body>) -> String {
+    String::from_utf8(response.into_body().concat2().wait().unwrap().to_vec()).unwrap()
+}
+
+#[test]
+fn test_dispatches_by_request() {
+    let mut service = ResourceService::new(dispatch);
+
+    let response = service.call(get("/orders/1")).wait().unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(body(response), "orders");
+
+    let response = service.call(get("/")).wait().unwrap();
+    assert_eq!(body(response), "index");
+}
+
+#[test]
+fn test_layered_with_middleware() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let mut service = Counting {
+        inner: ResourceService::with_pool(dispatch, Arc::new(CpuPool::new(1))),
+        count: count.clone(),
+    };
+
+    for _ in 0..3 {
+        service.poll_ready().unwrap();
+        assert_eq!(service.call(get("/")).wait().unwrap().status(), http::StatusCode::OK);
+    }
+
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_clones_share_the_factory() {
+    let service = ResourceService::new(dispatch);
+    let mut clone = service.clone();
+
+    let response = clone.call(get("/orders")).wait().unwrap();
+    assert_eq!(body(response), "orders");
+}
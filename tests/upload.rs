@@ -0,0 +1,149 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+
+use gerust::context::Context;
+use gerust::error::Error;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, AcceptedPair, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::{RequestBuilder, TestServer};
+
+#[derive(Default, Debug)]
+struct Upload;
+
+impl Resource for Upload {
+    fn allowed_methods(&self, _context: &Context) -> &'static [http::Method] {
+        &[http::Method::PUT]
+    }
+
+    fn valid_entity_length(&mut self, _context: &Context, len: u64) -> bool {
+        len <= 100
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+
+    fn content_types_accepted(&self, _context: &Context) -> &'static [AcceptedPair<Self>] {
+        &[AcceptedPair(mime::TEXT_PLAIN, Self::from_text)]
+    }
+}
+
+impl Upload {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "".into()
+    }
+
+    fn from_text(&mut self, context: &mut Context, _response: &mut DelayedResponse) -> Deferred<Self, ()> {
+        if context.uri().path() == "/ignored" {
+            return ().into();
+        }
+
+        let body = context.take_body().concat2()
+            .map_err(|_| Error::DefaultError)
+            .and_then(|body| if body.iter().all(|&byte| byte == b'x') { Ok(()) } else { Err(Error::DefaultError) });
+
+        if context.uri().path() == "/sync" {
+            return Deferred::future(futures::future::result(body.wait()));
+        }
+
+        Deferred::future(body)
+    }
+}
+
+fn put(path: &str, body: &str) -> RequestBuilder {
+    let mut builder = RequestBuilder::new(http::Method::PUT, path);
+    builder.header("Content-Type", "text/plain").body(body.to_string());
+    builder
+}
+
+/// Starts a chunked upload of `chunks` to `path`, without ending it.
+fn upload(server: &TestServer, path: &str, chunks: &[usize]) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "PUT {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n", path).unwrap();
+
+    for &size in chunks {
+        write!(stream, "{:x}\r\n{}\r\n", size, "x".repeat(size)).unwrap();
+    }
+
+    BufReader::new(stream)
+}
+
+fn read_status(reader: &mut BufReader<TcpStream>) -> String {
+    let mut status = String::new();
+    reader.read_line(&mut status).unwrap();
+    status.trim_end().to_string()
+}
+
+#[test]
+fn test_body_without_length_is_accepted() {
+    put("/upload", "xxxxx")
+        .execute(Upload)
+        .assert_status(http::StatusCode::NO_CONTENT)
+        .assert_visited("b3");
+}
+
+#[test]
+fn test_body_without_length_is_limited() {
+    put("/upload", &"x".repeat(101))
+        .execute(Upload)
+        .assert_status(http::StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[test]
+fn test_ignored_body() {
+    put("/ignored", &"x".repeat(500))
+        .execute(Upload)
+        .assert_status(http::StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn test_chunked_upload() {
+    let server = TestServer::start::<Upload>();
+    let mut reader = upload(&server, "/upload", &[40, 40, 20]);
+
+    reader.get_mut().write_all(b"0\r\n\r\n").unwrap();
+    assert_eq!(read_status(&mut reader), "HTTP/1.1 204 No Content");
+}
+
+#[test]
+fn test_chunked_upload_too_large() {
+    let server = TestServer::start::<Upload>();
+
+    // Rejected before the upload is complete.
+    let mut reader = upload(&server, "/upload", &[60, 60]);
+    assert_eq!(read_status(&mut reader), "HTTP/1.1 413 Payload Too Large");
+
+    // The rest of the body isn't read, so the connection is closed.
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(String::from_utf8_lossy(&rest).contains("Payload Too Large"));
+}
+
+#[test]
+fn test_chunked_upload_read_synchronously() {
+    let server = TestServer::start::<Upload>();
+    let mut reader = upload(&server, "/sync", &[40, 40]);
+
+    reader.get_mut().write_all(b"14\r\nxxxxxxxxxxxxxxxxxxxx\r\n0\r\n\r\n").unwrap();
+    assert_eq!(read_status(&mut reader), "HTTP/1.1 204 No Content");
+}
+
+#[test]
+fn test_chunked_upload_read_synchronously_too_large() {
+    let server = TestServer::start::<Upload>();
+
+    let mut reader = upload(&server, "/sync", &[60, 60]);
+    assert_eq!(read_status(&mut reader), "HTTP/1.1 413 Payload Too Large");
+}
@@ -0,0 +1,270 @@
+#![allow(clippy::wrong_self_convention)]
+
+extern crate gerust;
+extern crate mime;
+extern crate http;
+extern crate futures;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use futures::{Future, Sink, Stream};
+
+use gerust::context::Context;
+use gerust::flow::DelayedResponse;
+use gerust::resource::{Resource, ProvidedPair, Deferred};
+use gerust::response::Entity;
+use gerust::testing::{RequestBuilder, TestServer};
+use gerust::websocket::{self, Handler, Message, WebSocket};
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+#[derive(Default, Debug)]
+struct Echo;
+
+impl Resource for Echo {
+    fn is_authorized(&mut self, context: &Context) -> Deferred<Self, bool> {
+        (context.uri().path() != "/private").into()
+    }
+
+    fn accept_websocket(&mut self, context: &Context) -> Option<Handler> {
+        match context.uri().path() {
+            "/plain" => return None,
+            "/greet" => return Some(websocket::handler(|socket: WebSocket| {
+                socket.send(Message::Text("welcome".to_string()))
+                    .and_then(|socket| socket.send(Message::Close(Some((1000, "bye".to_string())))))
+                    // Waits for the client to answer the close.
+                    .and_then(|socket| socket.into_future().map_err(|(e, _)| e))
+                    .map(|_| ())
+                    .map_err(|_| ())
+            })),
+            _ => {},
+        }
+
+        Some(websocket::handler(|socket: WebSocket| {
+            let (sink, stream) = socket.split();
+
+            // Echoes data messages, upper-casing text.
+            let replies = stream.filter_map(|message| match message {
+                Message::Text(text) => Some(Message::Text(text.to_uppercase())),
+                Message::Binary(data) => Some(Message::Binary(data)),
+                _ => None,
+            });
+
+            replies.forward(sink).map(|_| ()).map_err(|_| ())
+        }))
+    }
+
+    fn content_types_provided(&self, _context: &Context) -> &'static [ProvidedPair<Self>] {
+        &[ProvidedPair(mime::TEXT_PLAIN, Self::to_text)]
+    }
+}
+
+impl Echo {
+    fn to_text(&mut self, _context: &Context, _response: &mut DelayedResponse) -> Deferred<Self, Entity> {
+        "plain HTTP".into()
+    }
+}
+
+fn handshake(path: &str) -> RequestBuilder {
+    let mut builder = RequestBuilder::get(path);
+    builder
+        .header("Connection", "keep-alive, Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", KEY);
+    builder
+}
+
+/// A masked client frame.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x80 | opcode];
+
+    if payload.len() < 126 {
+        frame.push(0x80 | payload.len() as u8);
+    } else {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    }
+
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    frame
+}
+
+/// Reads an unmasked server frame.
+fn read_frame<R: Read>(reader: &mut R) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).unwrap();
+    assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+
+    let length = match head[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length).unwrap();
+            u16::from_be_bytes(length) as usize
+        },
+        length => length as usize,
+    };
+
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0F, payload)
+}
+
+/// Connects and completes the handshake, returning the response head.
+fn connect(server: &TestServer, path: &str) -> (BufReader<TcpStream>, Vec<String>) {
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+                    Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n", path, KEY).unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut head = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        head.push(line.trim_end().to_lowercase());
+    }
+
+    (reader, head)
+}
+
+#[test]
+fn test_accept_key() {
+    assert_eq!(websocket::accept_key(KEY), ACCEPT);
+}
+
+#[test]
+fn test_handshake_is_accepted() {
+    handshake("/chat")
+        .execute(Echo)
+        .assert_status(http::StatusCode::SWITCHING_PROTOCOLS)
+        .assert_header("Upgrade", "websocket")
+        .assert_header("Connection", "Upgrade")
+        .assert_header("Sec-WebSocket-Accept", ACCEPT)
+        .assert_body("")
+        .assert_decided_at("b7b");
+}
+
+#[test]
+fn test_authorization_runs_first() {
+    handshake("/private")
+        .execute(Echo)
+        .assert_status(http::StatusCode::UNAUTHORIZED)
+        .assert_not_visited("b7b");
+}
+
+#[test]
+fn test_declined_upgrade_continues_the_flow() {
+    handshake("/plain")
+        .execute(Echo)
+        .assert_status(http::StatusCode::OK)
+        .assert_visited("b7b")
+        .assert_body("plain HTTP");
+}
+
+#[test]
+fn test_requests_without_upgrade_skip_the_handshake() {
+    RequestBuilder::get("/chat")
+        .execute(Echo)
+        .assert_status(http::StatusCode::OK)
+        .assert_not_visited("b7b");
+}
+
+#[test]
+fn test_unsupported_version() {
+    RequestBuilder::get("/chat")
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "8")
+        .header("Sec-WebSocket-Key", KEY)
+        .execute(Echo)
+        .assert_status(http::StatusCode::UPGRADE_REQUIRED)
+        .assert_header("Sec-WebSocket-Version", "13");
+}
+
+#[test]
+fn test_invalid_key() {
+    let response = RequestBuilder::get("/chat")
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", "short")
+        .execute(Echo);
+
+    response.assert_status(http::StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_messages() {
+    let server = TestServer::start::<Echo>();
+    let (mut reader, head) = connect(&server, "/chat");
+
+    assert_eq!(head[0], "http/1.1 101 switching protocols");
+    assert!(head.contains(&format!("sec-websocket-accept: {}", ACCEPT.to_lowercase())));
+    assert!(!head.iter().any(|line| line.starts_with("transfer-encoding") || line.starts_with("content-length")));
+
+    reader.get_mut().write_all(&frame(0x1, b"hello")).unwrap();
+    assert_eq!(read_frame(&mut reader), (0x1, b"HELLO".to_vec()));
+
+    // A fragmented binary message, with a ping in between.
+    let long = vec![7; 300];
+    let mut fragments = frame(0x2, &long[..200]);
+    fragments[0] &= 0x7F;
+    fragments.extend(frame(0x9, b"ping"));
+    fragments.extend(frame(0x0, &long[200..]));
+    reader.get_mut().write_all(&fragments).unwrap();
+
+    assert_eq!(read_frame(&mut reader), (0xA, b"ping".to_vec()));
+    assert_eq!(read_frame(&mut reader), (0x2, long));
+
+    // The closing handshake echoes the status code.
+    reader.get_mut().write_all(&frame(0x8, &[0x03, 0xE8])).unwrap();
+    assert_eq!(read_frame(&mut reader), (0x8, vec![0x03, 0xE8]));
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_unmasked_frames_close_the_connection() {
+    let server = TestServer::start::<Echo>();
+    let (mut reader, _) = connect(&server, "/chat");
+
+    reader.get_mut().write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn test_http_keeps_working_next_to_websockets() {
+    let server = TestServer::start::<Echo>();
+    let (_socket, _) = connect(&server, "/chat");
+
+    assert_eq!(server.client().get("/chat").unwrap().text(), "plain HTTP");
+}
+
+#[test]
+fn test_server_initiated_close() {
+    let server = TestServer::start::<Echo>();
+    let (mut reader, _) = connect(&server, "/greet");
+
+    assert_eq!(read_frame(&mut reader), (0x1, b"welcome".to_vec()));
+    assert_eq!(read_frame(&mut reader), (0x8, b"\x03\xE8bye".to_vec()));
+
+    reader.get_mut().write_all(&frame(0x8, &[0x03, 0xE8])).unwrap();
+
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}